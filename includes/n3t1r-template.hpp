@@ -1,6 +1,7 @@
 #pragma once
 
-#include <map>
+#include <functional>
+#include <memory>
 #include <stdexcept>
 #include <string>
 #include <vector>
@@ -12,7 +13,23 @@ namespace N3T1R {
 		[[ c_api ]]
 	}
 
+	struct SerialPortInfo {
+		std::string name;
+		std::string description;
+		std::string manufacturer;
+		std::string product;
+		std::string serial_number;
+		uint16_t vid;
+		uint16_t pid;
+		C_API::IRSerialPortKind port_type;
+		bool is_compatible;
+	};
+
+	class Bridge;
+
 	class IRCommunicationHandler {
+		friend class Bridge;
+
 		private:
 			static std::string as_string(char* c_str) {
 				std::string string = std::string(c_str);
@@ -32,35 +49,75 @@ namespace N3T1R {
 			}
 
 			C_API::IRCommunicationHandler* irch_ptr;
+			// Heap-allocated so that its address, given to the C API, stays valid
+			std::unique_ptr<std::function<void(C_API::IRConnectionState)>> state_callback;
+
+			static void state_callback_trampoline(C_API::IRConnectionState state, void* user_data) {
+				(*static_cast<std::function<void(C_API::IRConnectionState)>*>(user_data))(state);
+			}
+
+			// Hands the underlying handler over, leaving this object with a fresh one
+			C_API::IRCommunicationHandler* release() {
+				C_API::IRCommunicationHandler* released_ptr = this->irch_ptr;
+				// The callback lives in this object, it must not outlive it through the released handler
+				C_API::n3t1r_irch_set_state_callback(released_ptr, nullptr, nullptr);
+				this->state_callback.reset();
+				this->irch_ptr = C_API::n3t1r_irch_new();
+				return released_ptr;
+			}
+
+			explicit IRCommunicationHandler(C_API::IRCommunicationHandler* irch_ptr) : irch_ptr(irch_ptr) {}
+
+			static std::unique_ptr<IRCommunicationHandler> from_result(C_API::Result_String* result_ptr, C_API::IRCommunicationHandler* irch_ptr) {
+				throw_on_error(result_ptr);
+				return std::unique_ptr<IRCommunicationHandler>(new IRCommunicationHandler(irch_ptr));
+			}
 
 		public:
 			constexpr static const uintptr_t MAXIMUM_DATA_LENGTH = [[ maximum_data_len ]];
 
-			static std::map<std::string, std::string> get_available_serial_ports() {
-				C_API::Vec_String* names_vector_ptr = C_API::n3t1r_vec_string_new();
-				C_API::Vec_String* descriptions_vector_ptr = C_API::n3t1r_vec_string_new();
+			// Ports are sorted by name, generation (if not null) receives the generation of the enumeration
+			static std::vector<SerialPortInfo> get_available_serial_ports(uint64_t* generation = nullptr) {
+				C_API::SerialPortList* list_ptr = C_API::n3t1r_serial_port_list_new();
 				
-				throw_on_error(C_API::n3t1r_get_available_serial_ports(names_vector_ptr, descriptions_vector_ptr));
+				throw_on_error(C_API::n3t1r_get_available_serial_ports(list_ptr));
 				
-				size_t vector_len = C_API::n3t1r_vec_string_len(names_vector_ptr);
-				std::map<std::string, std::string> serial_ports;
+				size_t list_len = C_API::n3t1r_serial_port_list_len(list_ptr);
+				std::vector<SerialPortInfo> serial_ports(list_len);
 				
-				for (size_t i = 0; i < vector_len; ++i) {
-					char* name = C_API::n3t1r_vec_string_get(names_vector_ptr, i);
-					char* description = C_API::n3t1r_vec_string_get(descriptions_vector_ptr, i);
+				for (size_t i = 0; i < list_len; ++i) {
+					C_API::IRSerialPortInfo info;
+					C_API::n3t1r_serial_port_list_get_info(list_ptr, i, &info);
+
+					serial_ports[i].name = info.name;
+					serial_ports[i].description = info.description;
+					serial_ports[i].manufacturer = info.manufacturer;
+					serial_ports[i].product = info.product;
+					serial_ports[i].serial_number = info.serial_number;
+					serial_ports[i].vid = info.vid;
+					serial_ports[i].pid = info.pid;
+					serial_ports[i].port_type = info.port_type;
+					serial_ports[i].is_compatible = info.is_compatible;
 
-					serial_ports[name] = description;
+					C_API::n3t1r_serial_port_info_free(&info);
+				}
 
-					C_API::n3t1r_str_free(name);
-					C_API::n3t1r_str_free(description);
+				if (generation != nullptr) {
+					*generation = C_API::n3t1r_serial_port_list_generation(list_ptr);
 				}
 
-				C_API::n3t1r_vec_string_free(names_vector_ptr);
-				C_API::n3t1r_vec_string_free(descriptions_vector_ptr);
+				C_API::n3t1r_serial_port_list_free(list_ptr);
 
 				return serial_ports;
 			}
 
+			// Only changes when the available ports do, tells whether get_available_serial_ports needs calling again
+			static uint64_t get_serial_ports_generation() {
+				uint64_t generation = 0;
+				throw_on_error(C_API::n3t1r_get_serial_ports_generation(&generation));
+				return generation;
+			}
+
 			static std::vector<std::string> get_available_rooms() {
 				C_API::Vec_String* vector_ptr = C_API::n3t1r_vec_string_new();
 				
@@ -80,8 +137,61 @@ namespace N3T1R {
 				return rooms;
 			}
 
+			// Same as get_available_rooms, with the rooms of this machine looked for in rendezvous_folder
+			static std::vector<std::string> get_available_rooms_in(std::string rendezvous_folder) {
+				C_API::Vec_String* vector_ptr = C_API::n3t1r_vec_string_new();
+				
+				throw_on_error(C_API::n3t1r_get_available_rooms_in(rendezvous_folder.c_str(), vector_ptr));
+				
+				size_t vector_len = C_API::n3t1r_vec_string_len(vector_ptr);
+				std::vector<std::string> rooms(vector_len);
+				
+				for (size_t i = 0; i < vector_len; ++i) {
+					char* room_name = C_API::n3t1r_vec_string_get(vector_ptr, i);
+					rooms[i] = room_name;
+					C_API::n3t1r_str_free(room_name);
+				}
+
+				C_API::n3t1r_vec_string_free(vector_ptr);
+				
+				return rooms;
+			}
+
+			// Rooms of the relay server with a peer waiting in them
+			static std::vector<std::string> get_available_remote_rooms(std::string server_host, uint16_t server_port) {
+				C_API::Vec_String* vector_ptr = C_API::n3t1r_vec_string_new();
+
+				throw_on_error(C_API::n3t1r_get_available_remote_rooms(server_host.c_str(), server_port, vector_ptr));
+
+				size_t vector_len = C_API::n3t1r_vec_string_len(vector_ptr);
+				std::vector<std::string> rooms(vector_len);
+
+				for (size_t i = 0; i < vector_len; ++i) {
+					char* room_name = C_API::n3t1r_vec_string_get(vector_ptr, i);
+					rooms[i] = room_name;
+					C_API::n3t1r_str_free(room_name);
+				}
+
+				C_API::n3t1r_vec_string_free(vector_ptr);
+
+				return rooms;
+			}
+
 			IRCommunicationHandler() : irch_ptr(C_API::n3t1r_irch_new()) {}
 
+			// TOML configuration, overridden by the N3T1R_* environment variables, with its backend selected but not enabled
+			static std::unique_ptr<IRCommunicationHandler> from_config_file(std::string path) {
+				C_API::IRCommunicationHandler* irch_ptr = nullptr;
+				C_API::Result_String* result_ptr = C_API::n3t1r_irch_new_from_config_file(path.c_str(), &irch_ptr);
+				return from_result(result_ptr, irch_ptr);
+			}
+
+			static std::unique_ptr<IRCommunicationHandler> from_config_string(std::string config) {
+				C_API::IRCommunicationHandler* irch_ptr = nullptr;
+				C_API::Result_String* result_ptr = C_API::n3t1r_irch_new_from_config_string(config.c_str(), &irch_ptr);
+				return from_result(result_ptr, irch_ptr);
+			}
+
 			~IRCommunicationHandler() {
 				C_API::n3t1r_irch_free(this->irch_ptr);
 			}
@@ -89,10 +199,11 @@ namespace N3T1R {
 			void reset() {
 				C_API::n3t1r_irch_free(this->irch_ptr);
 				this->irch_ptr = C_API::n3t1r_irch_new();
+				this->state_callback.reset();
 			}
 
 			void select_serial_backend(const char *port_name) {
-				C_API::n3t1r_irch_select_serial_backend(this->irch_ptr, port_name);
+				throw_on_error(C_API::n3t1r_irch_select_serial_backend(this->irch_ptr, port_name));
 			}
 
 			void select_serial_backend(std::string port_name) {
@@ -100,19 +211,62 @@ namespace N3T1R {
 			}
 
 			void select_rendezvous_backend(const char *room_name) {
-				C_API::n3t1r_irch_select_rendezvous_backend(this->irch_ptr, room_name);
+				throw_on_error(C_API::n3t1r_irch_select_rendezvous_backend(this->irch_ptr, room_name));
 			}
 
 			void select_rendezvous_backend(std::string room_name) {
 				this->select_rendezvous_backend(room_name.c_str());
 			}
 
-			void select_network_backend(uint16_t source_port, const char *destination_host, uint16_t destination_port) {
-				C_API::n3t1r_irch_select_network_backend(this->irch_ptr, source_port, destination_host, destination_port);
+			// Meets peers of this machine in rendezvous_folder instead of the default one
+			void select_rendezvous_backend_in(std::string rendezvous_folder, std::string room_name) {
+				throw_on_error(C_API::n3t1r_irch_select_rendezvous_backend_in(this->irch_ptr, rendezvous_folder.c_str(), room_name.c_str()));
+			}
+
+			// Any number of peers join the room, and every frame is delivered to all the others
+			void select_broadcast_rendezvous_backend(std::string room_name) {
+				throw_on_error(C_API::n3t1r_irch_select_broadcast_rendezvous_backend_in(this->irch_ptr, nullptr, room_name.c_str()));
+			}
+
+			void select_broadcast_rendezvous_backend_in(std::string rendezvous_folder, std::string room_name) {
+				throw_on_error(C_API::n3t1r_irch_select_broadcast_rendezvous_backend_in(this->irch_ptr, rendezvous_folder.c_str(), room_name.c_str()));
+			}
+
+			void select_remote_rendezvous_backend(std::string server_host, uint16_t server_port, std::string room_name) {
+				throw_on_error(C_API::n3t1r_irch_select_remote_rendezvous_backend(this->irch_ptr, server_host.c_str(), server_port, room_name.c_str()));
+			}
+
+			void select_network_backend(uint16_t source_port, const char *destination_host, uint16_t destination_port, const C_API::IRNetworkOptions *options = nullptr) {
+				throw_on_error(C_API::n3t1r_irch_select_network_backend(this->irch_ptr, source_port, destination_host, destination_port, options));
+			}
+
+			void select_network_backend(uint16_t source_port, std::string destination_host, uint16_t destination_port, const C_API::IRNetworkOptions *options = nullptr) {
+				this->select_network_backend(source_port, destination_host.c_str(), destination_port, options);
+			}
+
+			// Secure mode, both peers must use the same passphrase
+			void select_network_backend(uint16_t source_port, std::string destination_host, uint16_t destination_port, std::string passphrase) {
+				C_API::IRNetworkOptions options = {};
+				options.passphrase = passphrase.c_str();
+				this->select_network_backend(source_port, destination_host.c_str(), destination_port, &options);
 			}
 
-			void select_network_backend(uint16_t source_port, std::string destination_host, uint16_t destination_port) {
-				this->select_network_backend(source_port, destination_host.c_str(), destination_port);
+			void set_transfer_pending_frames(bool transfer_pending_frames) {
+				C_API::n3t1r_irch_set_transfer_pending_frames(this->irch_ptr, transfer_pending_frames);
+			}
+
+			// Null restores the defaults, without timing emulation
+			void set_timing_options(const C_API::IRTimingOptions *options) {
+				C_API::n3t1r_irch_set_timing_options(this->irch_ptr, options);
+			}
+
+			// Switches to emulated time on the first call
+			void advance_clock(uint64_t elapsed_ns) {
+				C_API::n3t1r_irch_advance_clock(this->irch_ptr, elapsed_ns);
+			}
+
+			void use_wall_clock() {
+				C_API::n3t1r_irch_use_wall_clock(this->irch_ptr);
 			}
 
 			void enable() {
@@ -127,11 +281,113 @@ namespace N3T1R {
 				throw_on_error(C_API::n3t1r_irch_send(this->irch_ptr, data_ptr, data_len));
 			}
 
+			// Throws on a buffer too small for the next frame, which stays queued: MAXIMUM_DATA_LENGTH bytes always fit
 			size_t receive(uint8_t *data_ptr, size_t max_data_len) {
 				size_t data_sent_len = max_data_len;
 				this->throw_on_error(C_API::n3t1r_irch_receive(this->irch_ptr, data_ptr, &data_sent_len));
 				return data_sent_len;
 			}
 
+			// sender receives the member of the broadcast room that sent the frame, N3T1R_UNKNOWN_PEER on point-to-point links
+			size_t receive_from(uint8_t *data_ptr, size_t max_data_len, C_API::IRPeerId &sender) {
+				size_t data_sent_len = max_data_len;
+				this->throw_on_error(C_API::n3t1r_irch_receive_from(this->irch_ptr, data_ptr, &data_sent_len, &sender));
+				return data_sent_len;
+			}
+
+			// metadata is only filled when a frame is received
+			size_t receive_ex(uint8_t *data_ptr, size_t max_data_len, C_API::IRFrameMetadata &metadata) {
+				size_t data_sent_len = max_data_len;
+				this->throw_on_error(C_API::n3t1r_irch_receive_ex(this->irch_ptr, data_ptr, &data_sent_len, &metadata));
+				return data_sent_len;
+			}
+
+			// Length of the next frame, 0 when none was received. The frame stays queued for the next receive.
+			size_t peek_len() {
+				size_t len = 0;
+				this->throw_on_error(C_API::n3t1r_irch_peek_len(this->irch_ptr, &len));
+				return len;
+			}
+
+			std::vector<uint8_t> save_state() {
+				size_t state_len = 0;
+				uint8_t *state_ptr = C_API::n3t1r_irch_save_state(this->irch_ptr, &state_len);
+				std::vector<uint8_t> state(state_ptr, state_ptr + state_len);
+				C_API::n3t1r_state_free(state_ptr, state_len);
+				return state;
+			}
+
+			void load_state(const std::vector<uint8_t> &state) {
+				throw_on_error(C_API::n3t1r_irch_load_state(this->irch_ptr, state.data(), state.size()));
+			}
+
+			// True once after the peer loaded a savestate
+			bool take_resync_request() {
+				return C_API::n3t1r_irch_take_resync_request(this->irch_ptr);
+			}
+
+			C_API::IRConnectionState get_state() {
+				return C_API::n3t1r_irch_get_state(this->irch_ptr);
+			}
+
+			// Called from within enable/disable/send/receive, on the calling thread
+			void set_state_callback(std::function<void(C_API::IRConnectionState)> callback) {
+				if (callback) {
+					this->state_callback = std::make_unique<std::function<void(C_API::IRConnectionState)>>(std::move(callback));
+					C_API::n3t1r_irch_set_state_callback(this->irch_ptr, state_callback_trampoline, this->state_callback.get());
+				}
+				else {
+					C_API::n3t1r_irch_set_state_callback(this->irch_ptr, nullptr, nullptr);
+					this->state_callback.reset();
+				}
+			}
+
+			C_API::IRLinkStatistics get_stats() {
+				C_API::IRLinkStatistics stats;
+				C_API::n3t1r_irch_get_stats(this->irch_ptr, &stats);
+				return stats;
+			}
+
+			void reset_stats() {
+				C_API::n3t1r_irch_reset_stats(this->irch_ptr);
+			}
+
+	};
+
+	class Bridge {
+		private:
+			C_API::Bridge* bridge_ptr;
+
+		public:
+			Bridge(IRCommunicationHandler& left, IRCommunicationHandler& right) : bridge_ptr(C_API::n3t1r_bridge_new(left.release(), right.release())) {}
+
+			Bridge(const Bridge&) = delete;
+			Bridge& operator=(const Bridge&) = delete;
+
+			~Bridge() {
+				C_API::n3t1r_bridge_free(this->bridge_ptr);
+			}
+
+			void enable() {
+				IRCommunicationHandler::throw_on_error(C_API::n3t1r_bridge_enable(this->bridge_ptr));
+			}
+
+			void disable() {
+				C_API::n3t1r_bridge_disable(this->bridge_ptr);
+			}
+
+			bool step() {
+				bool forwarded = false;
+				IRCommunicationHandler::throw_on_error(C_API::n3t1r_bridge_step(this->bridge_ptr, &forwarded));
+				return forwarded;
+			}
+
+			void get_stats(C_API::IRBridgeStatistics& left_to_right, C_API::IRBridgeStatistics& right_to_left) {
+				C_API::n3t1r_bridge_get_stats(this->bridge_ptr, &left_to_right, &right_to_left);
+			}
+
+			void reset_stats() {
+				C_API::n3t1r_bridge_reset_stats(this->bridge_ptr);
+			}
 	};
 }
\ No newline at end of file
@@ -7,12 +7,151 @@
 
 #define N3T1R_MAXIMUM_DATA_LEN 255
 
+#define SECURE_OVERHEAD (FRAME_HEADER_LEN + TAG_LEN)
+
+#define RELIABLE_OVERHEAD DATA_HEADER_LEN
+
+#define N3T1R_IR_BYTE_TIME_NS 86806
+
+#define N3T1R_FRAME_FLAG_FRAMES_LOST (1 << 0)
+
+#define N3T1R_FRAME_FLAG_AFTER_RESYNC (1 << 1)
+
+#define N3T1R_DEFAULT_RELAY_PORT 7011
+
+typedef enum IRSerialPortKind {
+  IR_SERIAL_PORT_KIND_USB,
+  IR_SERIAL_PORT_KIND_PCI,
+  IR_SERIAL_PORT_KIND_BLUETOOTH,
+  IR_SERIAL_PORT_KIND_UNKNOWN,
+} IRSerialPortKind;
+
+typedef enum IRNetworkTransport {
+  IR_NETWORK_TRANSPORT_UDP,
+  IR_NETWORK_TRANSPORT_TCP,
+  IR_NETWORK_TRANSPORT_WEB_SOCKET,
+} IRNetworkTransport;
+
+typedef enum IRNetworkRole {
+  IR_NETWORK_ROLE_CLIENT,
+  IR_NETWORK_ROLE_SERVER,
+} IRNetworkRole;
+
+typedef enum IRConnectionState {
+  IR_CONNECTION_STATE_DISABLED,
+  IR_CONNECTION_STATE_CONNECTING,
+  IR_CONNECTION_STATE_WAITING_FOR_PEER,
+  IR_CONNECTION_STATE_CONNECTED,
+  IR_CONNECTION_STATE_RECONNECTING,
+  IR_CONNECTION_STATE_ERROR,
+} IRConnectionState;
+
+typedef struct Bridge Bridge;
+
 typedef struct IRCommunicationHandler IRCommunicationHandler;
 
 typedef struct Result_String Result_String;
 
+typedef struct SerialPortList SerialPortList;
+
 typedef struct Vec_String Vec_String;
 
+/**
+ * Strings are owned by the structure, release them with n3t1r_serial_port_info_free.
+ * Missing strings are empty, vid and pid are 0 for non-USB ports.
+ */
+typedef struct IRSerialPortInfo {
+  char *name;
+  char *description;
+  char *manufacturer;
+  char *product;
+  char *serial_number;
+  uint16_t vid;
+  uint16_t pid;
+  enum IRSerialPortKind port_type;
+  bool is_compatible;
+} IRSerialPortInfo;
+
+/**
+ * Options of the network backend, a null pointer selects the defaults. Both peers must use the same options.
+ * A non-null passphrase enables the secure mode: frames are encrypted and authenticated,
+ * and datagrams that fail authentication are dropped and counted in authentication_failures.
+ * Secure peers first start a session with a handshake: the state is WaitingForPeer until then, and frames sent meanwhile wait for it.
+ * The reliable mode retransmits lost frames for up to one second, and delivers frames once and in order.
+ * With TCP and WebSocket, clients connect to destination_host:destination_port and reconnect when the connection is lost,
+ * servers listen on source_port and wait for a client; frames can only be sent once connected.
+ * The broadcast mode joins a broadcast room through destination_host, typically a broadcast or multicast address
+ * that every member listens on: frames are delivered to all the other members. It only works over UDP,
+ * without the secure and reliable modes.
+ */
+typedef struct IRNetworkOptions {
+  const char *passphrase;
+  bool reliable;
+  enum IRNetworkTransport transport;
+  enum IRNetworkRole role;
+  bool broadcast;
+} IRNetworkOptions;
+
+/**
+ * Timing emulation applied to the frames of every backend, a null pointer restores the defaults (no emulation).
+ * Outgoing frames are held for send_delay_us plus a random part of send_jitter_us, without being reordered.
+ * Incoming frames are held for receive_delay_us, then released one after the other, taking byte_time_ns per byte
+ * (N3T1R_IR_BYTE_TIME_NS for the timing of the dongle), with at least turnaround_us between two frames.
+ */
+typedef struct IRTimingOptions {
+  uint64_t send_delay_us;
+  uint64_t send_jitter_us;
+  uint64_t receive_delay_us;
+  uint64_t byte_time_ns;
+  uint64_t turnaround_us;
+} IRTimingOptions;
+
+typedef uint32_t IRPeerId;
+
+/**
+ * Metadata of a received frame:
+ * - timestamp_us: when the handler got it from its backend, in microseconds since the handler was created, on the handler clock
+ *   (the emulated one after n3t1r_irch_advance_clock), before the playout delay of the timing emulation
+ * - source: member of the broadcast room that sent it, N3T1R_UNKNOWN_PEER on point-to-point links
+ * - sequence: counts the frames received through the handler, from 0
+ * - flags: N3T1R_FRAME_FLAG_FRAMES_LOST when frames were lost on reception since the previous one,
+ *   N3T1R_FRAME_FLAG_AFTER_RESYNC for the first frame since the peer loaded a savestate
+ */
+typedef struct IRFrameMetadata {
+  uint64_t timestamp_us;
+  IRPeerId source;
+  uint64_t sequence;
+  uint32_t flags;
+} IRFrameMetadata;
+
+/**
+ * Snapshot of the link statistics of an IRCommunicationHandler.
+ * Ages and round-trip times are UINT64_MAX until the corresponding event happened at least once.
+ */
+typedef struct IRLinkStatistics {
+  uint64_t frames_sent;
+  uint64_t bytes_sent;
+  uint64_t frames_received;
+  uint64_t bytes_received;
+  uint64_t frames_dropped;
+  uint64_t oversize_sends;
+  uint64_t framing_errors;
+  uint64_t last_send_age_ms;
+  uint64_t last_receive_age_ms;
+  uint64_t last_round_trip_us;
+  uint64_t smoothed_round_trip_us;
+  uint64_t authentication_failures;
+  uint64_t retransmissions;
+} IRLinkStatistics;
+
+typedef struct IRBridgeStatistics {
+  uint64_t frames;
+  uint64_t bytes;
+  uint64_t send_errors;
+} IRBridgeStatistics;
+
+#define N3T1R_UNKNOWN_PEER 0
+
 #ifdef __cplusplus
 extern "C" {
 #endif // __cplusplus
@@ -33,19 +172,120 @@ size_t n3t1r_vec_string_len(struct Vec_String *vector_ptr);
 
 char *n3t1r_vec_string_get(struct Vec_String *vector_ptr, size_t index);
 
-struct Result_String *n3t1r_get_available_serial_ports(struct Vec_String *names_vector_ptr, struct Vec_String *descriptions_vector_ptr);
+struct SerialPortList *n3t1r_serial_port_list_new(void);
+
+void n3t1r_serial_port_list_free(struct SerialPortList *list_ptr);
+
+size_t n3t1r_serial_port_list_len(struct SerialPortList *list_ptr);
+
+uint64_t n3t1r_serial_port_list_generation(struct SerialPortList *list_ptr);
 
+char *n3t1r_serial_port_list_get_name(struct SerialPortList *list_ptr, size_t index);
+
+char *n3t1r_serial_port_list_get_description(struct SerialPortList *list_ptr, size_t index);
+
+void n3t1r_serial_port_list_get_info(struct SerialPortList *list_ptr, size_t index, struct IRSerialPortInfo *info_ptr);
+
+void n3t1r_serial_port_info_free(struct IRSerialPortInfo *info_ptr);
+
+/**
+ * Replaces the content of the list, ports are sorted by name.
+ */
+struct Result_String *n3t1r_get_available_serial_ports(struct SerialPortList *list_ptr);
+
+/**
+ * Changes only when the available ports differ from the previous enumeration,
+ * compare it with n3t1r_serial_port_list_generation to know whether a list is outdated.
+ */
+struct Result_String *n3t1r_get_serial_ports_generation(uint64_t *generation_ptr);
+
+/**
+ * Rooms of this machine, and rooms with a peer waiting in them on the local network (found by UDP broadcast, this takes about 200 ms)
+ */
 struct Result_String *n3t1r_get_available_rooms(struct Vec_String *vector_ptr);
 
+/**
+ * Same as n3t1r_get_available_rooms, with the rooms of this machine looked for in rendezvous_folder (the default one if null)
+ */
+struct Result_String *n3t1r_get_available_rooms_in(const char *rendezvous_folder_ptr, struct Vec_String *vector_ptr);
+
+/**
+ * Rooms of the relay server with a peer waiting in them, a server_port of 0 selects the default relay port
+ */
+struct Result_String *n3t1r_get_available_remote_rooms(const char *server_host_ptr, uint16_t server_port, struct Vec_String *vector_ptr);
+
 struct IRCommunicationHandler *n3t1r_irch_new(void);
 
 void n3t1r_irch_free(struct IRCommunicationHandler *instance_ptr);
 
-void n3t1r_irch_select_serial_backend(struct IRCommunicationHandler *instance_ptr, const char *port_name_ptr);
+/**
+ * Creates an instance from a TOML configuration file, whose settings the N3T1R_* environment variables override
+ * (e.g. N3T1R_SERIAL_PORT for port in the [serial] section). The configured backend is selected, but not enabled.
+ * *instance_ptr_ptr is set to the new instance, or to null on failure.
+ */
+struct Result_String *n3t1r_irch_new_from_config_file(const char *path_ptr, struct IRCommunicationHandler **instance_ptr_ptr);
+
+/**
+ * Same as n3t1r_irch_new_from_config_file, with the TOML configuration itself. An empty string only takes the environment into account.
+ */
+struct Result_String *n3t1r_irch_new_from_config_string(const char *config_ptr, struct IRCommunicationHandler **instance_ptr_ptr);
+
+/**
+ * Selecting a backend while enabled disables the current backend and enables the new one.
+ * On failure the previous backend is restored and the error is returned.
+ * The n3t1r_irch_select_*_backend functions returned nothing before 0.2.0, see CHANGELOG.md.
+ */
+struct Result_String *n3t1r_irch_select_serial_backend(struct IRCommunicationHandler *instance_ptr, const char *port_name_ptr);
+
+/**
+ * Peers of this machine are met through the default rendez-vous folder, peers of the local network through UDP broadcast.
+ * The default folder is $XDG_RUNTIME_DIR/ntir11-rdv, or a folder of the user in the temporary directory without XDG_RUNTIME_DIR.
+ */
+struct Result_String *n3t1r_irch_select_rendezvous_backend(struct IRCommunicationHandler *instance_ptr, const char *room_name_ptr);
+
+/**
+ * Same as n3t1r_irch_select_rendezvous_backend, meeting peers of this machine in rendezvous_folder (the default one if null),
+ * e.g. for sandboxed emulators that do not share the temporary directory. The folder is created accessible to the user only.
+ */
+struct Result_String *n3t1r_irch_select_rendezvous_backend_in(struct IRCommunicationHandler *instance_ptr, const char *rendezvous_folder_ptr, const char *room_name_ptr);
+
+/**
+ * Joins a broadcast room of rendezvous_folder (the default one if null): any number of peers join it,
+ * and every frame is delivered to all the others. Members of this machine are met through the folder,
+ * members of the local network through UDP broadcast. Broadcast rooms do not meet point-to-point rooms of the same name.
+ */
+struct Result_String *n3t1r_irch_select_broadcast_rendezvous_backend_in(struct IRCommunicationHandler *instance_ptr, const char *rendezvous_folder_ptr, const char *room_name_ptr);
+
+/**
+ * Meets the peer through the relay server at server_host:server_port (n3t1r-relay), instead of the local temporary directory.
+ * A server_port of 0 selects the default relay port.
+ */
+struct Result_String *n3t1r_irch_select_remote_rendezvous_backend(struct IRCommunicationHandler *instance_ptr, const char *server_host_ptr, uint16_t server_port, const char *room_name_ptr);
+
+struct Result_String *n3t1r_irch_select_network_backend(struct IRCommunicationHandler *instance_ptr,
+                                                        uint16_t source_port,
+                                                        const char *destination_host_ptr,
+                                                        uint16_t destination_port,
+                                                        const struct IRNetworkOptions *options_ptr);
 
-void n3t1r_irch_select_rendezvous_backend(struct IRCommunicationHandler *instance_ptr, const char *room_name_ptr);
+/**
+ * Whether frames still queued by the previous backend (e.g. a rendez-vous without peer yet) are sent through the new one, true by default.
+ */
+void n3t1r_irch_set_transfer_pending_frames(struct IRCommunicationHandler *instance_ptr, bool transfer_pending_frames);
 
-void n3t1r_irch_select_network_backend(struct IRCommunicationHandler *instance_ptr, uint16_t source_port, const char *destination_host_ptr, uint16_t destination_port);
+void n3t1r_irch_set_timing_options(struct IRCommunicationHandler *instance_ptr, const struct IRTimingOptions *options_ptr);
+
+/**
+ * Reports the time the emulator emulated since the previous call. The first call switches the handler to emulated time:
+ * timing emulation and receive timeouts then follow the emulated clock instead of the wall clock,
+ * whether the emulator runs fast-forwarded, paused or frame by frame.
+ */
+void n3t1r_irch_advance_clock(struct IRCommunicationHandler *instance_ptr, uint64_t elapsed_ns);
+
+/**
+ * Switches the handler back to the wall clock, which it follows by default.
+ */
+void n3t1r_irch_use_wall_clock(struct IRCommunicationHandler *instance_ptr);
 
 struct Result_String *n3t1r_irch_enable(struct IRCommunicationHandler *instance_ptr);
 
@@ -53,10 +293,83 @@ void n3t1r_irch_disable(struct IRCommunicationHandler *instance_ptr);
 
 struct Result_String *n3t1r_irch_send(struct IRCommunicationHandler *instance_ptr, const uint8_t *data_ptr, size_t data_len);
 
+/**
+ * *data_len_ptr is the size of the buffer at data_ptr, and receives the length of the frame, 0 when none was received.
+ * A buffer too small for the next frame is an error, *data_len_ptr then receiving the length of the frame, which stays queued:
+ * buffers of N3T1R_MAXIMUM_DATA_LEN bytes always fit, see also n3t1r_irch_peek_len.
+ */
 struct Result_String *n3t1r_irch_receive(struct IRCommunicationHandler *instance_ptr, uint8_t *data_ptr, size_t *data_len_ptr);
 
+/**
+ * Same as n3t1r_irch_receive, also setting *sender_ptr to the sender of the frame when one is received:
+ * a member of the broadcast room, or N3T1R_UNKNOWN_PEER on point-to-point links. sender_ptr may be null.
+ */
+struct Result_String *n3t1r_irch_receive_from(struct IRCommunicationHandler *instance_ptr, uint8_t *data_ptr, size_t *data_len_ptr, IRPeerId *sender_ptr);
+
+/**
+ * Sets *len_ptr to the length of the next frame, 0 when none was received. The frame stays queued for the next receive.
+ */
+struct Result_String *n3t1r_irch_peek_len(struct IRCommunicationHandler *instance_ptr, size_t *len_ptr);
+
+/**
+ * Same as n3t1r_irch_receive, also filling *metadata_ptr when a frame is received (see IRFrameMetadata).
+ * metadata_ptr may be null.
+ */
+struct Result_String *n3t1r_irch_receive_ex(struct IRCommunicationHandler *instance_ptr, uint8_t *data_ptr, size_t *data_len_ptr, struct IRFrameMetadata *metadata_ptr);
+
+/**
+ * State of the handler to store along with an emulator savestate: frames the game sent that the peer did not get yet,
+ * and frames received that the game did not get yet. To be freed with n3t1r_state_free.
+ * Partial frames (bytes of a frame still being read from the serial port or a stream) and transport sessions
+ * (reliable and secure modes, relay pairing) are not saved: loading a state drops a frame that was only partly received.
+ */
+uint8_t *n3t1r_irch_save_state(struct IRCommunicationHandler *instance_ptr, size_t *state_len_ptr);
+
+void n3t1r_state_free(uint8_t *state_ptr, size_t state_len);
+
+/**
+ * Restores a state saved by n3t1r_irch_save_state, when the emulator loads the matching savestate.
+ * Frames queued since are discarded, and the peer is told to resync (see n3t1r_irch_take_resync_request).
+ */
+struct Result_String *n3t1r_irch_load_state(struct IRCommunicationHandler *instance_ptr, const uint8_t *state_ptr, size_t state_len);
+
+/**
+ * True once after the peer loaded a savestate: the frames it had in flight were discarded,
+ * the IR session is likely to need starting over.
+ */
+bool n3t1r_irch_take_resync_request(struct IRCommunicationHandler *instance_ptr);
+
+enum IRConnectionState n3t1r_irch_get_state(struct IRCommunicationHandler *instance_ptr);
+
+/**
+ * The callback is called on the thread calling into the handler, from within n3t1r_irch_* functions, on every state change.
+ * Passing a null callback removes the current one.
+ */
+void n3t1r_irch_set_state_callback(struct IRCommunicationHandler *instance_ptr, void (*callback)(enum IRConnectionState state, void *user_data), void *user_data);
+
+void n3t1r_irch_get_stats(struct IRCommunicationHandler *instance_ptr, struct IRLinkStatistics *stats_ptr);
+
+void n3t1r_irch_reset_stats(struct IRCommunicationHandler *instance_ptr);
+
+/**
+ * Takes ownership of both handlers, they must not be used nor freed afterwards.
+ */
+struct Bridge *n3t1r_bridge_new(struct IRCommunicationHandler *left_instance_ptr, struct IRCommunicationHandler *right_instance_ptr);
+
+void n3t1r_bridge_free(struct Bridge *bridge_ptr);
+
+struct Result_String *n3t1r_bridge_enable(struct Bridge *bridge_ptr);
+
+void n3t1r_bridge_disable(struct Bridge *bridge_ptr);
+
+struct Result_String *n3t1r_bridge_step(struct Bridge *bridge_ptr, bool *forwarded_ptr);
+
+void n3t1r_bridge_get_stats(struct Bridge *bridge_ptr, struct IRBridgeStatistics *left_to_right_ptr, struct IRBridgeStatistics *right_to_left_ptr);
+
+void n3t1r_bridge_reset_stats(struct Bridge *bridge_ptr);
+
 #ifdef __cplusplus
-} // extern "C"
-#endif // __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
 
-#endif /* N3T1R_H */
+#endif  /* N3T1R_H */
@@ -0,0 +1,234 @@
+// Relay protocol, spoken over TCP between the n3t1r-relay server and the remote rendez-vous backend.
+// Every message is prefixed by its length (16 bits, big endian), then starts with its kind (1 byte):
+//
+// JOIN room name      client -> server, the first two clients joining a room are paired
+// LIST                client -> server, asks for the rooms with a peer waiting in them
+// ROOMS names         server -> client, answer to LIST, room names separated by '\n'
+// PAIRED              server -> client, sent to both peers once paired
+// PEER_LEFT           server -> client, the peer left, the client is waiting in the room again
+// DATA frame          both ways, forwarded as is to the peer
+// ERROR message       server -> client, the server closes the connection after sending it
+// BIND token          client -> server, announces the token of the BIND datagrams of the client
+// REFLEXIVE address   server -> client, address of the client as seen by the server, from its BIND datagrams
+// PEER_ENDPOINT pair id | address
+//                     server -> client, once both peers have a reflexive address, to try reaching each other directly
+//
+// The server also listens on the same UDP port for BIND datagrams: magic "N3T1R" | token (8, LE).
+// Addresses are: family (4 or 6) | IP (4 or 16 bytes) | port (2, BE).
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+#[macro_use]
+pub mod log;
+
+pub mod server;
+
+pub const DEFAULT_RELAY_PORT: u16 = 7011;
+pub const MAXIMUM_MESSAGE_LEN: usize = u16::MAX as usize;
+pub(crate) const MAXIMUM_ROOM_NAME_LEN: usize = 64;
+const LENGTH_PREFIX_LEN: usize = 2;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const JOIN: u8 = 0;
+const LIST: u8 = 1;
+const ROOMS: u8 = 2;
+const PAIRED: u8 = 3;
+const PEER_LEFT: u8 = 4;
+const DATA: u8 = 5;
+const ERROR: u8 = 6;
+const BIND: u8 = 7;
+const REFLEXIVE: u8 = 8;
+const PEER_ENDPOINT: u8 = 9;
+
+const BIND_MAGIC: &[u8] = b"N3T1R";
+const BIND_DATAGRAM_LEN: usize = 5 + 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+	Join(String),
+	List,
+	Rooms(Vec<String>),
+	Paired,
+	PeerLeft,
+	Data(Vec<u8>),
+	Error(String),
+	Bind(u64),
+	Reflexive(SocketAddr),
+	PeerEndpoint(u64, SocketAddr),
+}
+
+fn encode_address(bytes: &mut Vec<u8>, address: &SocketAddr) {
+	match address.ip() {
+		IpAddr::V4(ip) => {
+			bytes.push(4);
+			bytes.extend_from_slice(&ip.octets());
+		},
+		IpAddr::V6(ip) => {
+			bytes.push(6);
+			bytes.extend_from_slice(&ip.octets());
+		},
+	}
+	bytes.extend_from_slice(&address.port().to_be_bytes());
+}
+
+fn decode_address(bytes: &[u8]) -> Result<SocketAddr, String> {
+	let ip: IpAddr = match (bytes.first(), bytes.len()) {
+		(Some(4), 7) => { Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[1..5]).unwrap()).into() },
+		(Some(6), 19) => { Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[1..17]).unwrap()).into() },
+		_ => {
+			return Err(String::from("Invalid address in relay message"));
+		},
+	};
+	return Ok(SocketAddr::new(ip, u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]])));
+}
+
+fn decode_u64(bytes: &[u8]) -> Result<u64, String> {
+	return Ok(u64::from_le_bytes(bytes.get(..8).ok_or(String::from("Relay message too short"))?.try_into().unwrap()));
+}
+
+impl Message {
+	// Kind and body, without the length prefix
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let (kind, body) = match self {
+			Self::Join(room_name) => { (JOIN, room_name.as_bytes().to_vec()) },
+			Self::List => { (LIST, Vec::new()) },
+			Self::Rooms(room_names) => { (ROOMS, room_names.join("\n").into_bytes()) },
+			Self::Paired => { (PAIRED, Vec::new()) },
+			Self::PeerLeft => { (PEER_LEFT, Vec::new()) },
+			Self::Data(data) => { (DATA, data.clone()) },
+			Self::Error(message) => { (ERROR, message.as_bytes().to_vec()) },
+			Self::Bind(token) => { (BIND, token.to_le_bytes().to_vec()) },
+			Self::Reflexive(address) => {
+				let mut body = Vec::new();
+				encode_address(&mut body, address);
+				(REFLEXIVE, body)
+			},
+			Self::PeerEndpoint(pair_id, address) => {
+				let mut body = pair_id.to_le_bytes().to_vec();
+				encode_address(&mut body, address);
+				(PEER_ENDPOINT, body)
+			},
+		};
+
+		let mut bytes = Vec::with_capacity(1 + body.len());
+		bytes.push(kind);
+		bytes.extend_from_slice(&body);
+		return bytes;
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+		let (kind, body) = bytes.split_first().ok_or(String::from("Empty relay message"))?;
+		let text = || String::from_utf8(body.to_vec()).map_err(|_| format!("Relay message {} is not valid UTF-8", kind));
+
+		return match *kind {
+			JOIN => { Ok(Self::Join(text()?)) },
+			LIST => { Ok(Self::List) },
+			ROOMS => {
+				let room_names = text()?;
+				Ok(Self::Rooms(room_names.split('\n').filter(|room_name| !room_name.is_empty()).map(|room_name| room_name.to_owned()).collect()))
+			},
+			PAIRED => { Ok(Self::Paired) },
+			PEER_LEFT => { Ok(Self::PeerLeft) },
+			DATA => { Ok(Self::Data(body.to_vec())) },
+			ERROR => { Ok(Self::Error(text()?)) },
+			BIND => { Ok(Self::Bind(decode_u64(body)?)) },
+			REFLEXIVE => { Ok(Self::Reflexive(decode_address(body)?)) },
+			PEER_ENDPOINT => { Ok(Self::PeerEndpoint(decode_u64(body)?, decode_address(body.get(8..).unwrap_or_default())?)) },
+			_ => { Err(format!("Unknown relay message kind {}", kind)) },
+		};
+	}
+}
+
+pub fn bind_datagram(token: u64) -> Vec<u8> {
+	let mut datagram = BIND_MAGIC.to_vec();
+	datagram.extend_from_slice(&token.to_le_bytes());
+	return datagram;
+}
+
+// Token of a BIND datagram
+pub fn parse_bind_datagram(datagram: &[u8]) -> Option<u64> {
+	if datagram.len() != BIND_DATAGRAM_LEN || !datagram.starts_with(BIND_MAGIC) {
+		return None;
+	}
+	return decode_u64(&datagram[BIND_MAGIC.len()..]).ok();
+}
+
+pub fn port_or_default(port: u16) -> u16 {
+	return if port == 0 { DEFAULT_RELAY_PORT } else { port };
+}
+
+pub fn is_valid_room_name(room_name: &str) -> bool {
+	return !room_name.is_empty() && room_name.len() <= MAXIMUM_ROOM_NAME_LEN && !room_name.contains(char::is_control);
+}
+
+// Appends the length-prefixed message to `output`
+pub fn write_message(output: &mut Vec<u8>, message: &Message) {
+	let bytes = message.to_bytes();
+	output.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+	output.extend_from_slice(&bytes);
+}
+
+// Removes the first complete message from `input`, None if it is not complete yet
+pub fn take_message(input: &mut Vec<u8>) -> Result<Option<Message>, String> {
+	if input.len() < LENGTH_PREFIX_LEN {
+		return Ok(None);
+	}
+
+	let len = u16::from_be_bytes([input[0], input[1]]) as usize;
+	if input.len() < LENGTH_PREFIX_LEN + len {
+		return Ok(None);
+	}
+
+	let message = Message::from_bytes(&input[LENGTH_PREFIX_LEN..LENGTH_PREFIX_LEN + len]);
+	input.drain(..LENGTH_PREFIX_LEN + len);
+	return message.map(Some);
+}
+
+// Asks the relay server for its rooms on a short-lived connection
+pub fn query_rooms(server_host: &str, server_port: u16) -> Result<Vec<String>, String> {
+	let address = (server_host, server_port).to_socket_addrs()
+		.map_err(|e| format!("Fail on resolve '{}': {}", server_host, e))?
+		.next()
+		.ok_or(format!("No address found for '{}'", server_host))?;
+	let mut stream = TcpStream::connect_timeout(&address, QUERY_TIMEOUT).map_err(|e| format!("Fail on connect to {}: {}", address, e))?;
+	stream.set_read_timeout(Some(QUERY_TIMEOUT)).map_err(|e| format!("Fail on set_read_timeout: {}", e))?;
+
+	let mut request = Vec::new();
+	write_message(&mut request, &Message::List);
+	stream.write_all(&request).map_err(|e| format!("Fail on write: {}", e))?;
+
+	let mut input = Vec::new();
+	let mut buffer = [0u8; 4096];
+	loop {
+		match take_message(&mut input)? {
+			Some(Message::Rooms(room_names)) => {
+				return Ok(room_names);
+			},
+			Some(Message::Error(error)) => {
+				return Err(format!("Relay server error: {}", error));
+			},
+			Some(message) => {
+				return Err(format!("Unexpected relay message {:?}", message));
+			},
+			None => {},
+		}
+
+		match stream.read(&mut buffer) {
+			Ok(0) => {
+				return Err(String::from("Relay server closed the connection"));
+			},
+			Ok(len) => {
+				input.extend_from_slice(&buffer[..len]);
+			},
+			Err(e) if e.kind() == ErrorKind::Interrupted => {},
+			Err(e) => {
+				return Err(format!("Fail on read: {}", e));
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Same levels as the n3t1r library, printed to stderr as well
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+	Quiet,
+	// Clients connecting, joining rooms and leaving
+	Info,
+	// Addresses learnt from BIND datagrams, and frames dropped for slow peers
+	Debug,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+impl LogLevel {
+	pub fn parse(name: &str) -> Option<Self> {
+		return match name.to_ascii_lowercase().as_str() {
+			"quiet" => { Some(Self::Quiet) },
+			"info" => { Some(Self::Info) },
+			"debug" => { Some(Self::Debug) },
+			_ => { None },
+		};
+	}
+}
+
+pub fn set_log_level(level: LogLevel) {
+	LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+	return match LOG_LEVEL.load(Ordering::Relaxed) {
+		0 => { LogLevel::Quiet },
+		1 => { LogLevel::Info },
+		_ => { LogLevel::Debug },
+	};
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+	return level <= log_level();
+}
+
+macro_rules! log_info {
+	($($arg:tt)*) => {
+		if $crate::log::is_enabled($crate::log::LogLevel::Info) {
+			eprintln!("n3t1r-relay: {}", format_args!($($arg)*));
+		}
+	};
+}
+
+macro_rules! log_debug {
+	($($arg:tt)*) => {
+		if $crate::log::is_enabled($crate::log::LogLevel::Debug) {
+			eprintln!("n3t1r-relay: {}", format_args!($($arg)*));
+		}
+	};
+}
@@ -0,0 +1,45 @@
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Arg, Command};
+
+use n3t1r_relay::log::{set_log_level, LogLevel};
+use n3t1r_relay::server::RelayServer;
+use n3t1r_relay::DEFAULT_RELAY_PORT;
+
+const IDLE_POLL_DELAY: Duration = Duration::from_millis(1);
+
+fn command() -> Command {
+	return Command::new("n3t1r-relay")
+		.about("NTiR-11 relay server: pairs the clients joining the same room, and forwards frames between them")
+		.arg(Arg::new("port")
+			.long("port")
+			.short('p')
+			.value_parser(clap::value_parser!(u16))
+			.help(format!("TCP and UDP port to listen on, {} by default", DEFAULT_RELAY_PORT)))
+		.arg(Arg::new("log-level").long("log-level").value_name("LEVEL").value_parser(["quiet", "info", "debug"]).default_value("info").help("Messages printed to stderr: clients coming and going with info, UDP addresses and dropped frames with debug"));
+}
+
+fn run(port: u16) -> Result<(), String> {
+	let mut server = RelayServer::bind(port)?;
+
+	loop {
+		if !server.poll()? {
+			thread::sleep(IDLE_POLL_DELAY);
+		}
+	}
+}
+
+fn main() -> ExitCode {
+	let matches = command().get_matches();
+	set_log_level(LogLevel::parse(matches.get_one::<String>("log-level").unwrap()).unwrap());
+
+	return match run(matches.get_one::<u16>("port").copied().unwrap_or(DEFAULT_RELAY_PORT)) {
+		Ok(()) => { ExitCode::SUCCESS },
+		Err(error) => {
+			eprintln!("Error: {}", error);
+			ExitCode::FAILURE
+		},
+	};
+}
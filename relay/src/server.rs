@@ -0,0 +1,338 @@
+// Relay server of the n3t1r-relay binary: pairs the clients joining the same room, and forwards frames between them.
+// Like the UDP based backends, forwarding is best-effort: frames for a peer that does not read fast enough are dropped.
+// It also tells clients their address as seen from the internet, and introduces paired peers to each other,
+// so that they can try reaching each other directly through their NATs (hole punching).
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+
+use super::{is_valid_room_name, parse_bind_datagram, take_message, write_message, Message, MAXIMUM_MESSAGE_LEN};
+
+const MAXIMUM_ROOM_MEMBERS: usize = 2;
+// Output buffered for a client past which forwarded frames are dropped
+const MAXIMUM_OUTPUT_LEN: usize = 64 * 1024;
+
+struct Client {
+	stream: TcpStream,
+	address: SocketAddr,
+	input: Vec<u8>,
+	output: Vec<u8>,
+	room_name: Option<String>,
+	udp_token: Option<u64>,
+	reflexive_address: Option<SocketAddr>,
+	// Shared with the peer, so that their direct datagrams can be told apart from anything else
+	pair_id: Option<u64>,
+	// Closed once the output is flushed
+	closing: bool,
+	closed: bool,
+}
+
+impl Client {
+	// Reads whatever is available without blocking, returns whether anything was read
+	fn read_available(&mut self) -> bool {
+		let mut buffer = [0u8; 4096];
+		let mut has_read = false;
+
+		while !self.closed {
+			match self.stream.read(&mut buffer) {
+				Ok(0) => {
+					self.closed = true;
+				},
+				Ok(len) => {
+					self.input.extend_from_slice(&buffer[..len]);
+					has_read = true;
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					break;
+				},
+				Err(e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => {
+					self.closed = true;
+				},
+			}
+		}
+		return has_read;
+	}
+
+	fn flush(&mut self) {
+		while !self.closed && !self.output.is_empty() {
+			match self.stream.write(&self.output) {
+				Ok(0) => {
+					self.closed = true;
+				},
+				Ok(len) => {
+					self.output.drain(..len);
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					return;
+				},
+				Err(e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(_) => {
+					self.closed = true;
+				},
+			}
+		}
+		if self.closing && self.output.is_empty() {
+			self.closed = true;
+		}
+	}
+}
+
+pub struct RelayServer {
+	listener: TcpListener,
+	udp_socket: UdpSocket,
+	clients: HashMap<u64, Client>,
+	rooms: BTreeMap<String, Vec<u64>>,
+	next_client_id: u64,
+}
+
+impl RelayServer {
+	pub fn bind(port: u16) -> Result<Self, String> {
+		let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|e| format!("Fail on bind tcp:{}: {}", port, e))?;
+		listener.set_nonblocking(true).map_err(|e| format!("Fail on set_nonblocking: {}", e))?;
+
+		// Same port number as the listener, which may have been picked by the system
+		let port = listener.local_addr().map_err(|e| format!("Fail on local_addr: {}", e))?.port();
+		let udp_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|e| format!("Fail on bind udp:{}: {}", port, e))?;
+		udp_socket.set_nonblocking(true).map_err(|e| format!("Fail on set_nonblocking: {}", e))?;
+		log_info!("Listening on tcp:{0} and udp:{0}", port);
+
+		return Ok(Self { listener, udp_socket, clients: HashMap::new(), rooms: BTreeMap::new(), next_client_id: 0 });
+	}
+
+	pub fn local_port(&self) -> Result<u16, String> {
+		return Ok(self.listener.local_addr().map_err(|e| format!("Fail on local_addr: {}", e))?.port());
+	}
+
+	// Rooms with a peer waiting in them
+	pub fn available_rooms(&self) -> Vec<String> {
+		return self.rooms.iter().filter(|(_, members)| members.len() < MAXIMUM_ROOM_MEMBERS).map(|(room_name, _)| room_name.clone()).collect();
+	}
+
+	// Does whatever can be done without blocking, returns whether anything happened
+	pub fn poll(&mut self) -> Result<bool, String> {
+		let mut is_active = self.accept_clients()?;
+		is_active |= self.receive_bind_datagrams();
+
+		let mut client_ids: Vec<u64> = self.clients.keys().copied().collect();
+		client_ids.sort();
+
+		for &client_id in &client_ids {
+			let client = self.clients.get_mut(&client_id).unwrap();
+			is_active |= client.read_available();
+
+			loop {
+				let client = self.clients.get_mut(&client_id).unwrap();
+				if client.closing {
+					break;
+				}
+
+				match take_message(&mut client.input) {
+					Ok(Some(message)) => {
+						self.handle_message(client_id, message);
+					},
+					Ok(None) => {
+						break;
+					},
+					Err(e) => {
+						self.close_with_error(client_id, &e);
+					},
+				}
+			}
+		}
+
+		for client_id in client_ids {
+			let client = self.clients.get_mut(&client_id).unwrap();
+			client.flush();
+
+			if client.closed {
+				self.remove_client(client_id);
+				is_active = true;
+			}
+		}
+		return Ok(is_active);
+	}
+
+	fn accept_clients(&mut self) -> Result<bool, String> {
+		let mut has_accepted = false;
+
+		loop {
+			match self.listener.accept() {
+				Ok((stream, address)) => {
+					// A client that can not be set up is simply dropped
+					if stream.set_nonblocking(true).is_ok() && stream.set_nodelay(true).is_ok() {
+						log_info!("Client {} connected", address);
+
+						self.clients.insert(self.next_client_id, Client {
+							stream,
+							address,
+							input: Vec::new(),
+							output: Vec::new(),
+							room_name: None,
+							udp_token: None,
+							reflexive_address: None,
+							pair_id: None,
+							closing: false,
+							closed: false,
+						});
+						self.next_client_id += 1;
+						has_accepted = true;
+					}
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					return Ok(has_accepted);
+				},
+				Err(e) if e.kind() == ErrorKind::Interrupted || e.kind() == ErrorKind::ConnectionAborted => {},
+				Err(e) => {
+					return Err(format!("Fail on accept: {}", e));
+				},
+			}
+		}
+	}
+
+	fn receive_bind_datagrams(&mut self) -> bool {
+		let mut buffer = [0u8; 64];
+		let mut has_received = false;
+
+		// Errors, like ICMP unreachable on Windows, are ignored as for any other stray datagram
+		while let Ok((len, source)) = self.udp_socket.recv_from(&mut buffer) {
+			has_received = true;
+
+			if let Some(token) = parse_bind_datagram(&buffer[..len]) {
+				let client_id = self.clients.iter().find(|(_, client)| client.udp_token == Some(token)).map(|(&client_id, _)| client_id);
+
+				if let Some(client_id) = client_id {
+					let client = self.clients.get_mut(&client_id).unwrap();
+					if client.reflexive_address != Some(source) {
+						log_debug!("Client {} seen from udp:{}", client.address, source);
+						client.reflexive_address = Some(source);
+						self.send_to(client_id, &Message::Reflexive(source));
+						self.introduce_peers(client_id);
+					}
+				}
+			}
+		}
+		return has_received;
+	}
+
+	// Sends each peer the reflexive address of the other, once both are known
+	fn introduce_peers(&mut self, client_id: u64) {
+		if let Some(peer_id) = self.peer_of(client_id) {
+			let (client, peer) = (&self.clients[&client_id], &self.clients[&peer_id]);
+
+			if let (Some(pair_id), Some(client_address), Some(peer_address)) = (client.pair_id, client.reflexive_address, peer.reflexive_address) {
+				self.send_to(client_id, &Message::PeerEndpoint(pair_id, peer_address));
+				self.send_to(peer_id, &Message::PeerEndpoint(pair_id, client_address));
+			}
+		}
+	}
+
+	fn handle_message(&mut self, client_id: u64, message: Message) {
+		match message {
+			Message::Join(room_name) => {
+				self.join_room(client_id, room_name);
+			},
+			Message::List => {
+				let mut room_names = Vec::new();
+				let mut len = 1;
+				for room_name in self.available_rooms() {
+					len += room_name.len() + 1;
+					if len > MAXIMUM_MESSAGE_LEN {
+						break;
+					}
+					room_names.push(room_name);
+				}
+				self.send_to(client_id, &Message::Rooms(room_names));
+			},
+			Message::Bind(token) => {
+				let client = self.clients.get_mut(&client_id).unwrap();
+				client.udp_token = Some(token);
+				client.reflexive_address = None;
+			},
+			Message::Data(data) => {
+				if let Some(peer_id) = self.peer_of(client_id) {
+					let peer = self.clients.get_mut(&peer_id).unwrap();
+					if peer.output.len() < MAXIMUM_OUTPUT_LEN {
+						write_message(&mut peer.output, &Message::Data(data));
+					}
+					else {
+						log_debug!("Frame for client {} dropped, it does not read fast enough", peer.address);
+					}
+				}
+			},
+			message => {
+				self.close_with_error(client_id, &format!("Unexpected message {:?}", message));
+			},
+		}
+	}
+
+	fn join_room(&mut self, client_id: u64, room_name: String) {
+		if self.clients[&client_id].room_name.is_some() {
+			self.close_with_error(client_id, "Already in a room");
+			return;
+		}
+		if !is_valid_room_name(&room_name) {
+			self.close_with_error(client_id, &format!("Invalid room name '{}'", room_name));
+			return;
+		}
+
+		let members = self.rooms.entry(room_name.clone()).or_default();
+		if members.len() >= MAXIMUM_ROOM_MEMBERS {
+			self.close_with_error(client_id, &format!("Room '{}' is full", room_name));
+			return;
+		}
+		members.push(client_id);
+		let members = members.clone();
+
+		let client = self.clients.get_mut(&client_id).unwrap();
+		log_info!("Client {} joined room '{}'", client.address, room_name);
+		client.room_name = Some(room_name);
+
+		if members.len() == MAXIMUM_ROOM_MEMBERS {
+			// Not a secret, only a way to tell the datagrams of the peer apart from stray ones
+			let mut pair_id = [0u8; 8];
+			let _ = getrandom::fill(&mut pair_id);
+
+			for &member_id in &members {
+				self.clients.get_mut(&member_id).unwrap().pair_id = Some(u64::from_le_bytes(pair_id));
+				self.send_to(member_id, &Message::Paired);
+			}
+			self.introduce_peers(client_id);
+		}
+	}
+
+	fn peer_of(&self, client_id: u64) -> Option<u64> {
+		let room_name = self.clients[&client_id].room_name.as_ref()?;
+		return self.rooms[room_name].iter().copied().find(|&member_id| member_id != client_id);
+	}
+
+	fn send_to(&mut self, client_id: u64, message: &Message) {
+		write_message(&mut self.clients.get_mut(&client_id).unwrap().output, message);
+	}
+
+	fn close_with_error(&mut self, client_id: u64, error: &str) {
+		log_info!("Closing client {}: {}", self.clients[&client_id].address, error);
+
+		self.send_to(client_id, &Message::Error(error.to_owned()));
+		self.clients.get_mut(&client_id).unwrap().closing = true;
+	}
+
+	fn remove_client(&mut self, client_id: u64) {
+		let peer_id = self.peer_of(client_id);
+		let client = self.clients.remove(&client_id).unwrap();
+		log_info!("Client {} disconnected", client.address);
+
+		if let Some(room_name) = client.room_name {
+			let members = self.rooms.get_mut(&room_name).unwrap();
+			members.retain(|&member_id| member_id != client_id);
+			if members.is_empty() {
+				self.rooms.remove(&room_name);
+			}
+		}
+		if let Some(peer_id) = peer_id {
+			self.clients.get_mut(&peer_id).unwrap().pair_id = None;
+			self.send_to(peer_id, &Message::PeerLeft);
+		}
+	}
+}
@@ -0,0 +1,216 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::server::RelayServer;
+use super::{bind_datagram, parse_bind_datagram, query_rooms, take_message, write_message, Message};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Relay server polled on a background thread until dropped
+struct TestServer {
+	port: u16,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+	fn start() -> Self {
+		let mut server = RelayServer::bind(0).unwrap();
+		let port = server.local_port().unwrap();
+		let stop = Arc::new(AtomicBool::new(false));
+
+		let thread_stop = stop.clone();
+		let thread = thread::spawn(move || {
+			while !thread_stop.load(Ordering::Relaxed) {
+				if !server.poll().unwrap() {
+					thread::sleep(Duration::from_millis(1));
+				}
+			}
+		});
+		return Self { port, stop, thread: Some(thread) };
+	}
+}
+
+impl Drop for TestServer {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			thread.join().unwrap();
+		}
+	}
+}
+
+struct TestClient {
+	stream: TcpStream,
+	input: Vec<u8>,
+}
+
+impl TestClient {
+	fn connect(server: &TestServer) -> Self {
+		let stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+		stream.set_read_timeout(Some(TEST_TIMEOUT)).unwrap();
+		return Self { stream, input: Vec::new() };
+	}
+
+	fn send(&mut self, message: &Message) {
+		let mut output = Vec::new();
+		write_message(&mut output, message);
+		self.stream.write_all(&output).unwrap();
+	}
+
+	// None once the server closed the connection
+	fn expect(&mut self) -> Option<Message> {
+		let mut buffer = [0u8; 1024];
+		loop {
+			if let Some(message) = take_message(&mut self.input).unwrap() {
+				return Some(message);
+			}
+
+			let len = self.stream.read(&mut buffer).unwrap();
+			if len == 0 {
+				return None;
+			}
+			self.input.extend_from_slice(&buffer[..len]);
+		}
+	}
+}
+
+#[test]
+fn messages_round_trip() {
+	let messages = [
+		Message::Join(String::from("room")),
+		Message::List,
+		Message::Rooms(vec![String::from("a"), String::from("b c")]),
+		Message::Rooms(Vec::new()),
+		Message::Paired,
+		Message::PeerLeft,
+		Message::Data(vec![0x00, 0xFF, 0x0A]),
+		Message::Data(Vec::new()),
+		Message::Error(String::from("nope")),
+		Message::Bind(0x0123456789ABCDEF),
+		Message::Reflexive(SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7), 40000))),
+		Message::PeerEndpoint(42, SocketAddr::from((Ipv6Addr::LOCALHOST, 1))),
+	];
+
+	let mut stream = Vec::new();
+	for message in &messages {
+		assert_eq!(&Message::from_bytes(&message.to_bytes()).unwrap(), message);
+		write_message(&mut stream, message);
+	}
+
+	// Fed one byte at a time, as it may come out of a TCP stream
+	let mut input = Vec::new();
+	let mut decoded = Vec::new();
+	for byte in stream {
+		input.push(byte);
+		if let Some(message) = take_message(&mut input).unwrap() {
+			decoded.push(message);
+		}
+	}
+	assert_eq!(decoded, messages);
+	assert!(input.is_empty());
+
+	assert!(Message::from_bytes(&[]).is_err());
+	assert!(Message::from_bytes(&[0xFF]).is_err());
+
+	assert_eq!(parse_bind_datagram(&bind_datagram(7)), Some(7));
+	assert_eq!(parse_bind_datagram(&bind_datagram(7)[1..]), None);
+}
+
+#[test]
+fn clients_of_a_room_are_paired_and_exchange_frames() {
+	let server = TestServer::start();
+	let mut left = TestClient::connect(&server);
+	let mut right = TestClient::connect(&server);
+
+	left.send(&Message::Join(String::from("lobby")));
+	// Makes sure the server handled the join before the next client asks
+	left.send(&Message::List);
+	assert_eq!(left.expect(), Some(Message::Rooms(vec![String::from("lobby")])));
+	assert_eq!(query_rooms("127.0.0.1", server.port).unwrap(), vec![String::from("lobby")]);
+
+	right.send(&Message::Join(String::from("lobby")));
+	assert_eq!(left.expect(), Some(Message::Paired));
+	assert_eq!(right.expect(), Some(Message::Paired));
+	// Full rooms are not listed
+	assert_eq!(query_rooms("127.0.0.1", server.port).unwrap(), Vec::<String>::new());
+
+	left.send(&Message::Data(vec![0xDE, 0xAD]));
+	assert_eq!(right.expect(), Some(Message::Data(vec![0xDE, 0xAD])));
+	right.send(&Message::Data(vec![0xBE, 0xEF]));
+	assert_eq!(left.expect(), Some(Message::Data(vec![0xBE, 0xEF])));
+
+	drop(right);
+	assert_eq!(left.expect(), Some(Message::PeerLeft));
+	assert_eq!(query_rooms("127.0.0.1", server.port).unwrap(), vec![String::from("lobby")]);
+}
+
+#[test]
+fn third_client_is_turned_away() {
+	let server = TestServer::start();
+	let mut clients: Vec<TestClient> = (0..3).map(|_| TestClient::connect(&server)).collect();
+
+	for client in &mut clients[..2] {
+		client.send(&Message::Join(String::from("full")));
+	}
+	for client in &mut clients[..2] {
+		assert_eq!(client.expect(), Some(Message::Paired));
+	}
+
+	clients[2].send(&Message::Join(String::from("full")));
+	assert_eq!(clients[2].expect(), Some(Message::Error(String::from("Room 'full' is full"))));
+	assert_eq!(clients[2].expect(), None);
+}
+
+#[test]
+fn invalid_messages_close_the_connection() {
+	let server = TestServer::start();
+
+	let mut client = TestClient::connect(&server);
+	client.send(&Message::Join(String::new()));
+	assert!(matches!(client.expect(), Some(Message::Error(_))));
+	assert_eq!(client.expect(), None);
+
+	let mut client = TestClient::connect(&server);
+	client.send(&Message::Paired);
+	assert!(matches!(client.expect(), Some(Message::Error(_))));
+	assert_eq!(client.expect(), None);
+}
+
+#[test]
+fn bound_peers_are_introduced_to_each_other() {
+	let server = TestServer::start();
+	let mut clients: Vec<TestClient> = (0..2).map(|_| TestClient::connect(&server)).collect();
+	let sockets: Vec<UdpSocket> = (0..2).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+
+	for client in &mut clients {
+		client.send(&Message::Join(String::from("punch")));
+	}
+	for client in &mut clients {
+		assert_eq!(client.expect(), Some(Message::Paired));
+	}
+
+	for (token, (client, socket)) in clients.iter_mut().zip(&sockets).enumerate() {
+		client.send(&Message::Bind(token as u64));
+		// Makes sure the server knows the token before the datagram comes
+		client.send(&Message::List);
+		assert_eq!(client.expect(), Some(Message::Rooms(Vec::new())));
+
+		socket.send_to(&bind_datagram(token as u64), ("127.0.0.1", server.port)).unwrap();
+		assert_eq!(client.expect(), Some(Message::Reflexive(socket.local_addr().unwrap())));
+	}
+
+	let endpoints: Vec<Option<Message>> = clients.iter_mut().map(|client| client.expect()).collect();
+	if let [Some(Message::PeerEndpoint(left_pair_id, left_peer)), Some(Message::PeerEndpoint(right_pair_id, right_peer))] = endpoints.as_slice() {
+		assert_eq!(left_pair_id, right_pair_id);
+		assert_eq!(*left_peer, sockets[1].local_addr().unwrap());
+		assert_eq!(*right_peer, sockets[0].local_addr().unwrap());
+	}
+	else {
+		panic!("unexpected {:?}", endpoints);
+	}
+}
@@ -1,15 +1,78 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use n3t1r_relay as relay;
+
+#[macro_use]
+pub mod log;
 
 mod io;
 use self::io::IOBackend;
+use self::io::broadcast;
 use self::io::serial::SerialBackend;
+pub use self::io::serial::{SerialOptions, SerialPortInfo, SerialPortKind, SerialPortList};
+pub use self::io::network::{NetworkOptions, NetworkRole, NetworkTransport};
+
+pub mod statistics;
+use self::statistics::{LinkStatistics, StatisticsRecorder};
+
+mod clock;
+use self::clock::Clock;
+
+mod rendezvous;
+
+mod discovery;
+use self::discovery::DiscoveryEndpoint;
+
+
+pub mod bridge;
+
+mod savestate;
+use self::savestate::SavedState;
+
+mod config;
+use self::config::BackendKind;
+pub use self::config::Config;
+
+mod timing;
+use self::timing::TimingLayer;
+
+mod frame;
+pub use self::frame::{ReceivedFrame, FRAME_FLAG_AFTER_RESYNC, FRAME_FLAG_FRAMES_LOST};
+pub use self::timing::{TimingOptions, IR_BYTE_TIME_NS};
 
 // To-Do: find a more "realistic" value
 pub const MAXIMUM_DATA_LEN: usize = 255;
 pub const SERIAL_RX_TIMEOUT: Duration = Duration::from_millis(10);
 
+// Identifies the sender of a frame in broadcast rooms
+pub type PeerId = u32;
+// Sender of the frames of point-to-point links, which have a single peer
+pub const UNKNOWN_PEER: PeerId = 0;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+	Disabled,
+	Connecting,
+	WaitingForPeer,
+	Connected,
+	Reconnecting,
+	Error,
+}
+
+impl ConnectionState {
+	pub fn is_enabled(&self) -> bool {
+		return matches!(self, Self::WaitingForPeer | Self::Connected | Self::Reconnecting);
+	}
+}
+
+// Called on the thread driving the handler, from within enable/disable/send/receive
+pub type StateCallback = Box<dyn FnMut(ConnectionState)>;
+
 pub mod traits {
+	use super::{ConnectionState, PeerId, UNKNOWN_PEER};
+
 	pub trait IOHandler {
 		fn enable(&mut self) -> Result<(), String> {
 			unimplemented!();
@@ -26,63 +89,489 @@ pub mod traits {
 		fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
 			unimplemented!();
 		}
+
+		// Same as receive, into `buffer` rather than a new Vec: returns the length of the frame.
+		// A frame longer than the buffer is an error, and is lost.
+		fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+			return match self.receive()? {
+				Some(data) => { Ok(Some(copy_frame(&data, buffer)?)) },
+				None => { Ok(None) },
+			};
+		}
+
+		// Same as receive, along with the sender of the frame
+		fn receive_from(&mut self) -> Result<Option<(PeerId, Vec<u8>)>, String> {
+			return Ok(self.receive()?.map(|data| (UNKNOWN_PEER, data)));
+		}
+
+		fn state(&self) -> ConnectionState {
+			unimplemented!();
+		}
+	}
+
+	// Whether a frame of `len` bytes fits in a receive_into buffer
+	pub fn check_buffer_len(len: usize, buffer: &[u8]) -> Result<(), String> {
+		if len > buffer.len() {
+			return Err(format!("Buffer too small ({} bytes) for a frame of {} bytes", buffer.len(), len));
+		}
+		return Ok(());
+	}
+
+	// Copies a received frame into a receive_into buffer
+	pub fn copy_frame(data: &[u8], buffer: &mut [u8]) -> Result<usize, String> {
+		check_buffer_len(data.len(), buffer)?;
+		buffer[..data.len()].copy_from_slice(data);
+		return Ok(data.len());
 	}
 }
 
-use self::traits::IOHandler;
+use self::traits::{check_buffer_len, IOHandler};
 
 pub struct IRCommunicationHandler {
-	backend: IOBackend,
+	backend: TimingLayer<IOBackend>,
+	statistics: StatisticsRecorder,
+	clock: Clock,
+	state: ConnectionState,
+	// Set when enabling failed, until the next enable() or disable()
+	failed: bool,
+	state_callback: Option<StateCallback>,
+	// Whether frames queued by the previous backend are sent again through the new one when switching
+	transfer_pending_frames: bool,
+	// Set when the peer loaded a savestate, until taken
+	resync_requested: bool,
+	// Frame timestamps are reported relative to it
+	created: Instant,
+	next_sequence: u64,
+	// Losses recorded when the previous frame was delivered
+	losses: u64,
+	// Flags of the next frame
+	frame_flags: u32,
 }
 
 impl IRCommunicationHandler {
 	pub fn new() -> Self {
-		return Self { backend: IOBackend::new() };
+		return Self {
+			backend: TimingLayer::new(IOBackend::new()),
+			statistics: StatisticsRecorder::new(),
+			clock: Clock::new(),
+			state: ConnectionState::Disabled,
+			failed: false,
+			state_callback: None,
+			transfer_pending_frames: true,
+			resync_requested: false,
+			created: Instant::now(),
+			next_sequence: 0,
+			losses: 0,
+			frame_flags: 0,
+		};
+	}
+
+	// Selects the configured backend without enabling it
+	pub fn from_config(config: &Config) -> Result<Self, String> {
+		if let Some(log_level) = config.log_level {
+			log::set_log_level(log_level);
+		}
+
+		let mut handler = Self::new();
+		handler.set_transfer_pending_frames(config.transfer_pending_frames);
+		handler.set_timing_options(config.timing_options);
+
+		match config.backend {
+			Some(BackendKind::Serial) => {
+				handler.select_serial_backend(config::required(&config.serial_port, "serial.port")?, &config.serial_options)?;
+			},
+			Some(BackendKind::RendezVous) => {
+				let room_name = config::required(&config.rendezvous_room, "rendezvous.room")?;
+				if config.rendezvous_broadcast {
+					handler.select_broadcast_rendezvous_backend_in(&config.rendezvous_folder, room_name)?;
+				}
+				else {
+					handler.select_rendezvous_backend_in(&config.rendezvous_folder, room_name)?;
+				}
+			},
+			Some(BackendKind::RemoteRendezVous) => {
+				handler.select_remote_rendezvous_backend(config::required(&config.relay_host, "relay.host")?, config.relay_port, config::required(&config.relay_room, "relay.room")?)?;
+			},
+			Some(BackendKind::Network) => {
+				let destination_host = config::required(&config.network_destination_host, "network.destination_host")?;
+				handler.select_network_backend(config.network_source_port, destination_host, config.network_destination_port, &config.network_options)?;
+			},
+			None => {},
+		}
+		return Ok(handler);
+	}
+
+	fn set_state(&mut self, state: ConnectionState) {
+		if state != self.state {
+			log_info!("Connection state changed from {:?} to {:?}", self.state, state);
+			self.state = state;
+
+			if let Some(state_callback) = &mut self.state_callback {
+				state_callback(state);
+			}
+		}
+	}
+
+	fn update_state(&mut self) {
+		if self.failed {
+			self.set_state(ConnectionState::Error);
+		}
+		else {
+			self.set_state(self.backend.state());
+		}
+	}
+
+	pub fn set_state_callback(&mut self, state_callback: Option<StateCallback>) {
+		self.state_callback = state_callback;
+	}
+
+	pub fn set_transfer_pending_frames(&mut self, transfer_pending_frames: bool) {
+		self.transfer_pending_frames = transfer_pending_frames;
+	}
+
+	// Delays and smooths the frames of every backend, transparent by default
+	pub fn set_timing_options(&mut self, options: TimingOptions) {
+		self.backend.set_options(options);
+	}
+
+	pub fn timing_options(&self) -> TimingOptions {
+		return self.backend.options();
+	}
+
+	// Switches to emulated time on the first call: frames are then paced, and timeouts evaluated,
+	// against the time the emulator reports instead of the wall clock
+	pub fn advance_clock(&mut self, duration: Duration) {
+		if !self.clock.is_emulated() {
+			log_info!("Following the emulated clock");
+		}
+		self.clock.advance(duration);
+	}
+
+	// Frames already held by the timing layer keep the time they were given
+	pub fn use_wall_clock(&mut self) {
+		if self.clock.is_emulated() {
+			log_info!("Following the wall clock");
+		}
+		self.clock.use_wall_clock();
+	}
+
+	// Frames the game sent that the peer did not get yet, and frames received that the game did not get yet
+	pub fn save_state(&self) -> Vec<u8> {
+		let (delayed_frames, incoming_frames) = self.backend.held_frames(self.clock.now());
+		let mut outgoing_frames: Vec<(Duration, Vec<u8>)> = self.backend.inner().pending_frames().into_iter().map(|data| (Duration::ZERO, data)).collect();
+		outgoing_frames.extend(delayed_frames);
+
+		return SavedState { outgoing_frames, incoming_frames }.to_bytes();
+	}
+
+	// Frames queued since the save are discarded, and the peer is told to resync: what it has in flight is stale
+	pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+		let state = SavedState::from_bytes(bytes)?;
+		log_info!("Loading state with {} outgoing and {} incoming frames", state.outgoing_frames.len(), state.incoming_frames.len());
+
+		self.backend.inner_mut().take_pending_frames();
+		// Before the restored frames, so that the peer does not discard them
+		if self.state.is_enabled() {
+			if let Err(e) = self.backend.inner_mut().request_resync() {
+				log_info!("Fail on request resync: {}", e);
+			}
+		}
+		self.backend.restore_held_frames(state.outgoing_frames, state.incoming_frames, self.clock.now());
+
+		self.update_state();
+		return Ok(());
+	}
+
+	// Whether the peer loaded a savestate since the last call
+	pub fn take_resync_request(&mut self) -> bool {
+		return std::mem::take(&mut self.resync_requested);
+	}
+
+	// Time of a frame timestamp since the handler was created, on the handler clock
+	pub fn time_since_creation(&self, timestamp: Instant) -> Duration {
+		return timestamp.saturating_duration_since(self.created);
+	}
+
+	// Same as receive, along with when the frame arrived, who sent it, and whether frames were lost before it
+	pub fn receive_frame(&mut self) -> Result<Option<ReceivedFrame>, String> {
+		let ret = self.backend.receive_frame_at(self.clock.now());
+		self.update_state();
+
+		match ret {
+			Ok(Some(mut frame)) => {
+				if let Some((sequence, flags)) = self.deliver(frame.data.len()) {
+					frame.sequence = sequence;
+					frame.flags = flags;
+					log_debug!("[core.rs]::IRCommunicationHandler.receive() -> {:?} from {:08x}", frame.data, frame.source);
+					return Ok(Some(frame));
+				}
+				return Ok(None);
+			},
+			_ => {
+				return ret;
+			},
+		}
 	}
 
-	pub fn get_available_serial_ports() -> Result<HashMap<String, String>, String> {
-		return SerialBackend::get_available_serial_ports();
+	// Length of the next frame, which stays queued until received: lets callers size their buffer
+	pub fn peek_frame_len(&mut self) -> Result<Option<usize>, String> {
+		loop {
+			let ret = self.backend.peek_frame_len_at(self.clock.now());
+			self.update_state();
+
+			match ret {
+				// Resync requests are not frames, they are taken right away
+				Ok(Some(0)) => {
+					self.receive_frame()?;
+				},
+				_ => {
+					return ret;
+				},
+			}
+		}
+	}
+
+	// Accounts for a frame of `len` bytes leaving the handler: returns its sequence and flags, None for resync requests
+	fn deliver(&mut self, len: usize) -> Option<(u64, u32)> {
+		// Resync requests are the only empty frames
+		if len == 0 {
+			log_info!("Peer loaded a savestate, resync requested");
+			self.resync_requested = true;
+			self.frame_flags |= FRAME_FLAG_AFTER_RESYNC;
+			return None;
+		}
+
+		let losses = self.statistics.losses();
+		if losses != self.losses {
+			self.losses = losses;
+			self.frame_flags |= FRAME_FLAG_FRAMES_LOST;
+		}
+		let sequence = self.next_sequence;
+		self.next_sequence += 1;
+
+		self.statistics.record_received(len);
+		return Some((sequence, std::mem::take(&mut self.frame_flags)));
+	}
+
+	// Frames accepted by send() but not delivered yet, by the backend first, then by the timing layer
+	fn take_pending_frames(&mut self) -> Vec<Vec<u8>> {
+		let mut pending_frames = self.backend.inner_mut().take_pending_frames();
+		pending_frames.extend(self.backend.take_delayed_frames());
+		return pending_frames;
 	}
 
+	// The old backend is disabled before the new one is enabled, so that both can use the same port.
+	// If the new backend fails to enable, the old one is enabled again and the error is returned.
+	fn switch_backend(&mut self, mut backend: IOBackend) -> Result<(), String> {
+		if !self.state.is_enabled() {
+			self.backend.disable();
+			self.backend.replace_inner(backend);
+			self.failed = false;
+			self.update_state();
+			return Ok(());
+		}
+
+		let pending_frames = self.take_pending_frames();
+		self.backend.disable();
+		self.set_state(ConnectionState::Connecting);
+
+		if let Err(error) = backend.enable() {
+			log_info!("Fail on switch backend: {}, restoring the previous one", error);
+
+			self.failed = self.backend.enable().is_err();
+			if !self.failed {
+				self.resend_frames(pending_frames);
+			}
+			self.update_state();
+			return Err(error);
+		}
+
+		self.backend.replace_inner(backend);
+		if self.transfer_pending_frames {
+			self.resend_frames(pending_frames);
+		}
+		else {
+			for _ in pending_frames {
+				self.statistics.record_dropped();
+			}
+		}
+		self.update_state();
+		return Ok(());
+	}
+
+	fn resend_frames(&mut self, frames: Vec<Vec<u8>>) {
+		for data in frames {
+			if self.backend.send_at(&data, self.clock.now()).is_err() {
+				self.statistics.record_dropped();
+			}
+		}
+	}
+
+	pub fn get_available_serial_ports() -> Result<SerialPortList, String> {
+		return SerialBackend::get_serial_port_list();
+	}
+
+	// Enumerates the ports again, the generation changes only if they differ from the previous enumeration
+	pub fn get_serial_ports_generation() -> Result<u64, String> {
+		return Ok(SerialBackend::get_serial_port_list()?.generation);
+	}
+
+	// Rooms of this machine and of the local network, the latter taking discovery::QUERY_DURATION to gather
 	pub fn get_available_rooms() -> Result<Vec<String>, String> {
-		//return Ok(Vec::new());
-		return Ok(vec!["Room A".to_string(), "Room B".to_string(), "Room C".to_string()]);
+		return Self::get_available_rooms_in(&rendezvous::default_rendezvous_folder());
+	}
+
+	pub fn get_available_rooms_in(rendezvous_folder: &Path) -> Result<Vec<String>, String> {
+		let mut rooms = rendezvous::get_available_rooms(rendezvous_folder).map_err(|e| e.to_string())?;
+
+		match discovery::query_rooms(&DiscoveryEndpoint::default(), discovery::QUERY_DURATION) {
+			Ok(lan_rooms) => { rooms.extend(lan_rooms); },
+			Err(e) => { log_info!("LAN discovery unavailable: {}", e); },
+		}
+
+		rooms.sort();
+		rooms.dedup();
+		return Ok(rooms);
+	}
+
+	// Rooms of the relay server with a peer waiting in them, port 0 selects the default relay port
+	pub fn get_available_remote_rooms(server_host: &str, server_port: u16) -> Result<Vec<String>, String> {
+		return relay::query_rooms(server_host, relay::port_or_default(server_port));
 	}
 
-	pub fn select_serial_backend(&mut self, port_name: &str) {
-		self.backend = IOBackend::new_serial(port_name);
+	pub fn select_serial_backend(&mut self, port_name: &str, options: &SerialOptions) -> Result<(), String> {
+		return self.switch_backend(IOBackend::new_serial(port_name, options, self.statistics.clone(), self.clock.clone()));
 	}
 
-	pub fn select_rendezvous_backend(&mut self, room_name: &str) {
-		self.backend = IOBackend::new_rendezvous(room_name);
+	pub fn select_rendezvous_backend(&mut self, room_name: &str) -> Result<(), String> {
+		return self.select_rendezvous_backend_in(&rendezvous::default_rendezvous_folder(), room_name);
 	}
 
-	pub fn select_network_backend(&mut self, source_port: u16, destination_host: &str, destination_port: u16) {
-		self.backend = IOBackend::new_network(source_port, destination_host, destination_port);
+	// Peers only meet in rooms of the same rendez-vous folder
+	pub fn select_rendezvous_backend_in(&mut self, rendezvous_folder: &Path, room_name: &str) -> Result<(), String> {
+		return self.switch_backend(IOBackend::new_rendezvous(room_name, rendezvous_folder, self.statistics.clone()));
+	}
+
+	// Any number of peers join the room, and every frame is delivered to all the others
+	pub fn select_broadcast_rendezvous_backend(&mut self, room_name: &str) -> Result<(), String> {
+		return self.select_broadcast_rendezvous_backend_in(&rendezvous::default_rendezvous_folder(), room_name);
+	}
+
+	pub fn select_broadcast_rendezvous_backend_in(&mut self, rendezvous_folder: &Path, room_name: &str) -> Result<(), String> {
+		return self.switch_backend(IOBackend::new_broadcast_rendezvous(room_name, rendezvous_folder, self.statistics.clone()));
+	}
+
+	// Port 0 selects the default relay port
+	pub fn select_remote_rendezvous_backend(&mut self, server_host: &str, server_port: u16, room_name: &str) -> Result<(), String> {
+		if !relay::is_valid_room_name(room_name) {
+			return Err(format!("Invalid room name '{}'", room_name));
+		}
+		return self.switch_backend(IOBackend::new_remote_rendezvous(server_host, relay::port_or_default(server_port), room_name, self.statistics.clone()));
+	}
+
+	pub fn select_network_backend(&mut self, source_port: u16, destination_host: &str, destination_port: u16, options: &NetworkOptions) -> Result<(), String> {
+		if options.broadcast {
+			broadcast::check_network_options(options)?;
+		}
+		return self.switch_backend(IOBackend::new_network(source_port, destination_host, destination_port, options, self.statistics.clone()));
+	}
+
+	pub fn stats(&self) -> LinkStatistics {
+		return self.statistics.snapshot();
+	}
+
+	pub fn reset_stats(&mut self) {
+		self.statistics.reset();
 	}
 }
 
 impl IOHandler for IRCommunicationHandler {
 	fn enable(&mut self) -> Result<(), String> {
-		return self.backend.enable();
+		if let IOBackend::Disabled = self.backend.inner() {
+			return Err(String::from("No backend selected"));
+		}
+		if self.state.is_enabled() {
+			log_info!("Already enabled ({:?})", self.state);
+			return Ok(());
+		}
+
+		self.failed = false;
+		self.set_state(ConnectionState::Connecting);
+
+		let ret = self.backend.enable();
+		self.failed = ret.is_err();
+		self.update_state();
+		return ret;
 	}
 
 	fn disable(&mut self) {
 		self.backend.disable();
+		self.failed = false;
+		self.update_state();
 	}
 
 	fn send(&mut self, data: &[u8]) -> Result<(), String> {
-		println!("[core.rs]::IRCommunicationHandler.send({:?})", data);
-		return self.backend.send(data);
+		log_debug!("[core.rs]::IRCommunicationHandler.send({:?})", data);
+
+		if data.len() > MAXIMUM_DATA_LEN {
+			self.statistics.record_oversize_send();
+			return Err(format!("Data too long ({} bytes), should be at most {}", data.len(), MAXIMUM_DATA_LEN));
+		}
+		// Reserved for resync requests
+		if data.is_empty() {
+			return Err(String::from("Data is empty"));
+		}
+		if !self.state.is_enabled() {
+			return Err(format!("Cannot send while {:?}", self.state));
+		}
+
+		let ret = self.backend.send_at(data, self.clock.now());
+		if ret.is_ok() {
+			self.statistics.record_sent(data.len());
+		}
+		self.update_state();
+		return ret;
 	}
 
 	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
-		let ret = self.backend.receive();
-		if ret.is_ok() {
-			if let Some(data) = ret.as_ref().unwrap() {
-				println!("[core.rs]::IRCommunicationHandler.receive() -> {:?}", data);
+		return Ok(self.receive_from()?.map(|(_, data)| data));
+	}
+
+	// Straight into `buffer` while the timing emulation holds no frame.
+	// A frame longer than the buffer stays queued.
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		// Any frame fits in MAXIMUM_DATA_LEN bytes
+		if buffer.len() < MAXIMUM_DATA_LEN {
+			if let Some(len) = self.peek_frame_len()? {
+				check_buffer_len(len, buffer)?;
 			}
 		}
-		return ret;
+
+		let ret = self.backend.receive_into_at(buffer, self.clock.now());
+		self.update_state();
+
+		match ret {
+			Ok(Some(len)) => {
+				if self.deliver(len).is_some() {
+					log_debug!("[core.rs]::IRCommunicationHandler.receive_into() -> {:?}", &buffer[..len]);
+					return ret;
+				}
+				return Ok(None);
+			},
+			_ => {
+				return ret;
+			},
+		}
 	}
-}
\ No newline at end of file
+
+	// The sender is UNKNOWN_PEER on point-to-point links
+	fn receive_from(&mut self) -> Result<Option<(PeerId, Vec<u8>)>, String> {
+		return Ok(self.receive_frame()?.map(|frame| (frame.source, frame.data)));
+	}
+
+	fn state(&self) -> ConnectionState {
+		return self.state;
+	}
+}
+#[cfg(test)]
+mod tests;
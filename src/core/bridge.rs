@@ -0,0 +1,77 @@
+use super::traits::IOHandler;
+use super::IRCommunicationHandler;
+
+#[derive(Clone, Debug, Default)]
+pub struct DirectionStatistics {
+	pub frames: u64,
+	pub bytes: u64,
+	pub send_errors: u64,
+}
+
+// Forwards frames both ways between two handlers, e.g. a serial dongle and a network peer
+pub struct Bridge {
+	left: IRCommunicationHandler,
+	right: IRCommunicationHandler,
+	left_to_right: DirectionStatistics,
+	right_to_left: DirectionStatistics,
+}
+
+impl Bridge {
+	pub fn new(left: IRCommunicationHandler, right: IRCommunicationHandler) -> Self {
+		return Self { left, right, left_to_right: DirectionStatistics::default(), right_to_left: DirectionStatistics::default() };
+	}
+
+	pub fn enable(&mut self) -> Result<(), String> {
+		self.left.enable()?;
+
+		if let Err(error) = self.right.enable() {
+			self.left.disable();
+			return Err(error);
+		}
+		return Ok(());
+	}
+
+	pub fn disable(&mut self) {
+		self.left.disable();
+		self.right.disable();
+	}
+
+	// Forwards at most one frame in each direction, returns whether anything was forwarded
+	pub fn step(&mut self) -> Result<bool, String> {
+		let left_forwarded = forward(&mut self.left, &mut self.right, &mut self.left_to_right)?;
+		let right_forwarded = forward(&mut self.right, &mut self.left, &mut self.right_to_left)?;
+		return Ok(left_forwarded || right_forwarded);
+	}
+
+	pub fn left_to_right(&self) -> &DirectionStatistics {
+		return &self.left_to_right;
+	}
+
+	pub fn right_to_left(&self) -> &DirectionStatistics {
+		return &self.right_to_left;
+	}
+
+	pub fn reset_stats(&mut self) {
+		self.left_to_right = DirectionStatistics::default();
+		self.right_to_left = DirectionStatistics::default();
+	}
+}
+
+// A failing receive means the source link is broken and is reported,
+// a failing send only loses that frame and is counted
+fn forward(source: &mut IRCommunicationHandler, target: &mut IRCommunicationHandler, statistics: &mut DirectionStatistics) -> Result<bool, String> {
+	if let Some(data) = source.receive()? {
+		if target.send(&data).is_ok() {
+			statistics.frames += 1;
+			statistics.bytes += data.len() as u64;
+		}
+		else {
+			statistics.send_errors += 1;
+		}
+		return Ok(true);
+	}
+	return Ok(false);
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use super::Bridge;
+use super::super::{IRCommunicationHandler, NetworkOptions};
+use super::super::traits::IOHandler;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+// Two handlers talking to each other over the loopback interface
+fn loopback_pair() -> (IRCommunicationHandler, IRCommunicationHandler) {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let (mut left, mut right) = (IRCommunicationHandler::new(), IRCommunicationHandler::new());
+
+	left.select_network_backend(left_port, "127.0.0.1", right_port, &NetworkOptions::default()).unwrap();
+	right.select_network_backend(right_port, "127.0.0.1", left_port, &NetworkOptions::default()).unwrap();
+	return (left, right);
+}
+
+// Steps the bridge until `endpoint` receives a frame
+fn forward_to(bridge: &mut Bridge, endpoint: &mut IRCommunicationHandler) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < TEST_TIMEOUT {
+		bridge.step().unwrap();
+		if let Some(data) = endpoint.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn frames_are_forwarded_both_ways() {
+	// left_endpoint <-> [left | bridge | right] <-> right_endpoint
+	let (mut left_endpoint, left) = loopback_pair();
+	let (right, mut right_endpoint) = loopback_pair();
+	let mut bridge = Bridge::new(left, right);
+	bridge.enable().unwrap();
+	left_endpoint.enable().unwrap();
+	right_endpoint.enable().unwrap();
+
+	assert!(!bridge.step().unwrap());
+
+	left_endpoint.send(&[0x01, 0x02, 0x03]).unwrap();
+	assert_eq!(forward_to(&mut bridge, &mut right_endpoint), Some(vec![0x01, 0x02, 0x03]));
+	right_endpoint.send(&[0x04]).unwrap();
+	right_endpoint.send(&[0x05, 0x06]).unwrap();
+	assert_eq!(forward_to(&mut bridge, &mut left_endpoint), Some(vec![0x04]));
+	assert_eq!(forward_to(&mut bridge, &mut left_endpoint), Some(vec![0x05, 0x06]));
+
+	assert_eq!((bridge.left_to_right().frames, bridge.left_to_right().bytes, bridge.left_to_right().send_errors), (1, 3, 0));
+	assert_eq!((bridge.right_to_left().frames, bridge.right_to_left().bytes, bridge.right_to_left().send_errors), (2, 3, 0));
+
+	// Lost, and counted as such
+	bridge.right.disable();
+	left_endpoint.send(&[0x07]).unwrap();
+	let start = Instant::now();
+	while bridge.left_to_right().send_errors == 0 && start.elapsed() < TEST_TIMEOUT {
+		bridge.step().unwrap();
+	}
+	assert_eq!((bridge.left_to_right().frames, bridge.left_to_right().send_errors), (1, 1));
+
+	bridge.reset_stats();
+	assert_eq!(bridge.left_to_right().frames, 0);
+	assert_eq!(bridge.right_to_left().bytes, 0);
+	bridge.disable();
+}
@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Time against which the handler paces frames and its backends evaluate timeouts: the wall clock by default,
+// or the clock of an emulator, that only moves when the emulator reports the time it emulated,
+// so that links behave the same whether the emulator runs fast-forwarded, paused or frame by frame.
+// Shared between the handler and its backends, like StatisticsRecorder.
+#[derive(Clone)]
+pub struct Clock {
+	// None while following the wall clock
+	emulated_now: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Clock {
+	pub fn new() -> Self {
+		return Self { emulated_now: Arc::new(Mutex::new(None)) };
+	}
+
+	pub fn now(&self) -> Instant {
+		return self.emulated_now.lock().unwrap().unwrap_or_else(Instant::now);
+	}
+
+	pub fn is_emulated(&self) -> bool {
+		return self.emulated_now.lock().unwrap().is_some();
+	}
+
+	// The first call switches to emulated time, starting from the current wall clock time
+	pub fn advance(&self, duration: Duration) {
+		let mut emulated_now = self.emulated_now.lock().unwrap();
+		let now = emulated_now.unwrap_or_else(Instant::now);
+		*emulated_now = Some(now.checked_add(duration).unwrap_or(now));
+	}
+
+	pub fn use_wall_clock(&self) {
+		*self.emulated_now.lock().unwrap() = None;
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,37 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Clock;
+
+#[test]
+fn follows_the_wall_clock_by_default() {
+	let clock = Clock::new();
+	let before = Instant::now();
+	thread::sleep(Duration::from_millis(5));
+
+	assert!(!clock.is_emulated());
+	assert!(clock.now() >= before + Duration::from_millis(5));
+}
+
+#[test]
+fn emulated_time_only_moves_when_advanced() {
+	let clock = Clock::new();
+	clock.advance(Duration::ZERO);
+	assert!(clock.is_emulated());
+
+	let start = clock.now();
+	thread::sleep(Duration::from_millis(5));
+	assert_eq!(clock.now(), start);
+
+	// Fast-forward, far ahead of the wall clock
+	clock.advance(Duration::from_secs(60));
+	assert_eq!(clock.now(), start + Duration::from_secs(60));
+
+	// Shared with the backends
+	let backend_clock = clock.clone();
+	clock.advance(Duration::from_nanos(1));
+	assert_eq!(backend_clock.now(), start + Duration::from_secs(60) + Duration::from_nanos(1));
+
+	clock.use_wall_clock();
+	assert!(!backend_clock.is_emulated());
+}
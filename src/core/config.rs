@@ -0,0 +1,318 @@
+// Settings of an IRCommunicationHandler, read from TOML then overridden by environment variables.
+// Every setting has a variable, named after its key: serial.baud_rate is N3T1R_SERIAL_BAUD_RATE.
+// An empty string unsets the optional ones.
+//
+//   backend = "serial"                # serial, rendezvous, remote-rendezvous or network, none by default
+//   transfer_pending_frames = true
+//
+//   [serial]
+//   port = "/dev/ttyACM0"
+//   baud_rate = 115200
+//   rx_timeout_ms = 10
+//
+//   [rendezvous]
+//   room = "trade"
+//   folder = "/run/user/1000/ntir11-rdv" # $XDG_RUNTIME_DIR/ntir11-rdv, or a folder of the user in the temporary directory
+//   broadcast = false                 # any number of peers, every frame is delivered to all the others
+//
+//   [relay]
+//   host = "relay.example.org"
+//   port = 0                          # 0 for the default relay port
+//   room = "trade"
+//
+//   [network]
+//   source_port = 8001
+//   destination_host = "192.168.1.2"
+//   destination_port = 8002
+//   transport = "udp"                 # udp, tcp or websocket
+//   role = "client"                   # client or server
+//   passphrase = ""
+//   reliable = false
+//   broadcast = false                 # to a broadcast or multicast destination, over UDP only
+//
+//   [timing]
+//   send_delay_us = 0
+//   send_jitter_us = 0
+//   receive_delay_us = 0
+//   byte_time_ns = 0                  # 86806 for the timing of the dongle
+//   turnaround_us = 0
+//
+//   [log]
+//   level = "debug"                   # quiet, info or debug, process-wide
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::io::network::{NetworkOptions, NetworkRole, NetworkTransport};
+use super::io::serial::SerialOptions;
+use super::log::LogLevel;
+use super::rendezvous::default_rendezvous_folder;
+use super::timing::TimingOptions;
+
+const ENVIRONMENT_PREFIX: &str = "N3T1R_";
+
+const SETTINGS: &[&str] = &[
+	"backend",
+	"transfer_pending_frames",
+	"serial.port",
+	"serial.baud_rate",
+	"serial.rx_timeout_ms",
+	"rendezvous.room",
+	"rendezvous.folder",
+	"rendezvous.broadcast",
+	"relay.host",
+	"relay.port",
+	"relay.room",
+	"network.source_port",
+	"network.destination_host",
+	"network.destination_port",
+	"network.transport",
+	"network.role",
+	"network.passphrase",
+	"network.reliable",
+	"network.broadcast",
+	"timing.send_delay_us",
+	"timing.send_jitter_us",
+	"timing.receive_delay_us",
+	"timing.byte_time_ns",
+	"timing.turnaround_us",
+	"log.level",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+	Serial,
+	RendezVous,
+	RemoteRendezVous,
+	Network,
+}
+
+#[derive(Clone)]
+pub struct Config {
+	// No backend is selected without it
+	pub backend: Option<BackendKind>,
+	pub transfer_pending_frames: bool,
+	pub serial_port: Option<String>,
+	pub serial_options: SerialOptions,
+	pub rendezvous_room: Option<String>,
+	pub rendezvous_folder: PathBuf,
+	pub rendezvous_broadcast: bool,
+	pub relay_host: Option<String>,
+	// 0 selects the default relay port
+	pub relay_port: u16,
+	pub relay_room: Option<String>,
+	pub network_source_port: u16,
+	pub network_destination_host: Option<String>,
+	pub network_destination_port: u16,
+	pub network_options: NetworkOptions,
+	pub timing_options: TimingOptions,
+	// The current level is kept without it
+	pub log_level: Option<LogLevel>,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		return Self {
+			backend: None,
+			transfer_pending_frames: true,
+			serial_port: None,
+			serial_options: SerialOptions::default(),
+			rendezvous_room: None,
+			rendezvous_folder: default_rendezvous_folder(),
+			rendezvous_broadcast: false,
+			relay_host: None,
+			relay_port: 0,
+			relay_room: None,
+			network_source_port: 0,
+			network_destination_host: None,
+			network_destination_port: 0,
+			network_options: NetworkOptions::default(),
+			timing_options: TimingOptions::default(),
+			log_level: None,
+		};
+	}
+}
+
+fn environment_variable(setting: &str) -> String {
+	return format!("{}{}", ENVIRONMENT_PREFIX, setting.replace('.', "_").to_ascii_uppercase());
+}
+
+fn parse_backend(value: &str) -> Option<Option<BackendKind>> {
+	return match value {
+		"" | "none" => { Some(None) },
+		"serial" => { Some(Some(BackendKind::Serial)) },
+		"rendezvous" => { Some(Some(BackendKind::RendezVous)) },
+		"remote-rendezvous" => { Some(Some(BackendKind::RemoteRendezVous)) },
+		"network" => { Some(Some(BackendKind::Network)) },
+		_ => { None },
+	};
+}
+
+fn parse_transport(value: &str) -> Option<NetworkTransport> {
+	return match value {
+		"udp" => { Some(NetworkTransport::Udp) },
+		"tcp" => { Some(NetworkTransport::Tcp) },
+		"websocket" => { Some(NetworkTransport::WebSocket) },
+		_ => { None },
+	};
+}
+
+fn parse_role(value: &str) -> Option<NetworkRole> {
+	return match value {
+		"client" => { Some(NetworkRole::Client) },
+		"server" => { Some(NetworkRole::Server) },
+		_ => { None },
+	};
+}
+
+// Environment variables are not typed, so the usual spellings are accepted
+fn parse_bool(value: &str) -> Option<bool> {
+	return match value.to_ascii_lowercase().as_str() {
+		"true" | "1" | "yes" | "on" => { Some(true) },
+		"false" | "0" | "no" | "off" => { Some(false) },
+		_ => { None },
+	};
+}
+
+// Each setter returns whether the value was valid, leaving the setting unchanged otherwise
+fn set_parsed<T>(target: &mut T, parsed: Option<T>) -> bool {
+	if let Some(parsed) = parsed {
+		*target = parsed;
+		return true;
+	}
+	return false;
+}
+
+fn set_number<T: FromStr>(target: &mut T, value: &str) -> bool {
+	return set_parsed(target, value.parse().ok());
+}
+
+fn set_duration(target: &mut Duration, value: &str, from_units: fn(u64) -> Duration) -> bool {
+	return set_parsed(target, value.parse().ok().map(from_units));
+}
+
+fn set_string(target: &mut Option<String>, value: &str) -> bool {
+	*target = if value.is_empty() { None } else { Some(value.to_owned()) };
+	return true;
+}
+
+// Strings are taken as they are, numbers and booleans as they are written
+fn toml_value_to_string(value: &toml::Value) -> Option<String> {
+	return match value {
+		toml::Value::String(string) => { Some(string.clone()) },
+		toml::Value::Integer(integer) => { Some(integer.to_string()) },
+		toml::Value::Boolean(boolean) => { Some(boolean.to_string()) },
+		_ => { None },
+	};
+}
+
+// Settings that the backend can not do without
+pub fn required<'a>(value: &'a Option<String>, setting: &str) -> Result<&'a str, String> {
+	return value.as_deref().ok_or(format!("Setting {} is required by the selected backend", setting));
+}
+
+impl Config {
+	// The TOML alone, without the environment
+	pub fn from_toml(text: &str) -> Result<Self, String> {
+		let table = text.parse::<toml::Table>().map_err(|e| format!("Invalid configuration: {}", e))?;
+		let mut config = Self::default();
+
+		for (key, value) in &table {
+			if let toml::Value::Table(section) = value {
+				for (section_key, section_value) in section {
+					config.set_toml_value(&format!("{}.{}", key, section_key), section_value)?;
+				}
+			}
+			else {
+				config.set_toml_value(key, value)?;
+			}
+		}
+		return Ok(config);
+	}
+
+	pub fn load_str(text: &str) -> Result<Self, String> {
+		let mut config = Self::from_toml(text)?;
+		config.apply_environment()?;
+		return Ok(config);
+	}
+
+	pub fn load_file(path: &Path) -> Result<Self, String> {
+		match fs::read_to_string(path) {
+			Ok(text) => {
+				return Self::load_str(&text);
+			},
+			Err(e) => {
+				return Err(format!("Fail on read '{}': {}", path.display(), e));
+			},
+		}
+	}
+
+	pub fn apply_environment(&mut self) -> Result<(), String> {
+		return self.apply_variables(|name| env::var(name).ok());
+	}
+
+	fn apply_variables(&mut self, variable: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+		for setting in SETTINGS {
+			let name = environment_variable(setting);
+			if let Some(value) = variable(&name) {
+				self.set(setting, &value).map_err(|e| format!("{} (from {})", e, name))?;
+			}
+		}
+		return Ok(());
+	}
+
+	fn set_toml_value(&mut self, setting: &str, value: &toml::Value) -> Result<(), String> {
+		if let Some(string) = toml_value_to_string(value) {
+			return self.set(setting, &string);
+		}
+		return Err(format!("Invalid value {} for {}", value, setting));
+	}
+
+	// Sets a setting by its key, e.g. "serial.baud_rate"
+	pub fn set(&mut self, setting: &str, value: &str) -> Result<(), String> {
+		let valid = match setting {
+			"backend" => { set_parsed(&mut self.backend, parse_backend(value)) },
+			"transfer_pending_frames" => { set_parsed(&mut self.transfer_pending_frames, parse_bool(value)) },
+			"serial.port" => { set_string(&mut self.serial_port, value) },
+			"serial.baud_rate" => { set_number(&mut self.serial_options.baud_rate, value) },
+			"serial.rx_timeout_ms" => { set_duration(&mut self.serial_options.rx_timeout, value, Duration::from_millis) },
+			"rendezvous.room" => { set_string(&mut self.rendezvous_room, value) },
+			"rendezvous.folder" => {
+				self.rendezvous_folder = if value.is_empty() { default_rendezvous_folder() } else { PathBuf::from(value) };
+				true
+			},
+			"rendezvous.broadcast" => { set_parsed(&mut self.rendezvous_broadcast, parse_bool(value)) },
+			"relay.host" => { set_string(&mut self.relay_host, value) },
+			"relay.port" => { set_number(&mut self.relay_port, value) },
+			"relay.room" => { set_string(&mut self.relay_room, value) },
+			"network.source_port" => { set_number(&mut self.network_source_port, value) },
+			"network.destination_host" => { set_string(&mut self.network_destination_host, value) },
+			"network.destination_port" => { set_number(&mut self.network_destination_port, value) },
+			"network.transport" => { set_parsed(&mut self.network_options.transport, parse_transport(value)) },
+			"network.role" => { set_parsed(&mut self.network_options.role, parse_role(value)) },
+			"network.passphrase" => { set_string(&mut self.network_options.passphrase, value) },
+			"network.reliable" => { set_parsed(&mut self.network_options.reliable, parse_bool(value)) },
+			"network.broadcast" => { set_parsed(&mut self.network_options.broadcast, parse_bool(value)) },
+			"timing.send_delay_us" => { set_duration(&mut self.timing_options.send_delay, value, Duration::from_micros) },
+			"timing.send_jitter_us" => { set_duration(&mut self.timing_options.send_jitter, value, Duration::from_micros) },
+			"timing.receive_delay_us" => { set_duration(&mut self.timing_options.receive_delay, value, Duration::from_micros) },
+			"timing.byte_time_ns" => { set_duration(&mut self.timing_options.byte_time, value, Duration::from_nanos) },
+			"timing.turnaround_us" => { set_duration(&mut self.timing_options.turnaround, value, Duration::from_micros) },
+			"log.level" => { set_parsed(&mut self.log_level, LogLevel::parse(value).map(Some)) },
+			_ => {
+				return Err(format!("Unknown setting '{}'", setting));
+			},
+		};
+
+		if !valid {
+			return Err(format!("Invalid value '{}' for {}", value, setting));
+		}
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::UdpSocket;
+use std::process;
+use std::time::{Duration, Instant};
+
+use super::{environment_variable, BackendKind, Config, SETTINGS};
+use super::super::io::network::{NetworkRole, NetworkTransport};
+use super::super::log::LogLevel;
+use super::super::rendezvous::default_rendezvous_folder;
+use super::super::traits::IOHandler;
+use super::super::{ConnectionState, IRCommunicationHandler, SERIAL_RX_TIMEOUT};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+fn receive_within(handler: &mut IRCommunicationHandler, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = handler.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+fn with_variables(config: &mut Config, variables: &[(&str, &str)]) -> Result<(), String> {
+	let variables: HashMap<String, String> = variables.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+	return config.apply_variables(|name| variables.get(name).cloned());
+}
+
+#[test]
+fn empty_configuration_keeps_the_defaults() {
+	let config = Config::from_toml("").unwrap();
+
+	assert_eq!(config.backend, None);
+	assert!(config.transfer_pending_frames);
+	assert_eq!(config.serial_options.baud_rate, 115200);
+	assert_eq!(config.serial_options.rx_timeout, SERIAL_RX_TIMEOUT);
+	assert_eq!(config.rendezvous_folder, default_rendezvous_folder());
+	assert_eq!(config.log_level, None);
+}
+
+#[test]
+fn settings_are_read_from_toml() {
+	let config = Config::from_toml(r#"
+		backend = "network"
+		transfer_pending_frames = false
+
+		[serial]
+		port = "/dev/ttyACM0"
+		baud_rate = 9600
+		rx_timeout_ms = 25
+
+		[rendezvous]
+		room = "trade"
+		folder = "/run/n3t1r"
+
+		[network]
+		source_port = 8001
+		destination_host = "192.168.1.2"
+		destination_port = 8002
+		transport = "websocket"
+		role = "server"
+		passphrase = "secret"
+		reliable = true
+
+		[timing]
+		send_delay_us = 1500
+		byte_time_ns = 86806
+
+		[log]
+		level = "info"
+	"#).unwrap();
+
+	assert_eq!(config.backend, Some(BackendKind::Network));
+	assert!(!config.transfer_pending_frames);
+	assert_eq!(config.serial_port.as_deref(), Some("/dev/ttyACM0"));
+	assert_eq!(config.serial_options.baud_rate, 9600);
+	assert_eq!(config.serial_options.rx_timeout, Duration::from_millis(25));
+	assert_eq!(config.rendezvous_room.as_deref(), Some("trade"));
+	assert_eq!(config.rendezvous_folder.to_str(), Some("/run/n3t1r"));
+	assert_eq!((config.network_source_port, config.network_destination_port), (8001, 8002));
+	assert_eq!(config.network_destination_host.as_deref(), Some("192.168.1.2"));
+	assert_eq!(config.network_options.transport, NetworkTransport::WebSocket);
+	assert_eq!(config.network_options.role, NetworkRole::Server);
+	assert_eq!(config.network_options.passphrase.as_deref(), Some("secret"));
+	assert!(config.network_options.reliable);
+	assert_eq!(config.timing_options.send_delay, Duration::from_micros(1500));
+	assert_eq!(config.timing_options.byte_time, Duration::from_nanos(86806));
+	assert_eq!(config.log_level, Some(LogLevel::Info));
+}
+
+#[test]
+fn environment_overrides_toml() {
+	let mut config = Config::from_toml("backend = \"serial\"\n[serial]\nport = \"COM3\"\n[network]\npassphrase = \"secret\"").unwrap();
+	with_variables(&mut config, &[
+		("N3T1R_SERIAL_PORT", "COM4"),
+		("N3T1R_SERIAL_BAUD_RATE", "57600"),
+		("N3T1R_NETWORK_RELIABLE", "yes"),
+		("N3T1R_NETWORK_PASSPHRASE", ""),
+		("N3T1R_LOG_LEVEL", "QUIET"),
+		("N3T1R_UNRELATED", "ignored"),
+	]).unwrap();
+
+	assert_eq!(config.backend, Some(BackendKind::Serial));
+	assert_eq!(config.serial_port.as_deref(), Some("COM4"));
+	assert_eq!(config.serial_options.baud_rate, 57600);
+	assert!(config.network_options.reliable);
+	assert_eq!(config.network_options.passphrase, None);
+	assert_eq!(config.log_level, Some(LogLevel::Quiet));
+}
+
+#[test]
+fn invalid_configurations_are_rejected() {
+	assert!(Config::from_toml("backend = ").is_err());
+	assert!(Config::from_toml("backend = \"infrared\"").err().unwrap().contains("backend"));
+	assert!(Config::from_toml("[serial]\nspeed = 9600").err().unwrap().contains("serial.speed"));
+	assert!(Config::from_toml("[serial]\nbaud_rate = -1").is_err());
+	assert!(Config::from_toml("[relay]\nport = 70000").is_err());
+	assert!(Config::from_toml("[network]\nreliable = [true]").is_err());
+
+	let mut config = Config::default();
+	assert!(with_variables(&mut config, &[("N3T1R_RELAY_PORT", "default")]).unwrap_err().contains("N3T1R_RELAY_PORT"));
+	assert_eq!(config.relay_port, 0);
+}
+
+#[test]
+fn every_setting_has_an_environment_variable() {
+	assert_eq!(environment_variable("serial.baud_rate"), "N3T1R_SERIAL_BAUD_RATE");
+	assert_eq!(environment_variable("backend"), "N3T1R_BACKEND");
+
+	for setting in SETTINGS {
+		let mut config = Config::default();
+		if let Err(e) = config.set(setting, "") {
+			assert!(!e.contains("Unknown"), "{}", e);
+		}
+	}
+}
+
+#[test]
+fn handlers_are_created_from_configurations() {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let network_config = |source_port: u16, destination_port: u16| {
+		return Config::from_toml(&format!(
+			"backend = \"network\"\n[network]\nsource_port = {}\ndestination_host = \"127.0.0.1\"\ndestination_port = {}\nreliable = true",
+			source_port, destination_port,
+		)).unwrap();
+	};
+
+	let mut left = IRCommunicationHandler::from_config(&network_config(left_port, right_port)).unwrap();
+	let mut right = IRCommunicationHandler::from_config(&network_config(right_port, left_port)).unwrap();
+	assert_eq!(left.state(), ConnectionState::Disabled);
+	left.enable().unwrap();
+	right.enable().unwrap();
+
+	left.send(&[0x01, 0x02]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01, 0x02]));
+
+	let missing_host = Config::from_toml("backend = \"remote-rendezvous\"\n[relay]\nroom = \"trade\"").unwrap();
+	assert!(IRCommunicationHandler::from_config(&missing_host).err().unwrap().contains("relay.host"));
+}
+
+#[test]
+fn rendezvous_folder_is_configurable() {
+	let folder = env::temp_dir().join(format!("n3t1r-config-test-{}", process::id()));
+	let mut config = Config::from_toml("backend = \"rendezvous\"\n[rendezvous]\nroom = \"config-test\"").unwrap();
+	config.set("rendezvous.folder", folder.to_str().unwrap()).unwrap();
+
+	let mut handler = IRCommunicationHandler::from_config(&config).unwrap();
+	handler.enable().unwrap();
+	assert!(folder.join("config-test").is_dir());
+
+	handler.disable();
+	assert!(!folder.join("config-test").exists());
+	let _ = fs::remove_dir(&folder);
+}
@@ -0,0 +1,205 @@
+// Discovery of rendez-vous peers on the local network, through UDP broadcast.
+// Every process listens on the first free port of [DISCOVERY_PORT, DISCOVERY_PORT + DISCOVERY_PORT_COUNT),
+// so that several of them can run on the same machine, and datagrams are broadcast to all of these ports.
+//
+// Datagram: magic "N3T1R" | version (1) | kind (1) | instance id (8, LE) | rendez-vous UDP port (2, BE) | room name
+// Peers waiting in a room ANNOUNCE it periodically, and answer the QUERY of get_available_rooms with an ANNOUNCE.
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::io::network::bind_udp_socket;
+
+const DISCOVERY_PORT: u16 = 7012;
+const DISCOVERY_PORT_COUNT: u16 = 8;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+pub(crate) const QUERY_DURATION: Duration = Duration::from_millis(200);
+
+const MAGIC: &[u8] = b"N3T1R";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 5 + 1 + 1 + 8 + 2;
+const MAXIMUM_DATAGRAM_LEN: usize = 512;
+
+const ANNOUNCE: u8 = 0;
+const QUERY: u8 = 1;
+
+// Where discovery datagrams are sent, the broadcast address of the local network by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscoveryEndpoint {
+	pub address: Ipv4Addr,
+	pub port: u16,
+}
+
+impl Default for DiscoveryEndpoint {
+	fn default() -> Self {
+		return Self { address: Ipv4Addr::BROADCAST, port: DISCOVERY_PORT };
+	}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Datagram {
+	pub kind: u8,
+	pub instance_id: u64,
+	pub port: u16,
+	pub room_name: String,
+}
+
+impl Datagram {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(HEADER_LEN + self.room_name.len());
+		bytes.extend_from_slice(MAGIC);
+		bytes.push(VERSION);
+		bytes.push(self.kind);
+		bytes.extend_from_slice(&self.instance_id.to_le_bytes());
+		bytes.extend_from_slice(&self.port.to_be_bytes());
+		bytes.extend_from_slice(self.room_name.as_bytes());
+		return bytes;
+	}
+
+	// None for anything that is not a discovery datagram of this version
+	pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) || bytes[5] != VERSION || (bytes[6] != ANNOUNCE && bytes[6] != QUERY) {
+			return None;
+		}
+
+		return Some(Self {
+			kind: bytes[6],
+			instance_id: u64::from_le_bytes(bytes[7..15].try_into().unwrap()),
+			port: u16::from_be_bytes([bytes[15], bytes[16]]),
+			room_name: String::from_utf8(bytes[HEADER_LEN..].to_vec()).ok()?,
+		});
+	}
+}
+
+fn random_instance_id() -> Result<u64, String> {
+	let mut instance_id = [0u8; 8];
+	getrandom::fill(&mut instance_id).map_err(|e| format!("Fail on getrandom: {}", e))?;
+	return Ok(u64::from_le_bytes(instance_id));
+}
+
+fn open_socket(port: u16) -> Result<UdpSocket, String> {
+	let socket = bind_udp_socket(port, &(Ipv4Addr::UNSPECIFIED, 0).into())?;
+	socket.set_broadcast(true).map_err(|e| format!("Fail on set_broadcast: {}", e))?;
+	return Ok(socket);
+}
+
+fn send_to_all(socket: &UdpSocket, endpoint: &DiscoveryEndpoint, datagram: &Datagram) -> Result<(), String> {
+	let bytes = datagram.to_bytes();
+	for port in endpoint.port..endpoint.port.saturating_add(DISCOVERY_PORT_COUNT) {
+		socket.send_to(&bytes, (endpoint.address, port)).map_err(|e| format!("Fail on send_to {}:{}: {}", endpoint.address, port, e))?;
+	}
+	return Ok(());
+}
+
+fn receive(socket: &UdpSocket) -> Option<(Datagram, SocketAddr)> {
+	let mut buffer = [0u8; MAXIMUM_DATAGRAM_LEN];
+
+	// Anything else than a valid datagram is ignored, including errors
+	while let Ok((len, source)) = socket.recv_from(&mut buffer) {
+		if let Some(datagram) = Datagram::from_bytes(&buffer[..len]) {
+			return Some((datagram, source));
+		}
+	}
+	return None;
+}
+
+// Addresses of this machine are replaced by the loopback one, so that peers meeting on the same machine
+// through the rendez-vous folder or through discovery agree on their addresses
+fn canonical_peer_address(address: SocketAddr) -> SocketAddr {
+	let is_local = address.ip().is_loopback() || UdpSocket::bind((address.ip(), 0)).is_ok();
+	return if is_local { (Ipv4Addr::LOCALHOST, address.port()).into() } else { address };
+}
+
+pub struct Discovery {
+	endpoint: DiscoveryEndpoint,
+	socket: UdpSocket,
+	instance_id: u64,
+	room_name: String,
+	// Port of the rendez-vous socket, where the peer should send its frames
+	port: u16,
+	last_announcement: Option<Instant>,
+	announce_failed: bool,
+}
+
+impl Discovery {
+	pub fn new(endpoint: DiscoveryEndpoint, room_name: &str, port: u16) -> Result<Self, String> {
+		let mut last_error = String::from("No discovery port");
+		for discovery_port in endpoint.port..endpoint.port.saturating_add(DISCOVERY_PORT_COUNT) {
+			match open_socket(discovery_port) {
+				Ok(socket) => {
+					return Ok(Self { endpoint, socket, instance_id: random_instance_id()?, room_name: room_name.to_owned(), port, last_announcement: None, announce_failed: false });
+				},
+				Err(e) => {
+					last_error = e;
+				},
+			}
+		}
+		return Err(last_error);
+	}
+
+	fn announcement(&self) -> Datagram {
+		return Datagram { kind: ANNOUNCE, instance_id: self.instance_id, port: self.port, room_name: self.room_name.clone() };
+	}
+
+	// Announces the room and answers queries, returns the address of a peer waiting in the same room if any
+	pub fn poll(&mut self) -> Option<SocketAddr> {
+		if self.last_announcement.is_none_or(|last_announcement| last_announcement.elapsed() >= ANNOUNCE_INTERVAL) {
+			self.last_announcement = Some(Instant::now());
+
+			// Networks without broadcast only lose LAN discovery, it is reported once
+			if let Err(e) = send_to_all(&self.socket, &self.endpoint, &self.announcement()) {
+				if !self.announce_failed {
+					log_info!("LAN discovery unavailable: {}", e);
+					self.announce_failed = true;
+				}
+			}
+		}
+
+		while let Some((datagram, source)) = receive(&self.socket) {
+			if datagram.instance_id == self.instance_id {
+				continue;
+			}
+
+			match datagram.kind {
+				QUERY => {
+					let _ = self.socket.send_to(&self.announcement().to_bytes(), source);
+				},
+				_ => {
+					if datagram.room_name == self.room_name {
+						return Some(canonical_peer_address(SocketAddr::new(source.ip(), datagram.port)));
+					}
+				},
+			}
+		}
+		return None;
+	}
+}
+
+// Rooms with a peer waiting in them on the local network, gathered during `duration`
+pub fn query_rooms(endpoint: &DiscoveryEndpoint, duration: Duration) -> Result<Vec<String>, String> {
+	let socket = open_socket(0)?;
+	let query = Datagram { kind: QUERY, instance_id: random_instance_id()?, port: 0, room_name: String::new() };
+	send_to_all(&socket, endpoint, &query)?;
+
+	let mut rooms: Vec<String> = Vec::new();
+	let start = Instant::now();
+	while start.elapsed() < duration {
+		match receive(&socket) {
+			Some((datagram, _)) => {
+				if datagram.kind == ANNOUNCE && !rooms.contains(&datagram.room_name) {
+					rooms.push(datagram.room_name);
+				}
+			},
+			None => {
+				thread::sleep(Duration::from_millis(1));
+			},
+		}
+	}
+
+	rooms.sort();
+	return Ok(rooms);
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{query_rooms, Datagram, Discovery, DiscoveryEndpoint, ANNOUNCE, QUERY};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Discovery datagrams sent to the loopback interface, on ports unlikely to be used by another test
+fn loopback_endpoint() -> DiscoveryEndpoint {
+	let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+	return DiscoveryEndpoint { address: Ipv4Addr::LOCALHOST, port: port.min(u16::MAX - 16) };
+}
+
+// Polls all of them, until each one met a peer or the timeout elapses
+fn poll_until_met(discoveries: &mut [&mut Discovery], timeout: Duration) -> Vec<Option<SocketAddr>> {
+	let mut peers = vec![None; discoveries.len()];
+	let start = Instant::now();
+	while start.elapsed() < timeout && peers.iter().any(|peer| peer.is_none()) {
+		for (discovery, peer) in discoveries.iter_mut().zip(&mut peers) {
+			if peer.is_none() {
+				*peer = discovery.poll();
+			}
+		}
+	}
+	return peers;
+}
+
+#[test]
+fn datagrams_round_trip() {
+	for datagram in [
+		Datagram { kind: ANNOUNCE, instance_id: 0x0123456789ABCDEF, port: 4242, room_name: String::from("room") },
+		Datagram { kind: QUERY, instance_id: 1, port: 0, room_name: String::new() },
+	] {
+		assert_eq!(Datagram::from_bytes(&datagram.to_bytes()), Some(datagram));
+	}
+
+	let mut bytes = Datagram { kind: ANNOUNCE, instance_id: 1, port: 1, room_name: String::from("room") }.to_bytes();
+	assert_eq!(Datagram::from_bytes(&bytes[..10]), None);
+	bytes[0] = b'X';
+	assert_eq!(Datagram::from_bytes(&bytes), None);
+}
+
+#[test]
+fn peers_of_the_same_room_meet() {
+	let endpoint = loopback_endpoint();
+	let mut left = Discovery::new(endpoint, "lan", 1111).unwrap();
+	let mut right = Discovery::new(endpoint, "lan", 2222).unwrap();
+	let mut other = Discovery::new(endpoint, "elsewhere", 3333).unwrap();
+
+	let peers = poll_until_met(&mut [&mut left, &mut right, &mut other], TEST_TIMEOUT);
+	assert_eq!(peers, vec![Some((Ipv4Addr::LOCALHOST, 2222).into()), Some((Ipv4Addr::LOCALHOST, 1111).into()), None]);
+}
+
+#[test]
+fn waiting_rooms_are_listed() {
+	let endpoint = loopback_endpoint();
+	let mut discoveries = vec![Discovery::new(endpoint, "beta", 1111).unwrap(), Discovery::new(endpoint, "alpha", 2222).unwrap()];
+
+	// Queries are answered from poll()
+	let rooms = thread::scope(|scope| {
+		let query = scope.spawn(|| query_rooms(&endpoint, Duration::from_millis(300)).unwrap());
+		while !query.is_finished() {
+			for discovery in &mut discoveries {
+				discovery.poll();
+			}
+		}
+		return query.join().unwrap();
+	});
+	assert_eq!(rooms, vec![String::from("alpha"), String::from("beta")]);
+}
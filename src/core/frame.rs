@@ -0,0 +1,33 @@
+// Frames as delivered by IRCommunicationHandler::receive_frame, with what is known of their reception
+
+use std::time::Instant;
+
+use super::PeerId;
+
+// Frames were lost on reception since the previous frame: framing errors, authentication failures,
+// gaps in the sequence numbers of best-effort UDP, or frames the reliable mode gave up on
+pub const FRAME_FLAG_FRAMES_LOST: u32 = 1 << 0;
+// First frame since the peer loaded a savestate and asked to resync
+pub const FRAME_FLAG_AFTER_RESYNC: u32 = 1 << 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceivedFrame {
+	pub data: Vec<u8>,
+	// When the handler got it from its backend, on the handler clock: before the playout delay of the timing emulation
+	pub timestamp: Instant,
+	// Member of the broadcast room that sent it, UNKNOWN_PEER on point-to-point links
+	pub source: PeerId,
+	// Counts the frames delivered by the handler, from 0
+	pub sequence: u64,
+	pub flags: u32,
+}
+
+impl ReceivedFrame {
+	// Sequence and flags are set by the handler, when it delivers the frame
+	pub fn new(data: Vec<u8>, timestamp: Instant, source: PeerId) -> Self {
+		return Self { data, timestamp, source, sequence: 0, flags: 0 };
+	}
+}
+
+#[cfg(test)]
+mod tests;
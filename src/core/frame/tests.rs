@@ -0,0 +1,112 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{ReceivedFrame, FRAME_FLAG_AFTER_RESYNC, FRAME_FLAG_FRAMES_LOST};
+use super::super::{IRCommunicationHandler, NetworkOptions, TimingOptions, MAXIMUM_DATA_LEN, UNKNOWN_PEER};
+use super::super::traits::IOHandler;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A handler, and the socket of its peer
+fn handler_with_raw_peer() -> (IRCommunicationHandler, UdpSocket, u16) {
+	let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+	let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+	let mut handler = IRCommunicationHandler::new();
+	handler.select_network_backend(port, "127.0.0.1", peer.local_addr().unwrap().port(), &NetworkOptions::default()).unwrap();
+	handler.enable().unwrap();
+	return (handler, peer, port);
+}
+
+// Best-effort UDP packets start with the sequence number of the sender
+fn send_raw(peer: &UdpSocket, port: u16, sequence: u32, frame: &[u8]) {
+	peer.send_to(&[sequence.to_le_bytes().as_slice(), frame].concat(), ("127.0.0.1", port)).unwrap();
+}
+
+fn receive_frame_within(handler: &mut IRCommunicationHandler, timeout: Duration) -> Option<ReceivedFrame> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(frame) = handler.receive_frame().unwrap() {
+			return Some(frame);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn frames_are_timestamped_on_arrival() {
+	let (mut handler, peer, port) = handler_with_raw_peer();
+	// Frozen, so that the frame arrives at a known time
+	handler.advance_clock(Duration::ZERO);
+	handler.set_timing_options(TimingOptions { receive_delay: Duration::from_millis(10), ..TimingOptions::default() });
+	let arrival = handler.clock.now();
+
+	send_raw(&peer, port, 0, &[0x01]);
+	send_raw(&peer, port, 1, &[0x02]);
+	// Held by the playout delay meanwhile
+	let start = Instant::now();
+	while start.elapsed() < TEST_TIMEOUT / 10 {
+		assert_eq!(handler.receive_frame().unwrap(), None);
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	handler.advance_clock(Duration::from_millis(10));
+	let first = handler.receive_frame().unwrap().unwrap();
+	let second = handler.receive_frame().unwrap().unwrap();
+	assert_eq!(first, ReceivedFrame { data: vec![0x01], timestamp: arrival, source: UNKNOWN_PEER, sequence: 0, flags: 0 });
+	assert_eq!((second.data, second.sequence), (vec![0x02], 1));
+	assert_eq!(handler.time_since_creation(handler.clock.now()) - handler.time_since_creation(first.timestamp), Duration::from_millis(10));
+}
+
+#[test]
+fn losses_and_resyncs_are_flagged_on_the_next_frame() {
+	let (mut handler, peer, port) = handler_with_raw_peer();
+
+	send_raw(&peer, port, 0, &[0x01]);
+	assert_eq!(receive_frame_within(&mut handler, TEST_TIMEOUT).map(|frame| frame.flags), Some(0));
+
+	// Too long to be a frame
+	send_raw(&peer, port, 1, &[0xFF; MAXIMUM_DATA_LEN + 1]);
+	send_raw(&peer, port, 2, &[0x02]);
+	let frame = receive_frame_within(&mut handler, TEST_TIMEOUT).unwrap();
+	assert_eq!((frame.data, frame.sequence, frame.flags), (vec![0x02], 1, FRAME_FLAG_FRAMES_LOST));
+
+	// Resync request, which is not a frame
+	send_raw(&peer, port, 3, &[]);
+	send_raw(&peer, port, 4, &[0x03]);
+	let frame = receive_frame_within(&mut handler, TEST_TIMEOUT).unwrap();
+	assert_eq!((frame.data, frame.sequence, frame.flags), (vec![0x03], 2, FRAME_FLAG_AFTER_RESYNC));
+	assert!(handler.take_resync_request());
+
+	// The simple call goes on with the same sequence
+	send_raw(&peer, port, 5, &[0x04]);
+	send_raw(&peer, port, 6, &[0x05]);
+	let start = Instant::now();
+	let mut data = None;
+	while data.is_none() && start.elapsed() < TEST_TIMEOUT {
+		data = handler.receive().unwrap();
+	}
+	assert_eq!(data, Some(vec![0x04]));
+	assert_eq!(receive_frame_within(&mut handler, TEST_TIMEOUT).map(|frame| (frame.sequence, frame.flags)), Some((4, 0)));
+}
+
+#[test]
+fn udp_drops_are_flagged_on_the_next_frame() {
+	let (mut handler, peer, port) = handler_with_raw_peer();
+
+	send_raw(&peer, port, 7, &[0x01]);
+	assert_eq!(receive_frame_within(&mut handler, TEST_TIMEOUT).map(|frame| frame.flags), Some(0));
+
+	// The frames numbered 8 and 9 were lost on the way
+	send_raw(&peer, port, 10, &[0x02]);
+	let frame = receive_frame_within(&mut handler, TEST_TIMEOUT).unwrap();
+	assert_eq!((frame.data, frame.flags), (vec![0x02], FRAME_FLAG_FRAMES_LOST));
+	assert_eq!(handler.stats().frames_dropped, 2);
+
+	// Too late, and not a loss anymore
+	send_raw(&peer, port, 9, &[0x03]);
+	send_raw(&peer, port, 11, &[0x04]);
+	let frame = receive_frame_within(&mut handler, TEST_TIMEOUT).unwrap();
+	assert_eq!((frame.data, frame.flags), (vec![0x04], 0));
+}
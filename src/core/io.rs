@@ -1,14 +1,27 @@
+use std::path::Path;
+
 use super::traits::IOHandler;
+use super::statistics::StatisticsRecorder;
+use super::clock::Clock;
+use super::{ConnectionState, PeerId, UNKNOWN_PEER};
+
+mod frame_ring;
 
 pub mod serial;
 pub mod rendezvous;
+pub mod remote_rendezvous;
 pub mod network;
+pub mod broadcast;
+#[cfg(unix)]
+pub mod unix_socket;
 
 pub enum IOBackend {
 	Disabled,
 	Serial(serial::SerialBackend),
 	RendezVous(rendezvous::RendezVousBackend),
-	Network(network::NetworkBackend),
+	RemoteRendezVous(Box<remote_rendezvous::RemoteRendezVousBackend>),
+	Network(Box<network::NetworkBackend>),
+	Broadcast(Box<broadcast::BroadcastBackend>),
 }
 
 impl IOBackend {
@@ -16,16 +29,55 @@ impl IOBackend {
 		return Self::Disabled;
 	}
 
-	pub fn new_serial(port_name: &str) -> Self {
-		return Self::Serial(serial::SerialBackend::new(port_name));
+	pub fn new_serial(port_name: &str, options: &serial::SerialOptions, statistics: StatisticsRecorder, clock: Clock) -> Self {
+		return Self::Serial(serial::SerialBackend::new(port_name, options, statistics, clock));
+	}
+
+	pub fn new_rendezvous(room_name: &str, rendezvous_folder: &Path, statistics: StatisticsRecorder) -> Self {
+		return Self::RendezVous(rendezvous::RendezVousBackend::new(room_name, rendezvous_folder, statistics));
+	}
+
+	pub fn new_remote_rendezvous(server_host: &str, server_port: u16, room_name: &str, statistics: StatisticsRecorder) -> Self {
+		return Self::RemoteRendezVous(Box::new(remote_rendezvous::RemoteRendezVousBackend::new(server_host, server_port, room_name, statistics)));
+	}
+
+	pub fn new_network(source_port: u16, destination_host: &str, destination_port: u16, options: &network::NetworkOptions, statistics: StatisticsRecorder) -> Self {
+		if options.broadcast {
+			return Self::Broadcast(Box::new(broadcast::BroadcastBackend::network(source_port, destination_host, destination_port, statistics)));
+		}
+		return Self::Network(Box::new(network::NetworkBackend::new(source_port, destination_host, destination_port, options, statistics)));
 	}
 
-	pub fn new_rendezvous(room_name: &str) -> Self {
-		return Self::RendezVous(rendezvous::RendezVousBackend::new(room_name));
+	pub fn new_broadcast_rendezvous(room_name: &str, rendezvous_folder: &Path, statistics: StatisticsRecorder) -> Self {
+		return Self::Broadcast(Box::new(broadcast::BroadcastBackend::rendezvous(room_name, rendezvous_folder, statistics)));
 	}
 
-	pub fn new_network(source_port: u16, destination_host: &str, destination_port: u16) -> Self {
-		return Self::Network(network::NetworkBackend::new(source_port, destination_host, destination_port));
+	// Outgoing frames accepted by send() but not delivered yet
+	pub fn pending_frames(&self) -> Vec<Vec<u8>> {
+		return match self {
+			Self::RendezVous(backend) => { backend.pending_frames() },
+			Self::RemoteRendezVous(backend) => { backend.pending_frames() },
+			Self::Broadcast(backend) => { backend.pending_frames() },
+			_ => { Vec::new() },
+		};
+	}
+
+	pub fn take_pending_frames(&mut self) -> Vec<Vec<u8>> {
+		return match self {
+			Self::RendezVous(backend) => { backend.take_pending_frames() },
+			Self::RemoteRendezVous(backend) => { backend.take_pending_frames() },
+			Self::Broadcast(backend) => { backend.take_pending_frames() },
+			_ => { Vec::new() },
+		};
+	}
+
+	// Tells the peer, with an empty frame, that this side loaded a savestate and that what it has in flight is stale.
+	// Serial dongles talk to real devices, which know nothing about it.
+	pub fn request_resync(&mut self) -> Result<(), String> {
+		return match self {
+			Self::Disabled | Self::Serial(_) => { Ok(()) },
+			_ => { self.send(&[]) },
+		};
 	}
 }
 
@@ -35,7 +87,9 @@ impl IOHandler for IOBackend {
 			Self::Disabled => { Ok(()) },
 			Self::Serial(backend) => { backend.enable() },
 			Self::RendezVous(backend) => { backend.enable() },
+			Self::RemoteRendezVous(backend) => { backend.enable() },
 			Self::Network(backend) => { backend.enable() },
+			Self::Broadcast(backend) => { backend.enable() },
 		};
 	}
 
@@ -44,7 +98,9 @@ impl IOHandler for IOBackend {
 			Self::Disabled => {},
 			Self::Serial(backend) => { backend.disable() },
 			Self::RendezVous(backend) => { backend.disable() },
+			Self::RemoteRendezVous(backend) => { backend.disable() },
 			Self::Network(backend) => { backend.disable() },
+			Self::Broadcast(backend) => { backend.disable() },
 		};
 	}
 
@@ -53,7 +109,9 @@ impl IOHandler for IOBackend {
 			Self::Disabled => { Ok(()) },
 			Self::Serial(backend) => { backend.send(data) },
 			Self::RendezVous(backend) => { backend.send(data) },
+			Self::RemoteRendezVous(backend) => { backend.send(data) },
 			Self::Network(backend) => { backend.send(data) },
+			Self::Broadcast(backend) => { backend.send(data) },
 		};
 	}
 
@@ -62,7 +120,39 @@ impl IOHandler for IOBackend {
 			Self::Disabled => { Ok(None) },
 			Self::Serial(backend) => { backend.receive() },
 			Self::RendezVous(backend) => { backend.receive() },
+			Self::RemoteRendezVous(backend) => { backend.receive() },
 			Self::Network(backend) => { backend.receive() },
+			Self::Broadcast(backend) => { backend.receive() },
+		};
+	}
+
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		return match self {
+			Self::Disabled => { Ok(None) },
+			Self::Serial(backend) => { backend.receive_into(buffer) },
+			Self::RendezVous(backend) => { backend.receive_into(buffer) },
+			Self::RemoteRendezVous(backend) => { backend.receive_into(buffer) },
+			Self::Network(backend) => { backend.receive_into(buffer) },
+			Self::Broadcast(backend) => { backend.receive_into(buffer) },
+		};
+	}
+
+	// Only broadcast rooms know the sender of their frames
+	fn receive_from(&mut self) -> Result<Option<(PeerId, Vec<u8>)>, String> {
+		return match self {
+			Self::Broadcast(backend) => { backend.receive_from() },
+			_ => { Ok(self.receive()?.map(|data| (UNKNOWN_PEER, data))) },
+		};
+	}
+
+	fn state(&self) -> ConnectionState {
+		return match self {
+			Self::Disabled => { ConnectionState::Disabled },
+			Self::Serial(backend) => { backend.state() },
+			Self::RendezVous(backend) => { backend.state() },
+			Self::RemoteRendezVous(backend) => { backend.state() },
+			Self::Network(backend) => { backend.state() },
+			Self::Broadcast(backend) => { backend.state() },
 		};
 	}
 }
\ No newline at end of file
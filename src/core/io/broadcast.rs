@@ -0,0 +1,317 @@
+// Broadcast rooms: any number of members join, and every frame is delivered to all the other members,
+// as IR does between all the devices in range. Members are met through the rendez-vous folder and LAN discovery,
+// or are all reached at once through a broadcast or multicast address with the network transport.
+//
+// Datagram: version (1) | sender peer id (4, LE) | data
+// Every member picks a random peer id, so that receivers know who sent a frame, and ignore their own frames
+// looped back by broadcast and multicast addresses.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::super::traits::{copy_frame, IOHandler};
+use super::super::statistics::StatisticsRecorder;
+use super::super::{ConnectionState, PeerId, MAXIMUM_DATA_LEN, UNKNOWN_PEER};
+use super::super::rendezvous::RendezVous;
+use super::super::discovery::{Discovery, DiscoveryEndpoint};
+use super::network::{bind_udp_socket, NetworkOptions, NetworkTransport};
+use super::rendezvous::MAXIMUM_PENDING_FRAMES;
+
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 1 + 4;
+// Members announced through LAN discovery are forgotten once they stop announcing for that long
+const MEMBER_TIMEOUT: Duration = Duration::from_secs(2);
+// Appended to the room name, so that broadcast rooms do not meet point-to-point rooms of the same name
+pub const BROADCAST_ROOM_SUFFIX: &str = "+broadcast";
+
+// Frames are sent as is to every member, the modes relying on a single peer do not apply
+pub fn check_network_options(options: &NetworkOptions) -> Result<(), String> {
+	if options.transport != NetworkTransport::Udp {
+		return Err(format!("Broadcast rooms need the UDP transport, not {:?}", options.transport));
+	}
+	if options.reliable {
+		return Err(String::from("Broadcast rooms do not support the reliable mode"));
+	}
+	if options.passphrase.is_some() {
+		return Err(String::from("Broadcast rooms do not support the secure mode"));
+	}
+	return Ok(());
+}
+
+fn random_peer_id() -> Result<PeerId, String> {
+	let mut peer_id = [0u8; 4];
+	loop {
+		getrandom::fill(&mut peer_id).map_err(|e| format!("Fail on getrandom: {}", e))?;
+		if PeerId::from_le_bytes(peer_id) != UNKNOWN_PEER {
+			return Ok(PeerId::from_le_bytes(peer_id));
+		}
+	}
+}
+
+#[derive(Clone)]
+enum Room {
+	RendezVous { rendezvous_folder: PathBuf, room_name: String, discovery_endpoint: DiscoveryEndpoint },
+	Network { source_port: u16, destination_host: String, destination_port: u16 },
+}
+
+pub struct BroadcastBackend {
+	room: Room,
+	// Picked on enable
+	peer_id: PeerId,
+	socket: Option<UdpSocket>,
+	// Set in rendez-vous rooms only
+	rendezvous: Option<RendezVous>,
+	discovery: Option<Discovery>,
+	// Members of this machine, as advertised in the room directory
+	local_members: Vec<SocketAddr>,
+	// Members announced through LAN discovery with when they last were, or the network destination that does not expire
+	remote_members: HashMap<SocketAddr, Option<Instant>>,
+	// Frames sent while alone in the room, delivered once a member shows up
+	pending_frames: VecDeque<Vec<u8>>,
+	// Last datagram received, with one extra byte to detect datagrams that are too long
+	datagram: [u8; HEADER_LEN + MAXIMUM_DATA_LEN + 1],
+	statistics: StatisticsRecorder,
+}
+
+impl BroadcastBackend {
+	fn new(room: Room, statistics: StatisticsRecorder) -> Self {
+		return Self { room, peer_id: UNKNOWN_PEER, socket: None, rendezvous: None, discovery: None, local_members: Vec::new(), remote_members: HashMap::new(), pending_frames: VecDeque::new(), datagram: [0; HEADER_LEN + MAXIMUM_DATA_LEN + 1], statistics };
+	}
+
+	pub fn rendezvous(room_name: &str, rendezvous_folder: &Path, statistics: StatisticsRecorder) -> Self {
+		return Self::rendezvous_with_discovery_endpoint(room_name, rendezvous_folder, DiscoveryEndpoint::default(), statistics);
+	}
+
+	pub fn rendezvous_with_discovery_endpoint(room_name: &str, rendezvous_folder: &Path, discovery_endpoint: DiscoveryEndpoint, statistics: StatisticsRecorder) -> Self {
+		let room_name = format!("{}{}", room_name, BROADCAST_ROOM_SUFFIX);
+		return Self::new(Room::RendezVous { rendezvous_folder: rendezvous_folder.to_owned(), room_name, discovery_endpoint }, statistics);
+	}
+
+	// The destination is typically a broadcast or multicast address, which every member listens on
+	pub fn network(source_port: u16, destination_host: &str, destination_port: u16, statistics: StatisticsRecorder) -> Self {
+		return Self::new(Room::Network { source_port, destination_host: destination_host.to_owned(), destination_port }, statistics);
+	}
+
+	fn open_rendezvous_room(&mut self, rendezvous_folder: &Path, room_name: &str, discovery_endpoint: DiscoveryEndpoint) -> Result<UdpSocket, String> {
+		let socket = bind_udp_socket(0, &(Ipv4Addr::LOCALHOST, 0).into())?;
+		let local_udp_port = socket.local_addr().map_err(|e| format!("Fail on local_addr: {}", e))?.port();
+
+		self.rendezvous = Some(RendezVous::new(rendezvous_folder, room_name, local_udp_port).map_err(|e| format!("Fail on advertise: {}", e))?);
+
+		// Without it, only members of this machine can be met
+		match Discovery::new(discovery_endpoint, room_name, local_udp_port) {
+			Ok(discovery) => { self.discovery = Some(discovery); },
+			Err(e) => { log_info!("LAN discovery unavailable: {}", e); },
+		}
+		return Ok(socket);
+	}
+
+	fn open_network_room(&mut self, source_port: u16, destination_host: &str, destination_port: u16) -> Result<UdpSocket, String> {
+		let destination = match (destination_host, destination_port).to_socket_addrs() {
+			Ok(mut addresses) => { addresses.next().ok_or(format!("No address found for '{}'", destination_host))? },
+			Err(e) => { return Err(format!("Fail on resolve '{}': {}", destination_host, e)); },
+		};
+
+		let socket = bind_udp_socket(source_port, &destination)?;
+		socket.set_broadcast(true).map_err(|e| format!("Fail on set_broadcast: {}", e))?;
+		match destination.ip() {
+			IpAddr::V4(group) if group.is_multicast() => {
+				socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED).map_err(|e| format!("Fail on join {}: {}", group, e))?;
+			},
+			IpAddr::V6(group) if group.is_multicast() => {
+				socket.join_multicast_v6(&group, 0).map_err(|e| format!("Fail on join {}: {}", group, e))?;
+			},
+			_ => {},
+		}
+
+		self.remote_members.insert(destination, None);
+		return Ok(socket);
+	}
+
+	// Peers of this machine met through LAN discovery too are only counted once
+	fn members(&self) -> Vec<SocketAddr> {
+		let mut members = self.local_members.clone();
+		members.extend(self.remote_members.keys().filter(|member| !self.local_members.contains(member)));
+		return members;
+	}
+
+	fn update_members(&mut self) -> Result<(), String> {
+		let was_alone = self.local_members.is_empty() && self.remote_members.is_empty();
+
+		if let Some(rendezvous) = &self.rendezvous {
+			self.local_members = rendezvous.advertised_ports().into_iter().map(|port| (Ipv4Addr::LOCALHOST, port).into()).collect();
+		}
+		if let Some(discovery) = &mut self.discovery {
+			self.remote_members.retain(|_, last_announcement| last_announcement.is_none_or(|last_announcement| last_announcement.elapsed() < MEMBER_TIMEOUT));
+			while let Some(member) = discovery.poll() {
+				self.remote_members.insert(member, Some(Instant::now()));
+			}
+		}
+
+		if was_alone && !self.members().is_empty() {
+			log_info!("Met {} member(s) in the broadcast room", self.members().len());
+		}
+
+		// Popped once sent, so that a failing send leaves them queued for the next call
+		if !self.members().is_empty() {
+			while let Some(data) = self.pending_frames.front() {
+				self.send_to_members(data)?;
+				self.pending_frames.pop_front();
+			}
+		}
+		return Ok(());
+	}
+
+	// Every member is tried, the first error is returned
+	fn send_to_members(&self, data: &[u8]) -> Result<(), String> {
+		let mut ret = Ok(());
+
+		if let Some(socket) = &self.socket {
+			let mut packet = Vec::with_capacity(HEADER_LEN + data.len());
+			packet.push(VERSION);
+			packet.extend_from_slice(&self.peer_id.to_le_bytes());
+			packet.extend_from_slice(data);
+
+			for member in self.members() {
+				if let (Err(e), Ok(())) = (socket.send_to(&packet, member), &ret) {
+					ret = Err(format!("Fail on send_to {}: {}", member, e));
+				}
+			}
+		}
+		return ret;
+	}
+
+	// Receives the next frame of another member into self.datagram: returns its sender and length
+	fn receive_datagram(&mut self) -> Result<Option<(PeerId, usize)>, String> {
+		self.update_members()?;
+
+		if let Some(socket) = &self.socket {
+			loop {
+				match socket.recv_from(&mut self.datagram) {
+					Ok((len, _)) => {
+						if !(HEADER_LEN..=HEADER_LEN + MAXIMUM_DATA_LEN).contains(&len) || self.datagram[0] != VERSION {
+							self.statistics.record_framing_error();
+							continue;
+						}
+
+						let sender = PeerId::from_le_bytes(self.datagram[1..HEADER_LEN].try_into().unwrap());
+						if sender != self.peer_id {
+							return Ok(Some((sender, len - HEADER_LEN)));
+						}
+					},
+					Err(e) if e.kind() == ErrorKind::WouldBlock => {
+						return Ok(None);
+					},
+					// Windows reports ICMP port unreachable from a previous send_to as an error on the next recv_from
+					Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+						continue;
+					},
+					Err(e) => {
+						return Err(format!("Fail on recv_from: {}", e));
+					},
+				}
+			}
+		}
+		return Ok(None);
+	}
+
+	pub fn pending_frames(&self) -> Vec<Vec<u8>> {
+		return self.pending_frames.iter().cloned().collect();
+	}
+
+	pub fn take_pending_frames(&mut self) -> Vec<Vec<u8>> {
+		return self.pending_frames.drain(..).collect();
+	}
+}
+
+impl IOHandler for BroadcastBackend {
+	fn enable(&mut self) -> Result<(), String> {
+		if self.socket.is_none() {
+			self.peer_id = random_peer_id()?;
+
+			let socket = match self.room.clone() {
+				Room::RendezVous { rendezvous_folder, room_name, discovery_endpoint } => {
+					log_info!("Enabling broadcast room '{}' as member {:08x}", room_name, self.peer_id);
+					self.open_rendezvous_room(&rendezvous_folder, &room_name, discovery_endpoint)
+				},
+				Room::Network { source_port, destination_host, destination_port } => {
+					log_info!("Enabling broadcast room from port {} to {}:{} as member {:08x}", source_port, destination_host, destination_port, self.peer_id);
+					self.open_network_room(source_port, &destination_host, destination_port)
+				},
+			};
+
+			match socket {
+				Ok(socket) => {
+					self.socket = Some(socket);
+				},
+				Err(e) => {
+					self.disable();
+					return Err(e);
+				},
+			}
+		}
+		return Ok(());
+	}
+
+	fn disable(&mut self) {
+		if self.socket.is_some() {
+			log_info!("Disabling broadcast room");
+		}
+
+		self.socket = None;
+		self.rendezvous = None;
+		self.discovery = None;
+		self.local_members.clear();
+		self.remote_members.clear();
+		self.pending_frames.clear();
+	}
+
+	// Frames sent while alone in the room are queued, the oldest ones being dropped past MAXIMUM_PENDING_FRAMES
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		self.update_members()?;
+
+		if self.socket.is_none() {
+			return Err(String::from("Broadcast room is not enabled"));
+		}
+		else if self.members().is_empty() {
+			if self.pending_frames.len() >= MAXIMUM_PENDING_FRAMES {
+				self.pending_frames.pop_front();
+				self.statistics.record_dropped();
+			}
+			self.pending_frames.push_back(data.to_vec());
+			return Ok(());
+		}
+		return self.send_to_members(data);
+	}
+
+	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		return Ok(self.receive_from()?.map(|(_, data)| data));
+	}
+
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		if let Some((_, len)) = self.receive_datagram()? {
+			return Ok(Some(copy_frame(&self.datagram[HEADER_LEN..HEADER_LEN + len], buffer)?));
+		}
+		return Ok(None);
+	}
+
+	fn receive_from(&mut self) -> Result<Option<(PeerId, Vec<u8>)>, String> {
+		return Ok(self.receive_datagram()?.map(|(sender, len)| (sender, self.datagram[HEADER_LEN..HEADER_LEN + len].to_vec())));
+	}
+
+	fn state(&self) -> ConnectionState {
+		if self.socket.is_none() {
+			return ConnectionState::Disabled;
+		}
+		else if self.members().is_empty() {
+			return ConnectionState::WaitingForPeer;
+		}
+		return ConnectionState::Connected;
+	}
+}
+
+#[cfg(test)]
+mod tests;
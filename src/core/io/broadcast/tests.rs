@@ -0,0 +1,126 @@
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use super::{BroadcastBackend, BROADCAST_ROOM_SUFFIX};
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::discovery::DiscoveryEndpoint;
+use super::super::super::rendezvous::get_available_rooms;
+use super::super::super::{ConnectionState, IRCommunicationHandler, NetworkOptions, NetworkTransport, PeerId, UNKNOWN_PEER};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+const ROOM: &str = "room";
+
+fn test_folder(name: &str) -> PathBuf {
+	return env::temp_dir().join(format!("n3t1r-broadcast-test-{}-{}", process::id(), name));
+}
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+// LAN discovery on a loopback port of its own, so that members only meet through the rendez-vous folder
+fn rendezvous_member(folder: &Path) -> BroadcastBackend {
+	let discovery_endpoint = DiscoveryEndpoint { address: Ipv4Addr::LOCALHOST, port: free_udp_port().min(u16::MAX - 16) };
+	let mut backend = BroadcastBackend::rendezvous_with_discovery_endpoint(ROOM, folder, discovery_endpoint, StatisticsRecorder::new());
+	backend.enable().unwrap();
+	return backend;
+}
+
+fn receive_within(backend: &mut BroadcastBackend, timeout: Duration) -> Option<(PeerId, Vec<u8>)> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(frame) = backend.receive_from().unwrap() {
+			return Some(frame);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn frames_reach_every_other_member() {
+	let folder = test_folder("members");
+	let mut first = rendezvous_member(&folder);
+
+	// Delivered once another member joins
+	first.send(&[0x01]).unwrap();
+	assert_eq!(first.state(), ConnectionState::WaitingForPeer);
+	assert_eq!(first.pending_frames(), vec![vec![0x01]]);
+	assert_eq!(get_available_rooms(&folder).unwrap(), vec![format!("{}{}", ROOM, BROADCAST_ROOM_SUFFIX)]);
+
+	let mut second = rendezvous_member(&folder);
+	assert_eq!(receive_within(&mut first, TEST_TIMEOUT / 10), None);
+	assert_eq!(receive_within(&mut second, TEST_TIMEOUT), Some((first.peer_id, vec![0x01])));
+
+	let mut third = rendezvous_member(&folder);
+	third.send(&[0x02, 0x03]).unwrap();
+	assert_eq!(receive_within(&mut first, TEST_TIMEOUT), Some((third.peer_id, vec![0x02, 0x03])));
+	assert_eq!(receive_within(&mut second, TEST_TIMEOUT), Some((third.peer_id, vec![0x02, 0x03])));
+	assert_eq!(receive_within(&mut third, TEST_TIMEOUT / 10), None);
+
+	// Members that left are not sent to anymore
+	drop(second);
+	first.send(&[0x04]).unwrap();
+	assert_eq!(receive_within(&mut third, TEST_TIMEOUT), Some((first.peer_id, vec![0x04])));
+	assert_eq!(first.members().len(), 1);
+
+	drop((first, third));
+	let _ = fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn own_frames_looped_back_are_ignored() {
+	let (port, other_port) = (free_udp_port(), free_udp_port());
+	// Stands for a broadcast address, which every member listens on, including the sender
+	let mut member = BroadcastBackend::network(port, "127.0.0.1", port, StatisticsRecorder::new());
+	let mut other_member = BroadcastBackend::network(other_port, "127.0.0.1", port, StatisticsRecorder::new());
+	member.enable().unwrap();
+	other_member.enable().unwrap();
+	assert_eq!(member.state(), ConnectionState::Connected);
+
+	member.send(&[0x01]).unwrap();
+	other_member.send(&[0x02]).unwrap();
+	assert_eq!(receive_within(&mut member, TEST_TIMEOUT), Some((other_member.peer_id, vec![0x02])));
+	assert_eq!(receive_within(&mut member, TEST_TIMEOUT / 10), None);
+
+	// Not from a member
+	let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+	socket.send_to(&[0x00, 0x01], ("127.0.0.1", port)).unwrap();
+	socket.send_to(&[0x02, 0x01, 0x02, 0x03, 0x04, 0x05], ("127.0.0.1", port)).unwrap();
+	assert_eq!(receive_within(&mut member, TEST_TIMEOUT / 10), None);
+	assert_eq!(member.statistics.snapshot().framing_errors, 2);
+}
+
+#[test]
+fn handlers_report_the_sender() {
+	let (port, other_port) = (free_udp_port(), free_udp_port());
+	let options = NetworkOptions { broadcast: true, ..NetworkOptions::default() };
+	let (mut handler, mut other_handler) = (IRCommunicationHandler::new(), IRCommunicationHandler::new());
+	handler.select_network_backend(port, "127.0.0.1", other_port, &options).unwrap();
+	other_handler.select_network_backend(other_port, "127.0.0.1", port, &options).unwrap();
+	handler.enable().unwrap();
+	other_handler.enable().unwrap();
+
+	other_handler.send(&[0x42]).unwrap();
+	let start = Instant::now();
+	let mut frame = None;
+	while frame.is_none() && start.elapsed() < TEST_TIMEOUT {
+		frame = handler.receive_from().unwrap();
+	}
+	let (sender, data) = frame.unwrap();
+	assert_eq!(data, vec![0x42]);
+	assert_ne!(sender, UNKNOWN_PEER);
+
+	// Only plain UDP datagrams can be sent to every member at once
+	for options in [
+		NetworkOptions { transport: NetworkTransport::Tcp, ..options.clone() },
+		NetworkOptions { reliable: true, ..options.clone() },
+		NetworkOptions { passphrase: Some(String::from("passphrase")), ..options.clone() },
+	] {
+		assert!(handler.select_network_backend(port, "127.0.0.1", other_port, &options).is_err());
+	}
+}
@@ -0,0 +1,96 @@
+// Queue of received frames in a buffer allocated once: every frame is stored as its length byte followed by its data,
+// wrapping around at the end of the buffer, so that queuing and taking frames does not allocate.
+
+use super::super::MAXIMUM_DATA_LEN;
+
+pub struct FrameRing {
+	buffer: Box<[u8]>,
+	// Where the oldest frame starts
+	head: usize,
+	used: usize,
+	frames: usize,
+	capacity: usize,
+}
+
+impl FrameRing {
+	// Room for `capacity` frames of up to MAXIMUM_DATA_LEN bytes
+	pub fn new(capacity: usize) -> Self {
+		assert!(capacity > 0);
+		return Self { buffer: vec![0; capacity * (1 + MAXIMUM_DATA_LEN)].into_boxed_slice(), head: 0, used: 0, frames: 0, capacity };
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.frames == 0;
+	}
+
+	pub fn clear(&mut self) {
+		self.head = 0;
+		self.used = 0;
+		self.frames = 0;
+	}
+
+	fn write_at(&mut self, position: usize, data: &[u8]) {
+		let position = position % self.buffer.len();
+		let first_len = data.len().min(self.buffer.len() - position);
+		self.buffer[position..position + first_len].copy_from_slice(&data[..first_len]);
+		self.buffer[..data.len() - first_len].copy_from_slice(&data[first_len..]);
+	}
+
+	fn read_at(&self, position: usize, target: &mut [u8]) {
+		let position = position % self.buffer.len();
+		let first_len = target.len().min(self.buffer.len() - position);
+		let target_len = target.len();
+		target[..first_len].copy_from_slice(&self.buffer[position..position + first_len]);
+		target[first_len..].copy_from_slice(&self.buffer[..target_len - first_len]);
+	}
+
+	// Queues a copy of the frame, dropping the oldest one when full: returns whether one was dropped
+	pub fn push(&mut self, data: &[u8]) -> bool {
+		assert!(data.len() <= MAXIMUM_DATA_LEN);
+
+		let full = self.frames == self.capacity;
+		if full {
+			self.skip_front();
+		}
+
+		let tail = self.head + self.used;
+		self.write_at(tail, &[data.len() as u8]);
+		self.write_at(tail + 1, data);
+		self.used += 1 + data.len();
+		self.frames += 1;
+		return full;
+	}
+
+	// Length of the oldest frame
+	pub fn front_len(&self) -> Option<usize> {
+		if self.is_empty() {
+			return None;
+		}
+		return Some(self.buffer[self.head] as usize);
+	}
+
+	fn skip_front(&mut self) {
+		if let Some(len) = self.front_len() {
+			self.head = (self.head + 1 + len) % self.buffer.len();
+			self.used -= 1 + len;
+			self.frames -= 1;
+		}
+	}
+
+	// Takes the oldest frame into `buffer`, which must be at least front_len() bytes long
+	pub fn pop_into(&mut self, buffer: &mut [u8]) -> Option<usize> {
+		let len = self.front_len()?;
+		self.read_at(self.head + 1, &mut buffer[..len]);
+		self.skip_front();
+		return Some(len);
+	}
+
+	pub fn pop(&mut self) -> Option<Vec<u8>> {
+		let mut data = vec![0; self.front_len()?];
+		self.pop_into(&mut data);
+		return Some(data);
+	}
+}
+
+#[cfg(test)]
+mod tests;
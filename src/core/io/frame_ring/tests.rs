@@ -0,0 +1,47 @@
+use super::FrameRing;
+use super::super::super::MAXIMUM_DATA_LEN;
+
+#[test]
+fn frames_come_out_in_order() {
+	let mut ring = FrameRing::new(4);
+	assert_eq!(ring.pop(), None);
+
+	assert!(!ring.push(&[0x01]));
+	assert!(!ring.push(&[0x02, 0x03]));
+	assert!(!ring.push(&[]));
+	assert_eq!(ring.front_len(), Some(1));
+
+	let mut buffer = [0u8; MAXIMUM_DATA_LEN];
+	assert_eq!(ring.pop_into(&mut buffer), Some(1));
+	assert_eq!(buffer[0], 0x01);
+	assert_eq!(ring.pop(), Some(vec![0x02, 0x03]));
+	assert_eq!(ring.pop(), Some(vec![]));
+	assert!(ring.is_empty());
+}
+
+#[test]
+fn frames_wrap_around_the_buffer() {
+	let mut ring = FrameRing::new(2);
+	let frames: Vec<Vec<u8>> = (0..16u8).map(|i| vec![i; MAXIMUM_DATA_LEN - i as usize * 7]).collect();
+
+	// Never aligned on the end of the buffer
+	for frame in &frames {
+		assert!(!ring.push(frame));
+		assert_eq!(ring.pop().as_ref(), Some(frame));
+	}
+	assert!(ring.is_empty());
+}
+
+#[test]
+fn oldest_frames_are_dropped_when_full() {
+	let mut ring = FrameRing::new(2);
+	assert!(!ring.push(&[0x01; MAXIMUM_DATA_LEN]));
+	assert!(!ring.push(&[0x02]));
+	assert!(ring.push(&[0x03; MAXIMUM_DATA_LEN]));
+	assert_eq!(ring.pop(), Some(vec![0x02]));
+	assert_eq!(ring.pop(), Some(vec![0x03; MAXIMUM_DATA_LEN]));
+
+	ring.push(&[0x04]);
+	ring.clear();
+	assert_eq!(ring.front_len(), None);
+}
@@ -1,34 +1,401 @@
-use super::super::traits::IOHandler;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Instant;
+
+use super::super::traits::{copy_frame, IOHandler};
+use super::super::statistics::StatisticsRecorder;
+use super::super::ConnectionState;
+use super::super::MAXIMUM_DATA_LEN;
+
+mod secure;
+use self::secure::{SecureChannel, SECURE_OVERHEAD};
+
+mod reliable;
+use self::reliable::{ReliableChannel, RELIABLE_OVERHEAD};
+
+mod sequence;
+use self::sequence::{SequenceChannel, SEQUENCE_OVERHEAD};
+
+mod stream;
+pub use self::stream::StreamLink;
+
+mod websocket;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetworkTransport {
+	#[default]
+	Udp,
+	Tcp,
+	WebSocket,
+}
+
+// Only meaningful for TCP and WebSocket: clients connect to the destination, servers listen on the source port
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetworkRole {
+	#[default]
+	Client,
+	Server,
+}
+
+// Both peers must use the same options, but opposite roles
+#[derive(Clone, Default)]
+pub struct NetworkOptions {
+	// Enables the secure mode
+	pub passphrase: Option<String>,
+	// Enables the reliable mode: lost frames are retransmitted, and frames are delivered once and in order
+	pub reliable: bool,
+	pub transport: NetworkTransport,
+	pub role: NetworkRole,
+	// Joins a broadcast room through the destination, e.g. a broadcast or multicast address, instead of talking to a single peer
+	pub broadcast: bool,
+}
+
+enum Link {
+	Udp(UdpSocket, SocketAddr),
+	Stream(StreamLink),
+}
+
+impl Link {
+	fn send(&mut self, packet: &[u8]) -> Result<(), String> {
+		match self {
+			Self::Udp(socket, destination) => {
+				if let Err(e) = socket.send_to(packet, *destination) {
+					return Err(format!("Fail on send_to: {}", e));
+				}
+				return Ok(());
+			},
+			Self::Stream(stream_link) => {
+				return stream_link.send(packet);
+			},
+		}
+	}
+
+	fn receive(&mut self, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+		return match self {
+			Self::Udp(socket, destination) => { receive_datagram(socket, destination, maximum_len, statistics) },
+			Self::Stream(stream_link) => { stream_link.receive(maximum_len, statistics) },
+		};
+	}
+
+	fn state(&self) -> ConnectionState {
+		return match self {
+			Self::Udp(_, _) => { ConnectionState::Connected },
+			Self::Stream(stream_link) => { stream_link.state() },
+		};
+	}
+}
 
 pub struct NetworkBackend {
 	source_port: u16,
 	destination_host: String,
 	destination_port: u16,
+	options: NetworkOptions,
+	link: Option<Link>,
+	// Created on the first enable, as deriving the master key is deliberately slow
+	secure_channel: Option<SecureChannel>,
+	reliable_channel: Option<ReliableChannel>,
+	// Best-effort UDP only: streams do not lose frames, and the reliable mode numbers its own
+	sequence_channel: Option<SequenceChannel>,
+	// UDP has no connection: the peer is only known to be there once something came from it
+	peer_heard: bool,
+	statistics: StatisticsRecorder,
 }
 
 impl NetworkBackend {
-	pub fn new(source_port: u16, destination_host: &str, destination_port: u16) -> Self {
-		return Self { source_port, destination_host: destination_host.to_owned(), destination_port };
+	pub fn new(source_port: u16, destination_host: &str, destination_port: u16, options: &NetworkOptions, statistics: StatisticsRecorder) -> Self {
+		return Self {
+			source_port,
+			destination_host: destination_host.to_owned(),
+			destination_port,
+			options: options.clone(),
+			link: None,
+			secure_channel: None,
+			reliable_channel: None,
+			sequence_channel: None,
+			peer_heard: false,
+			statistics,
+		};
+	}
+
+	fn resolve_destination(&self) -> Result<SocketAddr, String> {
+		match (self.destination_host.as_str(), self.destination_port).to_socket_addrs() {
+			Ok(mut addresses) => {
+				return addresses.next().ok_or(format!("No address found for '{}'", self.destination_host));
+			},
+			Err(e) => {
+				return Err(format!("Fail on resolve '{}': {}", self.destination_host, e));
+			},
+		}
+	}
+
+	fn open_link(&self) -> Result<Link, String> {
+		if self.options.transport == NetworkTransport::Udp {
+			let destination = self.resolve_destination()?;
+			return Ok(Link::Udp(bind_udp_socket(self.source_port, &destination)?, destination));
+		}
+		return Ok(Link::Stream(StreamLink::new(self.options.transport, self.options.role, self.source_port, &self.destination_host, self.destination_port)?));
+	}
+
+	fn maximum_packet_len(&self) -> usize {
+		if self.reliable_channel.is_some() {
+			return MAXIMUM_DATA_LEN + RELIABLE_OVERHEAD;
+		}
+		else if self.sequence_channel.is_some() {
+			return MAXIMUM_DATA_LEN + SEQUENCE_OVERHEAD;
+		}
+		return MAXIMUM_DATA_LEN;
+	}
+}
+
+// Binds a non-blocking UDP socket of the same address family as `peer`
+pub fn bind_udp_socket(port: u16, peer: &SocketAddr) -> Result<UdpSocket, String> {
+	let local_address: SocketAddr = match peer {
+		SocketAddr::V4(_) => { (std::net::Ipv4Addr::UNSPECIFIED, port).into() },
+		SocketAddr::V6(_) => { (std::net::Ipv6Addr::UNSPECIFIED, port).into() },
+	};
+
+	let socket = UdpSocket::bind(local_address).map_err(|e| format!("Fail on bind udp:{}: {}", port, e))?;
+	socket.set_nonblocking(true).map_err(|e| format!("Fail on set_nonblocking: {}", e))?;
+	return Ok(socket);
+}
+
+// Receives one datagram from `peer`, datagrams from anybody else are dropped
+pub fn receive_datagram(socket: &UdpSocket, peer: &SocketAddr, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+	// One extra byte to detect datagrams that are too long
+	let mut buffer: Vec<u8> = vec![0; maximum_len + 1];
+
+	if let Some(len) = receive_datagram_in(socket, peer, &mut buffer, statistics)? {
+		buffer.truncate(len);
+		return Ok(Some(buffer));
 	}
+	return Ok(None);
+}
+
+// Same as receive_datagram for frames, into `buffer`: straight into it when it has room for the extra byte
+pub fn receive_datagram_into(socket: &UdpSocket, peer: &SocketAddr, buffer: &mut [u8], statistics: &StatisticsRecorder) -> Result<Option<usize>, String> {
+	if buffer.len() > MAXIMUM_DATA_LEN {
+		return receive_datagram_in(socket, peer, &mut buffer[..MAXIMUM_DATA_LEN + 1], statistics);
+	}
+
+	let mut datagram = [0u8; MAXIMUM_DATA_LEN + 1];
+	if let Some(len) = receive_datagram_in(socket, peer, &mut datagram, statistics)? {
+		return Ok(Some(copy_frame(&datagram[..len], buffer)?));
+	}
+	return Ok(None);
+}
+
+// Datagrams filling the whole buffer are too long
+fn receive_datagram_in(socket: &UdpSocket, peer: &SocketAddr, buffer: &mut [u8], statistics: &StatisticsRecorder) -> Result<Option<usize>, String> {
+	loop {
+		match socket.recv_from(buffer) {
+			Ok((len, source)) => {
+				if source.port() != peer.port() || source.ip().to_canonical() != peer.ip().to_canonical() {
+					statistics.record_dropped();
+				}
+				else if len >= buffer.len() {
+					statistics.record_framing_error();
+				}
+				else {
+					return Ok(Some(len));
+				}
+			},
+			Err(e) if e.kind() == ErrorKind::WouldBlock => {
+				return Ok(None);
+			},
+			// Windows reports ICMP port unreachable from a previous send_to as an error on the next recv_from
+			Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+				continue;
+			},
+			Err(e) => {
+				return Err(format!("Fail on recv_from: {}", e));
+			},
+		}
+	}
+}
+
+// Packets are encrypted in secure mode, once the handshake started a session
+fn send_packet(link: &mut Link, secure_channel: &mut Option<SecureChannel>, packet: &[u8]) -> Result<(), String> {
+	return match secure_channel {
+		Some(secure_channel) => {
+			secure_channel.send(packet);
+			secure_channel.poll(Instant::now());
+			send_secure_packets(link, secure_channel)
+		},
+		None => { link.send(packet) },
+	};
+}
+
+fn send_secure_packets(link: &mut Link, secure_channel: &mut SecureChannel) -> Result<(), String> {
+	while let Some(packet) = secure_channel.take_packet() {
+		link.send(&packet)?;
+	}
+	return Ok(());
+}
+
+fn receive_packet(link: &mut Link, secure_channel: &mut Option<SecureChannel>, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+	if let Some(secure_channel) = secure_channel {
+		let now = Instant::now();
+		let mut packet = None;
+		// Handshakes and unauthenticated datagrams are skipped, so that they can not hide the frames behind them
+		while let Some(datagram) = link.receive(maximum_len + SECURE_OVERHEAD, statistics)? {
+			packet = secure_channel.open(&datagram, now)?;
+			if packet.is_some() {
+				break;
+			}
+		}
+
+		// Handshake answers, and the frames that waited for the session
+		secure_channel.poll(now);
+		if link.state() == ConnectionState::Connected {
+			send_secure_packets(link, secure_channel)?;
+		}
+		return Ok(packet);
+	}
+	return link.receive(maximum_len, statistics);
+}
+
+fn send_reliable_packets(link: &mut Link, secure_channel: &mut Option<SecureChannel>, reliable_channel: &mut ReliableChannel) -> Result<(), String> {
+	while let Some(packet) = reliable_channel.take_packet() {
+		send_packet(link, secure_channel, &packet)?;
+	}
+	return Ok(());
 }
 
 impl IOHandler for NetworkBackend {
 	fn enable(&mut self) -> Result<(), String> {
-		println!("Enabling network protocol from udp:{} to {}:{}", self.source_port, self.destination_host, self.destination_port);
+		if self.link.is_none() {
+			let mode = if self.options.passphrase.is_some() { "secure" } else { "plain" };
+			let delivery = if self.options.reliable { "reliable" } else { "best-effort" };
+			log_info!(
+				"Enabling {} {} {:?} network protocol as {:?} from port {} to {}:{}",
+				mode, delivery, self.options.transport, self.options.role, self.source_port, self.destination_host, self.destination_port,
+			);
+
+			// The peer has to go through a new handshake with us, but the master key is kept
+			match (&self.options.passphrase, &mut self.secure_channel) {
+				(_, Some(secure_channel)) => {
+					secure_channel.restart()?;
+				},
+				(Some(passphrase), None) => {
+					self.secure_channel = Some(SecureChannel::new(passphrase, self.statistics.clone())?);
+				},
+				(None, None) => {},
+			}
+
+			self.link = Some(self.open_link()?);
+			self.peer_heard = false;
+
+			// A new channel every time, so that the peer knows to start over
+			if self.options.reliable {
+				self.reliable_channel = Some(ReliableChannel::new(self.statistics.clone())?);
+			}
+			else if self.options.transport == NetworkTransport::Udp {
+				self.sequence_channel = Some(SequenceChannel::new(self.statistics.clone())?);
+			}
+		}
 		return Ok(());
 	}
 
 	fn disable(&mut self) {
-		println!("Disabling network protocol");
+		if self.link.is_some() {
+			log_info!("Disabling network protocol");
+
+			self.link = None;
+			self.reliable_channel = None;
+			self.sequence_channel = None;
+		}
 	}
 
 	fn send(&mut self, data: &[u8]) -> Result<(), String> {
-		println!("Sending via network to '{}:{}': {:?}", self.destination_host, self.destination_port, data);
-		return Ok(());
+		if let Some(link) = &mut self.link {
+			if let Some(reliable_channel) = &mut self.reliable_channel {
+				if link.state() != ConnectionState::Connected {
+					return Err(format!("Cannot send while {:?}", link.state()));
+				}
+
+				let now = Instant::now();
+				reliable_channel.send(data, now)?;
+				reliable_channel.poll(now);
+				return send_reliable_packets(link, &mut self.secure_channel, reliable_channel);
+			}
+			if let Some(sequence_channel) = &mut self.sequence_channel {
+				return send_packet(link, &mut self.secure_channel, &sequence_channel.number(data));
+			}
+			return send_packet(link, &mut self.secure_channel, data);
+		}
+		return Err(String::from("Network protocol is not enabled"));
 	}
 
 	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
-		println!("Trying to receive via network from '{}:{}'...", self.destination_host, self.destination_port);
-		return Ok(Some(format!("Hello back from '{}:{}'", self.destination_host, self.destination_port).as_bytes().to_vec()));
+		let maximum_len = self.maximum_packet_len();
+
+		if let Some(link) = &mut self.link {
+			if let Some(reliable_channel) = &mut self.reliable_channel {
+				let now = Instant::now();
+				while let Some(packet) = receive_packet(link, &mut self.secure_channel, maximum_len, &self.statistics)? {
+					self.peer_heard = true;
+					reliable_channel.handle_packet(&packet, now);
+				}
+				reliable_channel.poll(now);
+				// Only once connected, retransmissions take care of what could not be sent before
+				if link.state() == ConnectionState::Connected {
+					send_reliable_packets(link, &mut self.secure_channel, reliable_channel)?;
+				}
+				return Ok(reliable_channel.take_frame());
+			}
+
+			// Late packets are skipped, so that they can not hide the next ones behind them
+			while let Some(packet) = receive_packet(link, &mut self.secure_channel, maximum_len, &self.statistics)? {
+				self.peer_heard = true;
+				match &mut self.sequence_channel {
+					Some(sequence_channel) => {
+						if let Some(frame) = sequence_channel.check(&packet) {
+							return Ok(Some(frame.to_vec()));
+						}
+					},
+					None => {
+						return Ok(Some(packet));
+					},
+				}
+			}
+		}
+		return Ok(None);
+	}
+
+	// Plain UDP frames are received without any allocation
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		if let (Some(Link::Udp(socket, destination)), None, Some(sequence_channel)) = (&self.link, &self.secure_channel, &mut self.sequence_channel) {
+			// One extra byte to detect datagrams that are too long
+			let mut datagram = [0u8; SEQUENCE_OVERHEAD + MAXIMUM_DATA_LEN + 1];
+			while let Some(len) = receive_datagram_in(socket, destination, &mut datagram, &self.statistics)? {
+				self.peer_heard = true;
+				if let Some(frame) = sequence_channel.check(&datagram[..len]) {
+					return Ok(Some(copy_frame(frame, buffer)?));
+				}
+			}
+			return Ok(None);
+		}
+
+		return match self.receive()? {
+			Some(data) => { Ok(Some(copy_frame(&data, buffer)?)) },
+			None => { Ok(None) },
+		};
+	}
+
+	fn state(&self) -> ConnectionState {
+		return match &self.link {
+			Some(Link::Udp(_, _)) if !self.peer_heard && self.secure_channel.is_none() => { ConnectionState::WaitingForPeer },
+			// Secure peers are only there once the handshake started a session
+			Some(link) if link.state() == ConnectionState::Connected && self.secure_channel.as_ref().is_some_and(|secure_channel| !secure_channel.is_established()) => {
+				ConnectionState::WaitingForPeer
+			},
+			Some(link) => { link.state() },
+			None => { ConnectionState::Disabled },
+		};
 	}
 }
+
+#[cfg(test)]
+mod tests;
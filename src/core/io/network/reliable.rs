@@ -0,0 +1,251 @@
+// Reliable mode of the network backend: frames are numbered, acknowledged and retransmitted until acknowledged,
+// duplicates are suppressed and frames are delivered in order, one packet per frame.
+//
+// DATA packet: kind (1) | sender epoch (4) | sequence number (8, LE) | oldest unacknowledged sequence number (8, LE) | frame
+// ACK packet:  kind (1) | acknowledged epoch (4) | next expected sequence number (8, LE) | selective acks (4, LE)
+// Bit i of the selective acks is set when next expected + 1 + i was received out of order.
+// The epoch is picked at random by every sender, so that a restarted peer is not mistaken for a replay.
+// The oldest unacknowledged sequence number tells the receiver which frames it should not wait for anymore.
+//
+// Nothing happens between calls: retransmissions are only sent from within send() and receive().
+// A frame that could not be delivered within MAXIMUM_DELIVERY_DELAY is given up by the sender,
+// and skipped by the receiver, so that a single lost frame can not stall the link.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::super::super::statistics::StatisticsRecorder;
+
+const DATA_PACKET: u8 = 0;
+const ACK_PACKET: u8 = 1;
+const DATA_HEADER_LEN: usize = 1 + 4 + 8 + 8;
+const ACK_PACKET_LEN: usize = 1 + 4 + 8 + 4;
+pub const RELIABLE_OVERHEAD: usize = DATA_HEADER_LEN;
+
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(30);
+const MAXIMUM_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+pub const MAXIMUM_DELIVERY_DELAY: Duration = Duration::from_secs(1);
+// Frames sent but not acknowledged yet, and frames received ahead of a missing one
+const MAXIMUM_IN_FLIGHT: u64 = 64;
+
+struct PendingFrame {
+	packet: Vec<u8>,
+	first_sent: Instant,
+	last_sent: Instant,
+	retransmit_timeout: Duration,
+}
+
+pub struct ReliableChannel {
+	epoch: u32,
+	next_sequence: u64,
+	unacknowledged: BTreeMap<u64, PendingFrame>,
+
+	peer_epoch: Option<u32>,
+	next_expected: u64,
+	out_of_order: BTreeMap<u64, Vec<u8>>,
+	// When the frame at next_expected started to be waited for, while later ones were already received
+	gap_since: Option<Instant>,
+
+	outgoing_packets: VecDeque<Vec<u8>>,
+	received_frames: VecDeque<Vec<u8>>,
+	statistics: StatisticsRecorder,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+	return u32::from_le_bytes(bytes[..4].try_into().unwrap());
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+	return u64::from_le_bytes(bytes[..8].try_into().unwrap());
+}
+
+impl ReliableChannel {
+	pub fn new(statistics: StatisticsRecorder) -> Result<Self, String> {
+		let mut epoch = [0u8; 4];
+		getrandom::fill(&mut epoch).map_err(|e| format!("Fail on getrandom: {}", e))?;
+
+		return Ok(Self {
+			epoch: u32::from_le_bytes(epoch),
+			next_sequence: 0,
+			unacknowledged: BTreeMap::new(),
+			peer_epoch: None,
+			next_expected: 0,
+			out_of_order: BTreeMap::new(),
+			gap_since: None,
+			outgoing_packets: VecDeque::new(),
+			received_frames: VecDeque::new(),
+			statistics,
+		});
+	}
+
+	pub fn send(&mut self, data: &[u8], now: Instant) -> Result<(), String> {
+		if self.unacknowledged.len() as u64 >= MAXIMUM_IN_FLIGHT {
+			return Err(format!("Too many frames awaiting acknowledgement ({})", self.unacknowledged.len()));
+		}
+
+		let sequence = self.next_sequence;
+		self.next_sequence += 1;
+
+		let mut packet = Vec::with_capacity(DATA_HEADER_LEN + data.len());
+		packet.push(DATA_PACKET);
+		packet.extend_from_slice(&self.epoch.to_le_bytes());
+		packet.extend_from_slice(&sequence.to_le_bytes());
+		packet.extend_from_slice(&[0; 8]);
+		packet.extend_from_slice(data);
+
+		self.unacknowledged.insert(sequence, PendingFrame { packet, first_sent: now, last_sent: now, retransmit_timeout: INITIAL_RETRANSMIT_TIMEOUT });
+		self.queue_data(sequence);
+		return Ok(());
+	}
+
+	// The oldest unacknowledged sequence number is refreshed on every (re)transmission
+	fn queue_data(&mut self, sequence: u64) {
+		let oldest_unacknowledged = *self.unacknowledged.keys().next().unwrap();
+		let pending_frame = self.unacknowledged.get_mut(&sequence).unwrap();
+
+		pending_frame.packet[13..21].copy_from_slice(&oldest_unacknowledged.to_le_bytes());
+		self.outgoing_packets.push_back(pending_frame.packet.clone());
+	}
+
+	pub fn handle_packet(&mut self, packet: &[u8], now: Instant) {
+		match packet.first() {
+			Some(&DATA_PACKET) if packet.len() >= DATA_HEADER_LEN => {
+				self.handle_data(read_u32(&packet[1..]), read_u64(&packet[5..]), read_u64(&packet[13..]), &packet[DATA_HEADER_LEN..], now);
+			},
+			Some(&ACK_PACKET) if packet.len() == ACK_PACKET_LEN => {
+				self.handle_ack(read_u32(&packet[1..]), read_u64(&packet[5..]), read_u32(&packet[13..]));
+			},
+			_ => {
+				self.statistics.record_framing_error();
+			},
+		}
+	}
+
+	fn handle_data(&mut self, epoch: u32, sequence: u64, oldest_unacknowledged: u64, data: &[u8], now: Instant) {
+		if self.peer_epoch != Some(epoch) {
+			if self.peer_epoch.is_some() {
+				log_info!("Reliable peer restarted, resetting the receive sequence");
+			}
+			// Whatever the peer sent before we listened is not waited for
+			self.peer_epoch = Some(epoch);
+			self.next_expected = oldest_unacknowledged;
+			self.out_of_order.clear();
+			self.gap_since = None;
+		}
+		else if oldest_unacknowledged > self.next_expected {
+			self.skip_to(oldest_unacknowledged, now);
+		}
+
+		if sequence == self.next_expected {
+			self.received_frames.push_back(data.to_vec());
+			self.next_expected += 1;
+			self.deliver_in_order(now);
+		}
+		// Anything older or already buffered is a duplicate, only acknowledged again
+		else if sequence > self.next_expected && sequence < self.next_expected + MAXIMUM_IN_FLIGHT && !self.out_of_order.contains_key(&sequence) {
+			self.out_of_order.insert(sequence, data.to_vec());
+			if self.gap_since.is_none() {
+				self.gap_since = Some(now);
+			}
+		}
+
+		self.queue_ack(epoch);
+	}
+
+	fn deliver_in_order(&mut self, now: Instant) {
+		while let Some(data) = self.out_of_order.remove(&self.next_expected) {
+			self.received_frames.push_back(data);
+			self.next_expected += 1;
+		}
+		self.gap_since = if self.out_of_order.is_empty() { None } else { Some(now) };
+	}
+
+	// Stops waiting for the missing frames before `sequence`, the ones already received are delivered
+	fn skip_to(&mut self, sequence: u64, now: Instant) {
+		let mut dropped_frames = 0;
+		while self.next_expected < sequence {
+			match self.out_of_order.remove(&self.next_expected) {
+				Some(data) => { self.received_frames.push_back(data); },
+				None => { dropped_frames += 1; },
+			}
+			self.next_expected += 1;
+		}
+
+		self.statistics.record_lost_frames(dropped_frames);
+		self.deliver_in_order(now);
+	}
+
+	fn queue_ack(&mut self, epoch: u32) {
+		let mut selective_acks = 0u32;
+		for sequence in self.out_of_order.keys() {
+			let offset = sequence - self.next_expected - 1;
+			if offset < 32 {
+				selective_acks |= 1 << offset;
+			}
+		}
+
+		let mut packet = Vec::with_capacity(ACK_PACKET_LEN);
+		packet.push(ACK_PACKET);
+		packet.extend_from_slice(&epoch.to_le_bytes());
+		packet.extend_from_slice(&self.next_expected.to_le_bytes());
+		packet.extend_from_slice(&selective_acks.to_le_bytes());
+		self.outgoing_packets.push_back(packet);
+	}
+
+	fn handle_ack(&mut self, epoch: u32, next_expected: u64, selective_acks: u32) {
+		// Acknowledgements of a previous epoch of ours are stale
+		if epoch != self.epoch {
+			return;
+		}
+
+		self.unacknowledged.retain(|&sequence, _| {
+			if sequence < next_expected {
+				return false;
+			}
+
+			let offset = sequence - next_expected;
+			return !(1..=32).contains(&offset) || selective_acks & (1 << (offset - 1)) == 0;
+		});
+	}
+
+	// Retransmits the frames whose acknowledgement is late, and gives up on the ones that are too late
+	pub fn poll(&mut self, now: Instant) {
+		let statistics = &self.statistics;
+		self.unacknowledged.retain(|_, pending_frame| {
+			if now.duration_since(pending_frame.first_sent) >= MAXIMUM_DELIVERY_DELAY {
+				statistics.record_dropped();
+				return false;
+			}
+			return true;
+		});
+
+		let late_sequences: Vec<u64> = self.unacknowledged.iter()
+			.filter(|(_, pending_frame)| now.duration_since(pending_frame.last_sent) >= pending_frame.retransmit_timeout)
+			.map(|(&sequence, _)| sequence)
+			.collect();
+		for sequence in late_sequences {
+			let pending_frame = self.unacknowledged.get_mut(&sequence).unwrap();
+			pending_frame.last_sent = now;
+			pending_frame.retransmit_timeout = Duration::min(pending_frame.retransmit_timeout * 2, MAXIMUM_RETRANSMIT_TIMEOUT);
+
+			self.queue_data(sequence);
+			self.statistics.record_retransmission();
+		}
+
+		if let Some(gap_since) = self.gap_since {
+			if now.duration_since(gap_since) >= MAXIMUM_DELIVERY_DELAY {
+				// The sender gave up on the missing frames by now
+				let next_received = *self.out_of_order.keys().next().unwrap();
+				self.skip_to(next_received, now);
+			}
+		}
+	}
+
+	pub fn take_packet(&mut self) -> Option<Vec<u8>> {
+		return self.outgoing_packets.pop_front();
+	}
+
+	pub fn take_frame(&mut self) -> Option<Vec<u8>> {
+		return self.received_frames.pop_front();
+	}
+}
@@ -0,0 +1,321 @@
+// Secure mode of the network backend: frames are encrypted and authenticated with ChaCha20-Poly1305,
+// under keys derived from a passphrase shared by both peers.
+//
+// The passphrase is stretched once into a master key. Sessions then start with a handshake, authenticated with
+// HMAC-SHA-256 under a key derived from the master key:
+//   hello:   version (1) | kind (1) | challenge (8) | tag (16)
+//   reply:   version (1) | kind (1) | challenge (8) | echoed challenge (8) | tag (16)
+//   confirm: version (1) | kind (1) | challenge (8) | echoed challenge (8) | tag (16)
+// A hello is answered by a reply echoing its challenge, and a reply by a confirm echoing the challenge of the reply.
+// Either side starts a session once its own challenge comes back. Challenges are random and replaced every time
+// they started a session, so that handshakes and frames recorded earlier can not replace the current session.
+//
+// Frame: version (1) | kind (1) | sender challenge (8) | counter (8, LE) | ciphertext | tag (16)
+// Each direction of a session has its own key, derived with HKDF from the master key and both challenges:
+// the counter alone is a unique nonce, and replayed frames are detected for as long as the session lives.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chacha20poly1305::{AeadInOut, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::super::super::statistics::StatisticsRecorder;
+
+const VERSION: u8 = 1;
+const HELLO: u8 = 0;
+const REPLY: u8 = 1;
+const CONFIRM: u8 = 2;
+const FRAME: u8 = 3;
+
+const CHALLENGE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+const HELLO_LEN: usize = 2 + CHALLENGE_LEN + TAG_LEN;
+const ANSWER_LEN: usize = 2 + 2 * CHALLENGE_LEN + TAG_LEN;
+const FRAME_HEADER_LEN: usize = 2 + CHALLENGE_LEN + 8;
+pub const SECURE_OVERHEAD: usize = FRAME_HEADER_LEN + TAG_LEN;
+
+const MASTER_KEY_SALT: &[u8] = b"n3t1r network master key";
+const MASTER_KEY_ITERATIONS: u32 = 50_000;
+const HANDSHAKE_KEY_INFO: &[u8] = b"n3t1r handshake key";
+const FRAME_KEY_INFO: &[u8] = b"n3t1r frame key";
+
+// Hellos are repeated that often until a session starts, and sent at most that often for frames of unknown sessions
+pub(super) const HANDSHAKE_INTERVAL: Duration = Duration::from_millis(250);
+// Frames sent before the session starts wait for it, the oldest are dropped past that many
+const MAXIMUM_PENDING_FRAMES: usize = 64;
+// Frames reordered by at most this many positions are still accepted
+const REPLAY_WINDOW_LEN: u64 = 64;
+
+type Challenge = [u8; CHALLENGE_LEN];
+
+struct Session {
+	own_challenge: Challenge,
+	peer_challenge: Challenge,
+	sealing_cipher: ChaCha20Poly1305,
+	opening_cipher: ChaCha20Poly1305,
+	next_counter: u64,
+	highest_counter: u64,
+	// Bit i is set when highest_counter - i has been received
+	received: u64,
+}
+
+impl Session {
+	fn new(master_key: &Key, own_challenge: Challenge, peer_challenge: Challenge) -> Self {
+		return Self {
+			own_challenge,
+			peer_challenge,
+			sealing_cipher: frame_cipher(master_key, &own_challenge, &peer_challenge),
+			opening_cipher: frame_cipher(master_key, &peer_challenge, &own_challenge),
+			next_counter: 0,
+			highest_counter: 0,
+			received: 0,
+		};
+	}
+
+	fn accepts(&self, counter: u64) -> bool {
+		if counter > self.highest_counter {
+			return true;
+		}
+
+		let age = self.highest_counter - counter;
+		return age < REPLAY_WINDOW_LEN && self.received & (1 << age) == 0;
+	}
+
+	fn record(&mut self, counter: u64) {
+		if counter > self.highest_counter {
+			let shift = counter - self.highest_counter;
+			self.received = if shift < REPLAY_WINDOW_LEN { (self.received << shift) | 1 } else { 1 };
+			self.highest_counter = counter;
+		}
+		else {
+			self.received |= 1 << (self.highest_counter - counter);
+		}
+	}
+
+	fn seal(&mut self, data: &[u8]) -> Vec<u8> {
+		let counter = self.next_counter;
+		self.next_counter += 1;
+
+		let mut datagram = Vec::with_capacity(SECURE_OVERHEAD + data.len());
+		datagram.extend_from_slice(&[VERSION, FRAME]);
+		datagram.extend_from_slice(&self.own_challenge);
+		datagram.extend_from_slice(&counter.to_le_bytes());
+		datagram.extend_from_slice(data);
+
+		let (header, payload) = datagram.split_at_mut(FRAME_HEADER_LEN);
+		// Only fails past 256 GiB of payload
+		let tag = self.sealing_cipher.encrypt_inout_detached(&nonce(counter), header, payload.into()).unwrap();
+		datagram.extend_from_slice(&tag);
+		return datagram;
+	}
+}
+
+// The key of the frames sent by `sender` to `receiver`
+fn frame_cipher(master_key: &Key, sender: &Challenge, receiver: &Challenge) -> ChaCha20Poly1305 {
+	let mut key = Key::default();
+	// Only fails for keys longer than 255 hashes
+	Hkdf::<Sha256>::new(Some(&[*sender, *receiver].concat()), master_key).expand(FRAME_KEY_INFO, &mut key).unwrap();
+	return ChaCha20Poly1305::new(&key);
+}
+
+fn nonce(counter: u64) -> Nonce {
+	let mut nonce = Nonce::default();
+	nonce[4..].copy_from_slice(&counter.to_le_bytes());
+	return nonce;
+}
+
+fn random_challenge() -> Result<Challenge, String> {
+	let mut challenge: Challenge = [0; CHALLENGE_LEN];
+	getrandom::fill(&mut challenge).map_err(|e| format!("Fail on getrandom: {}", e))?;
+	return Ok(challenge);
+}
+
+pub struct SecureChannel {
+	master_key: Key,
+	handshake_key: Key,
+	// Sent in hellos and replies, replaced once it started a session
+	challenge: Challenge,
+	session: Option<Session>,
+	pending_frames: VecDeque<Vec<u8>>,
+	outgoing_packets: VecDeque<Vec<u8>>,
+	last_hello: Option<Instant>,
+	statistics: StatisticsRecorder,
+}
+
+impl SecureChannel {
+	pub fn new(passphrase: &str, statistics: StatisticsRecorder) -> Result<Self, String> {
+		let mut master_key = Key::default();
+		pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), MASTER_KEY_SALT, MASTER_KEY_ITERATIONS, &mut master_key);
+		let mut handshake_key = Key::default();
+		Hkdf::<Sha256>::new(None, &master_key).expand(HANDSHAKE_KEY_INFO, &mut handshake_key).unwrap();
+
+		return Ok(Self {
+			master_key,
+			handshake_key,
+			challenge: random_challenge()?,
+			session: None,
+			pending_frames: VecDeque::new(),
+			outgoing_packets: VecDeque::new(),
+			last_hello: None,
+			statistics,
+		});
+	}
+
+	// Drops the session, so that the next one starts with a handshake: the master key is kept
+	pub fn restart(&mut self) -> Result<(), String> {
+		self.challenge = random_challenge()?;
+		self.session = None;
+		self.pending_frames.clear();
+		self.outgoing_packets.clear();
+		self.last_hello = None;
+		return Ok(());
+	}
+
+	pub fn is_established(&self) -> bool {
+		return self.session.is_some();
+	}
+
+	// The frame waits for the session if there is none yet
+	pub fn send(&mut self, data: &[u8]) {
+		if let Some(session) = &mut self.session {
+			self.outgoing_packets.push_back(session.seal(data));
+			return;
+		}
+
+		if self.pending_frames.len() >= MAXIMUM_PENDING_FRAMES {
+			self.pending_frames.pop_front();
+			self.statistics.record_dropped();
+		}
+		self.pending_frames.push_back(data.to_vec());
+	}
+
+	// Returns the frame of `datagram`, None for handshakes and for datagrams that are not authenticated
+	// by the current session, or were already received
+	pub fn open(&mut self, datagram: &[u8], now: Instant) -> Result<Option<Vec<u8>>, String> {
+		if datagram.len() < 2 || datagram[0] != VERSION {
+			self.statistics.record_authentication_failure();
+			return Ok(None);
+		}
+
+		match datagram[1] {
+			HELLO if datagram.len() == HELLO_LEN => {
+				if let Some(peer_challenge) = self.authenticate_handshake(datagram) {
+					let reply = self.handshake(REPLY, &self.challenge, Some(&peer_challenge));
+					self.outgoing_packets.push_back(reply);
+				}
+			},
+			REPLY | CONFIRM if datagram.len() == ANSWER_LEN => {
+				if let Some(peer_challenge) = self.authenticate_handshake(datagram) {
+					let echoed_challenge: Challenge = datagram[2 + CHALLENGE_LEN..2 + 2 * CHALLENGE_LEN].try_into().unwrap();
+					if echoed_challenge == self.challenge {
+						self.start_session(peer_challenge, datagram[1] == REPLY)?;
+					}
+					// Late answers to the hello that started the current session are harmless, anything else is replayed
+					else if !matches!(&self.session, Some(session) if session.own_challenge == echoed_challenge) {
+						self.statistics.record_authentication_failure();
+					}
+				}
+			},
+			FRAME if datagram.len() >= SECURE_OVERHEAD => {
+				return Ok(self.open_frame(datagram, now));
+			},
+			_ => {
+				self.statistics.record_authentication_failure();
+			},
+		}
+		return Ok(None);
+	}
+
+	// Sends a hello when no session started yet, every HANDSHAKE_INTERVAL
+	pub fn poll(&mut self, now: Instant) {
+		if self.session.is_none() {
+			self.send_hello(now);
+		}
+	}
+
+	pub fn take_packet(&mut self) -> Option<Vec<u8>> {
+		return self.outgoing_packets.pop_front();
+	}
+
+	fn send_hello(&mut self, now: Instant) {
+		if self.last_hello.is_none_or(|last_hello| now.duration_since(last_hello) >= HANDSHAKE_INTERVAL) {
+			let hello = self.handshake(HELLO, &self.challenge, None);
+			self.outgoing_packets.push_back(hello);
+			self.last_hello = Some(now);
+		}
+	}
+
+	fn handshake_mac(&self, message: &[u8]) -> Hmac<Sha256> {
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.handshake_key).unwrap();
+		mac.update(message);
+		return mac;
+	}
+
+	fn handshake(&self, kind: u8, challenge: &Challenge, echoed_challenge: Option<&Challenge>) -> Vec<u8> {
+		let mut message = vec![VERSION, kind];
+		message.extend_from_slice(challenge);
+		if let Some(echoed_challenge) = echoed_challenge {
+			message.extend_from_slice(echoed_challenge);
+		}
+
+		let tag = self.handshake_mac(&message).finalize().into_bytes();
+		message.extend_from_slice(&tag[..TAG_LEN]);
+		return message;
+	}
+
+	// Returns the challenge of the sender, None when the handshake is not authenticated or is our own reflected back
+	fn authenticate_handshake(&self, datagram: &[u8]) -> Option<Challenge> {
+		let (message, tag) = datagram.split_at(datagram.len() - TAG_LEN);
+		let challenge: Challenge = message[2..2 + CHALLENGE_LEN].try_into().unwrap();
+
+		let is_own = challenge == self.challenge || matches!(&self.session, Some(session) if session.own_challenge == challenge);
+		if is_own || self.handshake_mac(message).verify_truncated_left(tag).is_err() {
+			self.statistics.record_authentication_failure();
+			return None;
+		}
+		return Some(challenge);
+	}
+
+	fn start_session(&mut self, peer_challenge: Challenge, confirm: bool) -> Result<(), String> {
+		let own_challenge = std::mem::replace(&mut self.challenge, random_challenge()?);
+		if confirm {
+			let confirm = self.handshake(CONFIRM, &own_challenge, Some(&peer_challenge));
+			self.outgoing_packets.push_back(confirm);
+		}
+
+		let mut session = Session::new(&self.master_key, own_challenge, peer_challenge);
+		for frame in self.pending_frames.drain(..) {
+			self.outgoing_packets.push_back(session.seal(&frame));
+		}
+		self.session = Some(session);
+		return Ok(());
+	}
+
+	fn open_frame(&mut self, datagram: &[u8], now: Instant) -> Option<Vec<u8>> {
+		let (header, rest) = datagram.split_at(FRAME_HEADER_LEN);
+		let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+		let challenge: Challenge = header[2..2 + CHALLENGE_LEN].try_into().unwrap();
+		let counter = u64::from_le_bytes(header[2 + CHALLENGE_LEN..].try_into().unwrap());
+
+		match &mut self.session {
+			Some(session) if session.peer_challenge == challenge => {
+				let mut data = ciphertext.to_vec();
+				if !session.accepts(counter) || session.opening_cipher.decrypt_inout_detached(&nonce(counter), header, data.as_mut_slice().into(), &Tag::try_from(tag).unwrap()).is_err() {
+					self.statistics.record_authentication_failure();
+					return None;
+				}
+				session.record(counter);
+				return Some(data);
+			},
+			_ => {
+				// The peer may have started a session that we missed the end of: a new handshake sorts it out
+				self.statistics.record_authentication_failure();
+				self.send_hello(now);
+				return None;
+			},
+		}
+	}
+}
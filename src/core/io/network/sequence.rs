@@ -0,0 +1,61 @@
+// Best-effort UDP mode of the network backend: frames are numbered, so that those lost on the way are noticed.
+//
+// Packet: sequence number (4, LE) | frame
+// Every sender starts from a random sequence number, so that a restarted peer is not mistaken for a gap.
+// Packets arriving after a later one (reordered or duplicated) are dropped, their frame was already counted as lost.
+
+use super::super::super::statistics::StatisticsRecorder;
+
+pub(super) const SEQUENCE_OVERHEAD: usize = 4;
+// Longer gaps are taken for a restarted peer
+const MAXIMUM_GAP: u32 = 1024;
+
+pub struct SequenceChannel {
+	next_sequence: u32,
+	// None until the first packet of the peer
+	next_expected: Option<u32>,
+	statistics: StatisticsRecorder,
+}
+
+impl SequenceChannel {
+	pub fn new(statistics: StatisticsRecorder) -> Result<Self, String> {
+		let mut next_sequence = [0u8; 4];
+		getrandom::fill(&mut next_sequence).map_err(|e| format!("Fail on getrandom: {}", e))?;
+
+		return Ok(Self { next_sequence: u32::from_le_bytes(next_sequence), next_expected: None, statistics });
+	}
+
+	pub fn number(&mut self, frame: &[u8]) -> Vec<u8> {
+		let mut packet = Vec::with_capacity(SEQUENCE_OVERHEAD + frame.len());
+		packet.extend_from_slice(&self.next_sequence.to_le_bytes());
+		packet.extend_from_slice(frame);
+
+		self.next_sequence = self.next_sequence.wrapping_add(1);
+		return packet;
+	}
+
+	// Returns the frame of `packet`, None when it is too short or arrived late.
+	// Frames skipped since the previous packet are recorded as lost.
+	pub fn check<'a>(&mut self, packet: &'a [u8]) -> Option<&'a [u8]> {
+		if packet.len() < SEQUENCE_OVERHEAD {
+			self.statistics.record_framing_error();
+			return None;
+		}
+
+		let sequence = u32::from_le_bytes(packet[..SEQUENCE_OVERHEAD].try_into().unwrap());
+		if let Some(next_expected) = self.next_expected {
+			let gap = sequence.wrapping_sub(next_expected);
+			if gap < MAXIMUM_GAP {
+				if gap > 0 {
+					self.statistics.record_lost_frames(gap as u64);
+				}
+			}
+			else if next_expected.wrapping_sub(sequence) <= MAXIMUM_GAP {
+				return None;
+			}
+		}
+
+		self.next_expected = Some(sequence.wrapping_add(1));
+		return Some(&packet[SEQUENCE_OVERHEAD..]);
+	}
+}
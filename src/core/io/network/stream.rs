@@ -0,0 +1,426 @@
+// TCP based links, for networks where UDP does not get through: frames are either prefixed by their length
+// (16 bits, big endian), or sent as binary WebSocket messages.
+//
+// Clients connect to the destination, and reconnect when the connection is lost.
+// Servers listen on the source port and talk to one client at a time, later clients are turned away.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::ConnectionState;
+use super::websocket::{self, Frame};
+use super::{NetworkRole, NetworkTransport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+const LENGTH_PREFIX_LEN: usize = 2;
+
+enum Handshake {
+	// Client side, waiting for the response to the request sent with this key
+	AwaitingResponse(String),
+	// Server side, waiting for the request of the client
+	AwaitingRequest,
+}
+
+pub struct StreamLink {
+	transport: NetworkTransport,
+	role: NetworkRole,
+	destination_host: String,
+	destination_port: u16,
+	listener: Option<TcpListener>,
+	connecting: Option<Receiver<Result<TcpStream, String>>>,
+	last_connect_attempt: Option<Instant>,
+	stream: Option<TcpStream>,
+	handshake: Option<Handshake>,
+	ever_connected: bool,
+	input: Vec<u8>,
+	output: Vec<u8>,
+	// Binary WebSocket message received in several frames
+	fragments: Option<Vec<u8>>,
+}
+
+fn connect(host: String, port: u16) -> Result<TcpStream, String> {
+	let address = (host.as_str(), port).to_socket_addrs()
+		.map_err(|e| format!("Fail on resolve '{}': {}", host, e))?
+		.next()
+		.ok_or(format!("No address found for '{}'", host))?;
+	return TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|e| format!("Fail on connect to {}: {}", address, e));
+}
+
+impl StreamLink {
+	pub fn new(transport: NetworkTransport, role: NetworkRole, source_port: u16, destination_host: &str, destination_port: u16) -> Result<Self, String> {
+		let mut listener = None;
+		if role == NetworkRole::Server {
+			let tcp_listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, source_port)).map_err(|e| format!("Fail on bind tcp:{}: {}", source_port, e))?;
+			tcp_listener.set_nonblocking(true).map_err(|e| format!("Fail on set_nonblocking: {}", e))?;
+			listener = Some(tcp_listener);
+		}
+
+		return Ok(Self {
+			transport,
+			role,
+			destination_host: destination_host.to_owned(),
+			destination_port,
+			listener,
+			connecting: None,
+			last_connect_attempt: None,
+			stream: None,
+			handshake: None,
+			ever_connected: false,
+			input: Vec::new(),
+			output: Vec::new(),
+			fragments: None,
+		});
+	}
+
+	pub fn state(&self) -> ConnectionState {
+		if self.stream.is_some() && self.handshake.is_none() {
+			return ConnectionState::Connected;
+		}
+		if self.ever_connected {
+			return ConnectionState::Reconnecting;
+		}
+		return ConnectionState::WaitingForPeer;
+	}
+
+	fn is_open(&self) -> bool {
+		return self.state() == ConnectionState::Connected;
+	}
+
+	fn connection_lost(&mut self, reason: &str) {
+		log_info!("Lost {:?} connection: {}", self.transport, reason);
+
+		// Frames received before the connection was lost are still delivered, but not a partial handshake
+		if self.handshake.is_some() {
+			self.input.clear();
+		}
+		self.stream = None;
+		self.handshake = None;
+		self.output.clear();
+		self.fragments = None;
+	}
+
+	fn connection_established(&mut self, stream: TcpStream) -> Result<(), String> {
+		stream.set_nonblocking(true).map_err(|e| format!("Fail on set_nonblocking: {}", e))?;
+		stream.set_nodelay(true).map_err(|e| format!("Fail on set_nodelay: {}", e))?;
+		self.stream = Some(stream);
+		self.input.clear();
+
+		if self.transport == NetworkTransport::WebSocket {
+			match self.role {
+				NetworkRole::Client => {
+					let key = websocket::random_key()?;
+					self.output.extend_from_slice(websocket::client_request(&self.destination_host, self.destination_port, &key).as_bytes());
+					self.handshake = Some(Handshake::AwaitingResponse(key));
+				},
+				NetworkRole::Server => {
+					self.handshake = Some(Handshake::AwaitingRequest);
+				},
+			}
+		}
+		else {
+			log_info!("{:?} connection established", self.transport);
+			self.ever_connected = true;
+		}
+		return Ok(());
+	}
+
+	// Connects or accepts a connection if needed, and goes on with the handshake
+	fn poll_connection(&mut self) -> Result<(), String> {
+		if self.stream.is_none() {
+			match self.role {
+				NetworkRole::Client => { self.poll_connect()?; },
+				NetworkRole::Server => { self.poll_accept()?; },
+			}
+		}
+		else if let Some(listener) = &self.listener {
+			// Only one client at a time
+			if let Ok((_, address)) = listener.accept() {
+				log_info!("Turning away {}, already connected", address);
+			}
+		}
+
+		if self.handshake.is_some() {
+			self.poll_handshake()?;
+		}
+		return Ok(());
+	}
+
+	fn poll_connect(&mut self) -> Result<(), String> {
+		if let Some(connecting) = &self.connecting {
+			match connecting.try_recv() {
+				Ok(Ok(stream)) => {
+					self.connecting = None;
+					return self.connection_established(stream);
+				},
+				Ok(Err(e)) => {
+					log_info!("{}", e);
+					self.connecting = None;
+				},
+				Err(TryRecvError::Empty) => {},
+				Err(TryRecvError::Disconnected) => {
+					self.connecting = None;
+				},
+			}
+		}
+		else if self.last_connect_attempt.is_none_or(|last_attempt| last_attempt.elapsed() >= RECONNECT_INTERVAL) {
+			// Connecting may block for a while, especially when resolving the host name
+			let (sender, receiver) = mpsc::channel();
+			let (host, port) = (self.destination_host.clone(), self.destination_port);
+			thread::spawn(move || {
+				let _ = sender.send(connect(host, port));
+			});
+
+			self.connecting = Some(receiver);
+			self.last_connect_attempt = Some(Instant::now());
+		}
+		return Ok(());
+	}
+
+	fn poll_accept(&mut self) -> Result<(), String> {
+		if let Some(listener) = &self.listener {
+			match listener.accept() {
+				Ok((stream, address)) => {
+					log_info!("Accepted {:?} connection from {}", self.transport, address);
+					return self.connection_established(stream);
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+				Err(e) => {
+					return Err(format!("Fail on accept: {}", e));
+				},
+			}
+		}
+		return Ok(());
+	}
+
+	fn poll_handshake(&mut self) -> Result<(), String> {
+		self.flush();
+		self.read_available();
+
+		if let Some((head_len, lines)) = websocket::parse_http_head(&self.input) {
+			self.input.drain(..head_len);
+
+			let result = match self.handshake.take() {
+				Some(Handshake::AwaitingResponse(key)) => { websocket::check_server_response(&lines, &key) },
+				Some(Handshake::AwaitingRequest) => {
+					match websocket::check_client_request(&lines) {
+						Ok(key) => {
+							self.output.extend_from_slice(websocket::server_response(&key).as_bytes());
+							Ok(())
+						},
+						Err(e) => {
+							self.output.extend_from_slice(websocket::BAD_REQUEST_RESPONSE.as_bytes());
+							Err(e)
+						},
+					}
+				},
+				None => { Ok(()) },
+			};
+
+			match result {
+				Ok(()) => {
+					log_info!("WebSocket connection established");
+					self.ever_connected = true;
+				},
+				Err(e) => {
+					self.flush();
+					self.connection_lost(&e);
+				},
+			}
+		}
+		else if self.input.len() > websocket::MAXIMUM_HANDSHAKE_LEN {
+			self.connection_lost("WebSocket handshake too long");
+		}
+
+		self.flush();
+		return Ok(());
+	}
+
+	// Reads whatever is available without blocking
+	fn read_available(&mut self) {
+		let mut buffer = [0u8; 4096];
+
+		while let Some(stream) = &mut self.stream {
+			match stream.read(&mut buffer) {
+				Ok(0) => {
+					self.connection_lost("closed by peer");
+				},
+				Ok(len) => {
+					self.input.extend_from_slice(&buffer[..len]);
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					return;
+				},
+				Err(e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(e) => {
+					self.connection_lost(&e.to_string());
+				},
+			}
+		}
+	}
+
+	// Writes as much of the pending output as possible without blocking
+	fn flush(&mut self) {
+		while let Some(stream) = &mut self.stream {
+			if self.output.is_empty() {
+				return;
+			}
+
+			match stream.write(&self.output) {
+				Ok(0) => {
+					self.connection_lost("closed by peer");
+				},
+				Ok(len) => {
+					self.output.drain(..len);
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					return;
+				},
+				Err(e) if e.kind() == ErrorKind::Interrupted => {},
+				Err(e) => {
+					self.connection_lost(&e.to_string());
+				},
+			}
+		}
+	}
+
+	fn websocket_mask() -> Result<Option<[u8; 4]>, String> {
+		let mut mask = [0u8; 4];
+		getrandom::fill(&mut mask).map_err(|e| format!("Fail on getrandom: {}", e))?;
+		return Ok(Some(mask));
+	}
+
+	pub fn send(&mut self, packet: &[u8]) -> Result<(), String> {
+		self.poll_connection()?;
+		if !self.is_open() {
+			return Err(format!("No {:?} peer connected", self.transport));
+		}
+
+		match self.transport {
+			NetworkTransport::WebSocket => {
+				let mask = if self.role == NetworkRole::Client { Self::websocket_mask()? } else { None };
+				self.output.extend(websocket::encode_frame(websocket::OPCODE_BINARY, packet, mask));
+			},
+			_ => {
+				self.output.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+				self.output.extend_from_slice(packet);
+			},
+		}
+
+		self.flush();
+		if !self.is_open() {
+			return Err(format!("Lost {:?} connection while sending", self.transport));
+		}
+		return Ok(());
+	}
+
+	pub fn receive(&mut self, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+		self.poll_connection()?;
+		if !self.is_open() && self.input.is_empty() {
+			return Ok(None);
+		}
+
+		self.flush();
+		self.read_available();
+
+		let ret = match self.transport {
+			NetworkTransport::WebSocket => { self.take_websocket_message(maximum_len, statistics) },
+			_ => { self.take_length_prefixed_frame(maximum_len, statistics) },
+		};
+		self.flush();
+		return ret;
+	}
+
+	fn take_length_prefixed_frame(&mut self, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+		if self.input.len() < LENGTH_PREFIX_LEN {
+			return Ok(None);
+		}
+
+		let len = u16::from_be_bytes([self.input[0], self.input[1]]) as usize;
+		if len > maximum_len {
+			// Nothing after this can be trusted to be aligned on a frame
+			statistics.record_framing_error();
+			self.input.clear();
+			self.connection_lost(&format!("frame too long ({} bytes)", len));
+			return Ok(None);
+		}
+		if self.input.len() < LENGTH_PREFIX_LEN + len {
+			return Ok(None);
+		}
+
+		let frame = self.input[LENGTH_PREFIX_LEN..LENGTH_PREFIX_LEN + len].to_vec();
+		self.input.drain(..LENGTH_PREFIX_LEN + len);
+		return Ok(Some(frame));
+	}
+
+	fn take_websocket_message(&mut self, maximum_len: usize, statistics: &StatisticsRecorder) -> Result<Option<Vec<u8>>, String> {
+		loop {
+			let (Frame { fin, opcode, payload }, len) = match websocket::decode_frame(&self.input, self.role, maximum_len) {
+				Ok(Some(decoded)) => { decoded },
+				Ok(None) => {
+					return Ok(None);
+				},
+				Err(e) => {
+					// Same as a length-prefixed frame too long
+					statistics.record_framing_error();
+					self.input.clear();
+					self.connection_lost(&e);
+					return Ok(None);
+				},
+			};
+			self.input.drain(..len);
+
+			match opcode {
+				websocket::OPCODE_BINARY | websocket::OPCODE_CONTINUATION => {
+					let message = match (opcode, self.fragments.take()) {
+						(websocket::OPCODE_BINARY, None) => { payload },
+						(websocket::OPCODE_CONTINUATION, Some(mut fragments)) => {
+							fragments.extend_from_slice(&payload);
+							fragments
+						},
+						_ => {
+							statistics.record_framing_error();
+							self.connection_lost("unexpected WebSocket continuation");
+							return Ok(None);
+						},
+					};
+
+					if message.len() > maximum_len {
+						statistics.record_framing_error();
+						self.connection_lost("WebSocket message too long");
+						return Ok(None);
+					}
+					if fin {
+						return Ok(Some(message));
+					}
+					self.fragments = Some(message);
+				},
+				websocket::OPCODE_PING => {
+					let mask = if self.role == NetworkRole::Client { Self::websocket_mask()? } else { None };
+					self.output.extend(websocket::encode_frame(websocket::OPCODE_PONG, &payload, mask));
+				},
+				websocket::OPCODE_CLOSE => {
+					let mask = if self.role == NetworkRole::Client { Self::websocket_mask()? } else { None };
+					self.output.extend(websocket::encode_frame(websocket::OPCODE_CLOSE, &payload, mask));
+					self.flush();
+					self.connection_lost("closed by peer");
+					return Ok(None);
+				},
+				websocket::OPCODE_PONG => {},
+				websocket::OPCODE_TEXT => {
+					// Frames are binary
+					statistics.record_dropped();
+				},
+				_ => {
+					statistics.record_framing_error();
+					self.connection_lost(&format!("unknown WebSocket opcode {:#x}", opcode));
+					return Ok(None);
+				},
+			}
+		}
+	}
+}
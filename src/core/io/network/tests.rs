@@ -0,0 +1,728 @@
+// Secure and reliable modes: SecureChannel and ReliableChannel on their own (the latter over a simulated lossy link),
+// then two NetworkBackends talking over the loopback interface
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use super::reliable::{ReliableChannel, MAXIMUM_DELIVERY_DELAY};
+use super::secure::{SecureChannel, HANDSHAKE_INTERVAL};
+use super::sequence::SequenceChannel;
+use super::websocket;
+use super::{NetworkBackend, NetworkOptions, NetworkRole, NetworkTransport};
+use super::super::super::{ConnectionState, MAXIMUM_DATA_LEN};
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+
+const PASSPHRASE: &str = "correct horse battery staple";
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn channel_pair() -> (SecureChannel, SecureChannel) {
+	return (SecureChannel::new(PASSPHRASE, StatisticsRecorder::new()).unwrap(), SecureChannel::new(PASSPHRASE, StatisticsRecorder::new()).unwrap());
+}
+
+// Carries the packets of both channels to each other until neither has any left, returns them
+fn exchange(alice: &mut SecureChannel, bob: &mut SecureChannel, now: Instant) -> Vec<Vec<u8>> {
+	let mut packets = Vec::new();
+	loop {
+		let (alice_packet, bob_packet) = (alice.take_packet(), bob.take_packet());
+		if alice_packet.is_none() && bob_packet.is_none() {
+			return packets;
+		}
+		if let Some(packet) = alice_packet {
+			assert_eq!(bob.open(&packet, now).unwrap(), None);
+			packets.push(packet);
+		}
+		if let Some(packet) = bob_packet {
+			assert_eq!(alice.open(&packet, now).unwrap(), None);
+			packets.push(packet);
+		}
+	}
+}
+
+// Returns the packets of the handshake
+fn handshake(alice: &mut SecureChannel, bob: &mut SecureChannel) -> Vec<Vec<u8>> {
+	let now = Instant::now();
+	alice.poll(now);
+	bob.poll(now);
+	let packets = exchange(alice, bob, now);
+	assert!(alice.is_established() && bob.is_established());
+	return packets;
+}
+
+fn seal(channel: &mut SecureChannel, frame: &[u8]) -> Vec<u8> {
+	channel.send(frame);
+	return channel.take_packet().unwrap();
+}
+
+#[test]
+fn secure_round_trip() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+
+	for frame in [vec![], vec![0x42], vec![0xA5; 255]] {
+		assert_eq!(bob.open(&seal(&mut alice, &frame), now).unwrap(), Some(frame.clone()));
+		assert_eq!(alice.open(&seal(&mut bob, &frame), now).unwrap(), Some(frame));
+	}
+}
+
+#[test]
+fn frames_wait_for_the_handshake() {
+	let (mut alice, mut bob) = channel_pair();
+	let now = Instant::now();
+	alice.send(&[0x01]);
+	alice.send(&[0x02]);
+	alice.poll(now);
+
+	assert_eq!(bob.open(&alice.take_packet().unwrap(), now).unwrap(), None);
+	assert_eq!(alice.take_packet(), None);
+	assert_eq!(alice.open(&bob.take_packet().unwrap(), now).unwrap(), None);
+	assert_eq!(bob.open(&alice.take_packet().unwrap(), now).unwrap(), None);
+	assert_eq!(bob.open(&alice.take_packet().unwrap(), now).unwrap(), Some(vec![0x01]));
+	assert_eq!(bob.open(&alice.take_packet().unwrap(), now).unwrap(), Some(vec![0x02]));
+}
+
+#[test]
+fn hellos_are_repeated_until_answered() {
+	let (mut alice, _) = channel_pair();
+	let now = Instant::now();
+
+	alice.poll(now);
+	assert!(alice.take_packet().is_some());
+	alice.poll(now);
+	assert_eq!(alice.take_packet(), None);
+	alice.poll(now + HANDSHAKE_INTERVAL);
+	assert!(alice.take_packet().is_some());
+}
+
+#[test]
+fn frames_are_not_sent_in_clear() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let frame = b"Pokewalker handshake";
+
+	let datagram = seal(&mut alice, frame);
+	assert!(!datagram.windows(frame.len()).any(|window| window == frame));
+}
+
+#[test]
+fn wrong_passphrase_is_rejected() {
+	let statistics = StatisticsRecorder::new();
+	let mut alice = SecureChannel::new(PASSPHRASE, statistics.clone()).unwrap();
+	let mut mallory = SecureChannel::new("hunter2", StatisticsRecorder::new()).unwrap();
+	let now = Instant::now();
+
+	mallory.poll(now);
+	assert_eq!(alice.open(&mallory.take_packet().unwrap(), now).unwrap(), None);
+	assert_eq!(alice.take_packet(), None);
+	assert_eq!(statistics.snapshot().authentication_failures, 1);
+}
+
+#[test]
+fn tampered_datagrams_are_rejected() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+	let datagram = seal(&mut alice, &[0x01, 0x02, 0x03]);
+
+	for position in [0, 1, 2, 10, 18, datagram.len() - 1] {
+		let mut tampered = datagram.clone();
+		tampered[position] ^= 0x80;
+		assert_eq!(bob.open(&tampered, now).unwrap(), None, "byte {} tampered", position);
+	}
+	assert_eq!(bob.open(&datagram[..datagram.len() - 1], now).unwrap(), None);
+	assert_eq!(bob.open(&datagram, now).unwrap(), Some(vec![0x01, 0x02, 0x03]));
+}
+
+#[test]
+fn replays_are_rejected_reordering_is_not() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+	let datagrams: Vec<Vec<u8>> = (0..100u8).map(|i| seal(&mut alice, &[i])).collect();
+
+	assert_eq!(bob.open(&datagrams[1], now).unwrap(), Some(vec![1]));
+	assert_eq!(bob.open(&datagrams[1], now).unwrap(), None);
+
+	// Late but within the window
+	assert_eq!(bob.open(&datagrams[0], now).unwrap(), Some(vec![0]));
+	assert_eq!(bob.open(&datagrams[0], now).unwrap(), None);
+
+	// Out of the window
+	assert_eq!(bob.open(&datagrams[99], now).unwrap(), Some(vec![99]));
+	assert_eq!(bob.open(&datagrams[2], now).unwrap(), None);
+	assert_eq!(bob.open(&datagrams[98], now).unwrap(), Some(vec![98]));
+}
+
+#[test]
+fn restarted_peer_is_accepted_after_a_handshake() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+	let old_datagram = seal(&mut alice, &[0x01]);
+	assert_eq!(bob.open(&old_datagram, now).unwrap(), Some(vec![0x01]));
+
+	let mut restarted_alice = SecureChannel::new(PASSPHRASE, StatisticsRecorder::new()).unwrap();
+	handshake(&mut restarted_alice, &mut bob);
+	assert_eq!(bob.open(&seal(&mut restarted_alice, &[0x02]), now).unwrap(), Some(vec![0x02]));
+
+	assert_eq!(bob.open(&seal(&mut alice, &[0x03]), now).unwrap(), None);
+	assert_eq!(bob.open(&old_datagram, now).unwrap(), None);
+}
+
+#[test]
+fn recorded_datagrams_do_not_start_sessions() {
+	let (mut alice, mut bob) = channel_pair();
+	let mut recorded = handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+	recorded.push(seal(&mut alice, &[0x01]));
+	assert_eq!(bob.open(recorded.last().unwrap(), now).unwrap(), Some(vec![0x01]));
+
+	// Neither in a later run of Bob
+	let mut restarted_bob = SecureChannel::new(PASSPHRASE, StatisticsRecorder::new()).unwrap();
+	for datagram in &recorded {
+		assert_eq!(restarted_bob.open(datagram, now).unwrap(), None);
+	}
+	assert!(!restarted_bob.is_established());
+
+	// Nor in the current one, whose challenge was replaced once it started the session
+	for datagram in &recorded {
+		assert_eq!(bob.open(datagram, now).unwrap(), None);
+	}
+	assert_eq!(bob.open(&seal(&mut alice, &[0x02]), now).unwrap(), Some(vec![0x02]));
+}
+
+#[test]
+fn lost_confirm_is_recovered_from() {
+	let (mut alice, mut bob) = channel_pair();
+	handshake(&mut alice, &mut bob);
+	let now = Instant::now();
+
+	// Alice restarts, and the confirm that would have started the session of Bob is lost
+	let mut restarted_alice = SecureChannel::new(PASSPHRASE, StatisticsRecorder::new()).unwrap();
+	restarted_alice.poll(now);
+	assert_eq!(bob.open(&restarted_alice.take_packet().unwrap(), now).unwrap(), None);
+	assert_eq!(restarted_alice.open(&bob.take_packet().unwrap(), now).unwrap(), None);
+	assert!(restarted_alice.is_established());
+	restarted_alice.take_packet().unwrap();
+
+	// Its frames are not authenticated by the session of Bob, who starts a new handshake
+	let now = now + HANDSHAKE_INTERVAL;
+	assert_eq!(bob.open(&seal(&mut restarted_alice, &[0x01]), now).unwrap(), None);
+	exchange(&mut restarted_alice, &mut bob, now);
+	assert_eq!(bob.open(&seal(&mut restarted_alice, &[0x02]), now).unwrap(), Some(vec![0x02]));
+}
+
+#[test]
+fn own_datagrams_reflected_back_are_rejected() {
+	let (mut alice, mut bob) = channel_pair();
+	let now = Instant::now();
+	alice.poll(now);
+	let hello = alice.take_packet().unwrap();
+	assert_eq!(alice.open(&hello, now).unwrap(), None);
+	assert_eq!(alice.take_packet(), None);
+
+	assert_eq!(bob.open(&hello, now).unwrap(), None);
+	exchange(&mut alice, &mut bob, now);
+	let datagram = seal(&mut alice, &[0x01]);
+	assert_eq!(alice.open(&datagram, now).unwrap(), None);
+}
+
+#[test]
+fn sequence_gaps_are_counted_as_lost() {
+	let statistics = StatisticsRecorder::new();
+	let mut sender = SequenceChannel::new(StatisticsRecorder::new()).unwrap();
+	let mut receiver = SequenceChannel::new(statistics.clone()).unwrap();
+	let packets: Vec<Vec<u8>> = (0..5u8).map(|i| sender.number(&[i])).collect();
+
+	assert_eq!(receiver.check(&packets[0]), Some([0].as_slice()));
+	assert_eq!(receiver.check(&packets[3]), Some([3].as_slice()));
+	assert_eq!(statistics.losses(), 2);
+
+	// Late or duplicated
+	assert_eq!(receiver.check(&packets[1]), None);
+	assert_eq!(receiver.check(&packets[3]), None);
+	assert_eq!(receiver.check(&packets[4]), Some([4].as_slice()));
+	assert_eq!(statistics.losses(), 2);
+
+	assert_eq!(receiver.check(&[0x00]), None);
+	assert_eq!(statistics.snapshot().framing_errors, 1);
+}
+
+#[test]
+fn restarted_sequence_is_not_a_gap() {
+	let statistics = StatisticsRecorder::new();
+	let mut receiver = SequenceChannel::new(statistics.clone()).unwrap();
+
+	for sequence in [0u32, 1, 0x8000_0000, 0x8000_0001, 0] {
+		let packet = [sequence.to_le_bytes().as_slice(), &[0x42]].concat();
+		assert_eq!(receiver.check(&packet), Some([0x42].as_slice()), "sequence {:#x}", sequence);
+	}
+	assert_eq!(statistics.losses(), 0);
+}
+
+// Drops, duplicates and delays packets, deterministically
+struct LossyLink {
+	random_state: u64,
+	loss_percent: u64,
+	duplicate_percent: u64,
+	maximum_latency: Duration,
+	// (arrival, towards the right channel, packet)
+	in_flight: Vec<(Instant, bool, Vec<u8>)>,
+}
+
+impl LossyLink {
+	fn new(loss_percent: u64, duplicate_percent: u64) -> Self {
+		return Self { random_state: 0x9E3779B97F4A7C15, loss_percent, duplicate_percent, maximum_latency: Duration::from_millis(20), in_flight: Vec::new() };
+	}
+
+	// xorshift64*
+	fn random(&mut self, bound: u64) -> u64 {
+		self.random_state ^= self.random_state >> 12;
+		self.random_state ^= self.random_state << 25;
+		self.random_state ^= self.random_state >> 27;
+		return self.random_state.wrapping_mul(0x2545F4914F6CDD1D) % bound;
+	}
+
+	fn transmit(&mut self, now: Instant, to_right: bool, packet: Vec<u8>) {
+		if self.random(100) < self.loss_percent {
+			return;
+		}
+
+		let copies = if self.random(100) < self.duplicate_percent { 2 } else { 1 };
+		for _ in 0..copies {
+			let latency = Duration::from_micros(self.random(self.maximum_latency.as_micros() as u64));
+			self.in_flight.push((now + latency, to_right, packet.clone()));
+		}
+	}
+
+	fn exchange(&mut self, now: Instant, left: &mut ReliableChannel, right: &mut ReliableChannel) {
+		while let Some(packet) = left.take_packet() {
+			self.transmit(now, true, packet);
+		}
+		while let Some(packet) = right.take_packet() {
+			self.transmit(now, false, packet);
+		}
+
+		let (arrived, in_flight) = self.in_flight.drain(..).partition(|(arrival, _, _)| *arrival <= now);
+		self.in_flight = in_flight;
+
+		for (_, to_right, packet) in arrived {
+			if to_right {
+				right.handle_packet(&packet, now);
+			}
+			else {
+				left.handle_packet(&packet, now);
+			}
+		}
+	}
+}
+
+const TICK: Duration = Duration::from_millis(5);
+
+fn reliable_pair() -> ((ReliableChannel, StatisticsRecorder), (ReliableChannel, StatisticsRecorder)) {
+	let (left_statistics, right_statistics) = (StatisticsRecorder::new(), StatisticsRecorder::new());
+	let left = ReliableChannel::new(left_statistics.clone()).unwrap();
+	let right = ReliableChannel::new(right_statistics.clone()).unwrap();
+	return ((left, left_statistics), (right, right_statistics));
+}
+
+// Sends `frame_count` frames each way, as fast as the in-flight limit allows, and returns what each side received
+fn run_lossy_exchange(link: &mut LossyLink, frame_count: u8) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, StatisticsRecorder) {
+	let ((mut left, left_statistics), (mut right, _)) = reliable_pair();
+	let (mut left_sent, mut right_sent) = (0, 0);
+	let (mut left_received, mut right_received) = (Vec::new(), Vec::new());
+
+	let mut now = Instant::now();
+	let deadline = now + Duration::from_secs(60);
+	while (left_received.len() < frame_count as usize || right_received.len() < frame_count as usize) && now < deadline {
+		if left_sent < frame_count && left.send(&[0x00, left_sent], now).is_ok() {
+			left_sent += 1;
+		}
+		if right_sent < frame_count && right.send(&[0x01, right_sent], now).is_ok() {
+			right_sent += 1;
+		}
+
+		left.poll(now);
+		right.poll(now);
+		link.exchange(now, &mut left, &mut right);
+
+		while let Some(frame) = left.take_frame() {
+			left_received.push(frame);
+		}
+		while let Some(frame) = right.take_frame() {
+			right_received.push(frame);
+		}
+		now += TICK;
+	}
+	return (left_received, right_received, left_statistics);
+}
+
+#[test]
+fn reliable_delivery_over_lossy_link() {
+	let mut link = LossyLink::new(20, 10);
+	let (left_received, right_received, left_statistics) = run_lossy_exchange(&mut link, 200);
+
+	let expected_by_right: Vec<Vec<u8>> = (0..200).map(|i| vec![0x00, i]).collect();
+	let expected_by_left: Vec<Vec<u8>> = (0..200).map(|i| vec![0x01, i]).collect();
+	assert_eq!(right_received, expected_by_right);
+	assert_eq!(left_received, expected_by_left);
+
+	let statistics = left_statistics.snapshot();
+	assert!(statistics.retransmissions > 0);
+	assert_eq!(statistics.frames_dropped, 0);
+}
+
+#[test]
+fn reliable_delivery_over_perfect_link_does_not_retransmit() {
+	let mut link = LossyLink::new(0, 0);
+	link.maximum_latency = Duration::from_millis(1);
+	let (left_received, right_received, left_statistics) = run_lossy_exchange(&mut link, 50);
+
+	assert_eq!(left_received.len(), 50);
+	assert_eq!(right_received.len(), 50);
+	assert_eq!(left_statistics.snapshot().retransmissions, 0);
+}
+
+#[test]
+fn lost_frames_are_given_up_without_stalling_the_link() {
+	let mut link = LossyLink::new(100, 0);
+	let ((mut left, left_statistics), (mut right, right_statistics)) = reliable_pair();
+	let mut now = Instant::now();
+
+	// Frame 0 goes through, frame 1 is lost for good
+	link.loss_percent = 0;
+	left.send(&[0], now).unwrap();
+	link.exchange(now, &mut left, &mut right);
+	now += link.maximum_latency;
+	link.exchange(now, &mut left, &mut right);
+	assert_eq!(right.take_frame(), Some(vec![0]));
+
+	link.loss_percent = 100;
+	left.send(&[1], now).unwrap();
+	link.exchange(now, &mut left, &mut right);
+
+	// Frame 2 arrives, but is held back while frame 1 may still come
+	link.loss_percent = 0;
+	left.send(&[2], now).unwrap();
+	let give_up = now + MAXIMUM_DELIVERY_DELAY + TICK;
+	let mut received = Vec::new();
+	while now <= give_up + link.maximum_latency {
+		if now < give_up {
+			// Only the retransmissions of frame 1 keep getting lost
+			link.in_flight.retain(|(_, _, packet)| packet.len() < 21 || packet[21..] != [1]);
+		}
+
+		left.poll(now);
+		right.poll(now);
+		link.exchange(now, &mut left, &mut right);
+		if now < give_up - TICK * 2 {
+			assert_eq!(right.take_frame(), None);
+		}
+		while let Some(frame) = right.take_frame() {
+			received.push(frame);
+		}
+		now += TICK;
+	}
+
+	assert_eq!(received, vec![vec![2]]);
+	assert_eq!(left_statistics.snapshot().frames_dropped, 1);
+	assert_eq!(right_statistics.snapshot().frames_dropped, 1);
+}
+
+#[test]
+fn restarted_reliable_peer_starts_a_new_sequence() {
+	let mut link = LossyLink::new(0, 0);
+	let ((mut left, _), (mut right, _)) = reliable_pair();
+	let mut now = Instant::now();
+
+	for i in 0..3 {
+		left.send(&[i], now).unwrap();
+	}
+	for _ in 0..10 {
+		link.exchange(now, &mut left, &mut right);
+		now += TICK;
+	}
+	for i in 0..3 {
+		assert_eq!(right.take_frame(), Some(vec![i]));
+	}
+
+	let mut restarted_left = ReliableChannel::new(StatisticsRecorder::new()).unwrap();
+	restarted_left.send(&[0x42], now).unwrap();
+	for _ in 0..10 {
+		link.exchange(now, &mut restarted_left, &mut right);
+		now += TICK;
+	}
+	assert_eq!(right.take_frame(), Some(vec![0x42]));
+}
+
+#[test]
+fn in_flight_frames_are_bounded() {
+	let ((mut left, _), _) = reliable_pair();
+	let now = Instant::now();
+
+	let accepted = (0..100u8).take_while(|&i| left.send(&[i], now).is_ok()).count();
+	assert_eq!(accepted, 64);
+}
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+fn receive_within(backend: &mut NetworkBackend, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = backend.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+fn backend_pair(left_options: &NetworkOptions, right_options: &NetworkOptions) -> ((NetworkBackend, StatisticsRecorder), (NetworkBackend, StatisticsRecorder)) {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let (left_statistics, right_statistics) = (StatisticsRecorder::new(), StatisticsRecorder::new());
+
+	let mut left = NetworkBackend::new(left_port, "127.0.0.1", right_port, left_options, left_statistics.clone());
+	let mut right = NetworkBackend::new(right_port, "127.0.0.1", left_port, right_options, right_statistics.clone());
+	left.enable().unwrap();
+	right.enable().unwrap();
+	return ((left, left_statistics), (right, right_statistics));
+}
+
+// Secure backends wait for the peer until the handshake started a session, which takes both of them receiving
+fn wait_for_sessions(left: &mut NetworkBackend, right: &mut NetworkBackend) {
+	assert_eq!(left.state(), ConnectionState::WaitingForPeer);
+	let start = Instant::now();
+	while left.state() != ConnectionState::Connected || right.state() != ConnectionState::Connected {
+		assert!(start.elapsed() < TEST_TIMEOUT, "No session started");
+		assert_eq!(left.receive().unwrap(), None);
+		assert_eq!(right.receive().unwrap(), None);
+	}
+}
+
+#[test]
+fn secure_backends_exchange_frames() {
+	let options = NetworkOptions { passphrase: Some(PASSPHRASE.to_owned()), ..Default::default() };
+	let ((mut left, _), (mut right, right_statistics)) = backend_pair(&options, &options);
+	wait_for_sessions(&mut left, &mut right);
+
+	left.send(&[0xDE, 0xAD]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0xDE, 0xAD]));
+
+	right.send(&[0xBE, 0xEF]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0xBE, 0xEF]));
+
+	assert_eq!(right_statistics.snapshot().authentication_failures, 0);
+}
+
+#[test]
+fn secure_backend_drops_and_counts_unauthenticated_datagrams() {
+	let secure_options = NetworkOptions { passphrase: Some(PASSPHRASE.to_owned()), ..Default::default() };
+	let ((mut plain, _), (mut secure, secure_statistics)) = backend_pair(&NetworkOptions::default(), &secure_options);
+
+	plain.send(&[0x01, 0x02, 0x03]).unwrap();
+	assert_eq!(receive_within(&mut secure, TEST_TIMEOUT / 4), None);
+	assert_eq!(secure_statistics.snapshot().authentication_failures, 1);
+
+	// Long enough to be parsed as a secure datagram, but still not authenticated
+	plain.send(&[0x00; 255]).unwrap();
+	assert_eq!(receive_within(&mut secure, TEST_TIMEOUT / 4), None);
+	assert_eq!(secure_statistics.snapshot().authentication_failures, 2);
+}
+
+#[test]
+fn reliable_secure_backends_exchange_frames() {
+	let options = NetworkOptions { passphrase: Some(PASSPHRASE.to_owned()), reliable: true, ..Default::default() };
+	let ((mut left, left_statistics), (mut right, _)) = backend_pair(&options, &options);
+	wait_for_sessions(&mut left, &mut right);
+
+	for i in 0..10u8 {
+		left.send(&[i; 255]).unwrap();
+	}
+	for i in 0..10u8 {
+		assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![i; 255]));
+	}
+
+	// Acknowledgements are processed on the next call
+	right.send(&[0xFF]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0xFF]));
+	assert_eq!(left_statistics.snapshot().frames_dropped, 0);
+}
+
+fn receive_into_within(backend: &mut NetworkBackend, buffer: &mut [u8], timeout: Duration) -> Result<Option<usize>, String> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(len) = backend.receive_into(buffer)? {
+			return Ok(Some(len));
+		}
+	}
+	return Ok(None);
+}
+
+#[test]
+fn frames_are_received_into_caller_buffers() {
+	for options in [NetworkOptions::default(), NetworkOptions { reliable: true, ..Default::default() }] {
+		let ((mut left, _), (mut right, right_statistics)) = backend_pair(&options, &options);
+
+		// Plain datagrams land straight in buffers with room for the extra byte, the others go through a copy
+		for buffer_len in [MAXIMUM_DATA_LEN + 1, MAXIMUM_DATA_LEN, 3] {
+			let mut buffer = vec![0u8; buffer_len];
+			left.send(&[0x01, 0x02, 0x03]).unwrap();
+			assert_eq!(receive_into_within(&mut right, &mut buffer, TEST_TIMEOUT), Ok(Some(3)));
+			assert_eq!(buffer[..3], [0x01, 0x02, 0x03]);
+		}
+
+		left.send(&[0xFF; MAXIMUM_DATA_LEN]).unwrap();
+		assert!(receive_into_within(&mut right, &mut [0u8; 3], TEST_TIMEOUT).is_err());
+		assert_eq!(right_statistics.snapshot().framing_errors, 0);
+	}
+}
+
+#[test]
+fn websocket_accept_key() {
+	// Example of RFC 6455, section 1.3
+	assert_eq!(websocket::accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn websocket_frames_round_trip() {
+	for len in [0, 1, 125, 126, 255, 65535, 65536] {
+		let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+		// Clients mask their frames, servers do not
+		for (mask, receiver) in [(None, NetworkRole::Client), (Some([0x12, 0x34, 0x56, 0x78]), NetworkRole::Server)] {
+			let frame = websocket::encode_frame(websocket::OPCODE_BINARY, &payload, mask);
+
+			// Incomplete frames are waited for
+			assert!(websocket::decode_frame(&frame[..frame.len() - 1], receiver, 65536).unwrap().is_none());
+
+			let (decoded, decoded_len) = websocket::decode_frame(&frame, receiver, 65536).unwrap().unwrap();
+			assert_eq!(decoded_len, frame.len());
+			assert!(decoded.fin);
+			assert_eq!(decoded.opcode, websocket::OPCODE_BINARY);
+			assert_eq!(decoded.payload, payload);
+		}
+	}
+
+	let frame = websocket::encode_frame(websocket::OPCODE_BINARY, &[0; 300], None);
+	assert!(websocket::decode_frame(&frame, NetworkRole::Client, 255).is_err());
+}
+
+#[test]
+fn websocket_frames_masked_the_wrong_way_are_rejected() {
+	let unmasked = websocket::encode_frame(websocket::OPCODE_BINARY, &[0x01], None);
+	assert!(websocket::decode_frame(&unmasked, NetworkRole::Server, 255).is_err());
+
+	let masked = websocket::encode_frame(websocket::OPCODE_BINARY, &[0x01], Some([0x12, 0x34, 0x56, 0x78]));
+	assert!(websocket::decode_frame(&masked, NetworkRole::Client, 255).is_err());
+}
+
+fn free_tcp_port() -> u16 {
+	return TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+fn wait_for_connection(left: &mut NetworkBackend, right: &mut NetworkBackend) {
+	let start = Instant::now();
+	while left.state() != ConnectionState::Connected || right.state() != ConnectionState::Connected {
+		assert!(start.elapsed() < TEST_TIMEOUT * 5, "not connected: {:?} {:?}", left.state(), right.state());
+		left.receive().unwrap();
+		right.receive().unwrap();
+	}
+}
+
+fn stream_backend_pair(transport: NetworkTransport, reliable: bool) -> (NetworkBackend, NetworkBackend) {
+	let port = free_tcp_port();
+	let server_options = NetworkOptions { transport, role: NetworkRole::Server, reliable, ..Default::default() };
+	let client_options = NetworkOptions { transport, role: NetworkRole::Client, reliable, ..Default::default() };
+
+	let mut server = NetworkBackend::new(port, "127.0.0.1", 0, &server_options, StatisticsRecorder::new());
+	let mut client = NetworkBackend::new(0, "127.0.0.1", port, &client_options, StatisticsRecorder::new());
+	server.enable().unwrap();
+	client.enable().unwrap();
+	wait_for_connection(&mut server, &mut client);
+	return (server, client);
+}
+
+fn exchange_frames(server: &mut NetworkBackend, client: &mut NetworkBackend) {
+	for len in [0, 1, 255] {
+		let frame: Vec<u8> = (0..len).map(|i| i as u8).collect();
+		client.send(&frame).unwrap();
+		assert_eq!(receive_within(server, TEST_TIMEOUT), Some(frame.clone()));
+		server.send(&frame).unwrap();
+		assert_eq!(receive_within(client, TEST_TIMEOUT), Some(frame));
+	}
+}
+
+#[test]
+fn tcp_backends_exchange_frames() {
+	let (mut server, mut client) = stream_backend_pair(NetworkTransport::Tcp, false);
+	exchange_frames(&mut server, &mut client);
+}
+
+#[test]
+fn websocket_backends_exchange_frames() {
+	let (mut server, mut client) = stream_backend_pair(NetworkTransport::WebSocket, false);
+	exchange_frames(&mut server, &mut client);
+}
+
+#[test]
+fn reliable_websocket_backends_exchange_frames() {
+	let (mut server, mut client) = stream_backend_pair(NetworkTransport::WebSocket, true);
+	exchange_frames(&mut server, &mut client);
+}
+
+#[test]
+fn websocket_server_closes_connections_sending_unmasked_frames() {
+	let port = free_tcp_port();
+	let options = NetworkOptions { transport: NetworkTransport::WebSocket, role: NetworkRole::Server, ..Default::default() };
+	let statistics = StatisticsRecorder::new();
+	let mut server = NetworkBackend::new(port, "127.0.0.1", 0, &options, statistics.clone());
+	server.enable().unwrap();
+
+	let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+	client.set_read_timeout(Some(TEST_TIMEOUT)).unwrap();
+	client.write_all(websocket::client_request("127.0.0.1", port, "dGhlIHNhbXBsZSBub25jZQ==").as_bytes()).unwrap();
+	while server.state() != ConnectionState::Connected {
+		assert_eq!(server.receive().unwrap(), None);
+	}
+
+	client.write_all(&websocket::encode_frame(websocket::OPCODE_BINARY, &[0x01], None)).unwrap();
+	assert_eq!(receive_within(&mut server, TEST_TIMEOUT / 4), None);
+	assert_eq!(statistics.snapshot().framing_errors, 1);
+	assert_eq!(server.state(), ConnectionState::Reconnecting);
+
+	// Past the response to the handshake, the connection is closed without anything else
+	let mut received = Vec::new();
+	client.read_to_end(&mut received).unwrap();
+	assert!(received.starts_with(b"HTTP/1.1 101 "));
+	assert!(received.ends_with(b"\r\n\r\n"));
+}
+
+#[test]
+fn stream_server_waits_for_a_client() {
+	let options = NetworkOptions { transport: NetworkTransport::Tcp, role: NetworkRole::Server, ..Default::default() };
+	let mut server = NetworkBackend::new(free_tcp_port(), "127.0.0.1", 0, &options, StatisticsRecorder::new());
+	server.enable().unwrap();
+
+	assert_eq!(server.receive().unwrap(), None);
+	assert_eq!(server.state(), ConnectionState::WaitingForPeer);
+	assert!(server.send(&[0x01]).is_err());
+}
+
+#[test]
+fn stream_client_reconnects_after_server_restart() {
+	let (mut server, mut client) = stream_backend_pair(NetworkTransport::Tcp, false);
+
+	server.disable();
+	let start = Instant::now();
+	while client.state() == ConnectionState::Connected {
+		assert!(start.elapsed() < TEST_TIMEOUT);
+		client.receive().unwrap();
+	}
+	assert_eq!(client.state(), ConnectionState::Reconnecting);
+
+	server.enable().unwrap();
+	wait_for_connection(&mut server, &mut client);
+	exchange_frames(&mut server, &mut client);
+}
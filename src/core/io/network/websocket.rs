@@ -0,0 +1,181 @@
+// Just enough of RFC 6455 to exchange binary messages with a peer: the opening handshake on both sides,
+// binary, continuation, ping, pong and close frames. Extensions and subprotocols are not supported.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use super::NetworkRole;
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Longest HTTP head accepted during the handshake
+pub(super) const MAXIMUM_HANDSHAKE_LEN: usize = 4096;
+
+pub(super) const OPCODE_CONTINUATION: u8 = 0x0;
+pub(super) const OPCODE_TEXT: u8 = 0x1;
+pub(super) const OPCODE_BINARY: u8 = 0x2;
+pub(super) const OPCODE_CLOSE: u8 = 0x8;
+pub(super) const OPCODE_PING: u8 = 0x9;
+pub(super) const OPCODE_PONG: u8 = 0xA;
+
+pub struct Frame {
+	pub fin: bool,
+	pub opcode: u8,
+	pub payload: Vec<u8>,
+}
+
+pub fn accept_key(key: &str) -> String {
+	return BASE64.encode(Sha1::digest(format!("{}{}", key, ACCEPT_GUID).as_bytes()));
+}
+
+pub fn random_key() -> Result<String, String> {
+	let mut nonce = [0u8; 16];
+	getrandom::fill(&mut nonce).map_err(|e| format!("Fail on getrandom: {}", e))?;
+	return Ok(BASE64.encode(nonce));
+}
+
+pub fn client_request(host: &str, port: u16, key: &str) -> String {
+	return format!(
+		"GET / HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+		host, port, key,
+	);
+}
+
+pub fn server_response(key: &str) -> String {
+	return format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+}
+
+pub const BAD_REQUEST_RESPONSE: &str = "HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+// Returns the length of the HTTP head at the start of `buffer` and its lines, None if it is not complete yet
+pub fn parse_http_head(buffer: &[u8]) -> Option<(usize, Vec<String>)> {
+	let end = buffer.windows(4).position(|window| window == b"\r\n\r\n")?;
+	let head = String::from_utf8_lossy(&buffer[..end]);
+	return Some((end + 4, head.split("\r\n").map(|line| line.to_owned()).collect()));
+}
+
+fn header_value<'a>(lines: &'a [String], name: &str) -> Option<&'a str> {
+	return lines.iter().skip(1).find_map(|line| {
+		let (header, value) = line.split_once(':')?;
+		return if header.trim().eq_ignore_ascii_case(name) { Some(value.trim()) } else { None };
+	});
+}
+
+// Returns the key of a valid upgrade request
+pub fn check_client_request(lines: &[String]) -> Result<String, String> {
+	if !lines[0].starts_with("GET ") {
+		return Err(format!("Not a WebSocket upgrade request: '{}'", lines[0]));
+	}
+	if !header_value(lines, "Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket")) {
+		return Err(String::from("Missing 'Upgrade: websocket' header"));
+	}
+	if header_value(lines, "Sec-WebSocket-Version") != Some("13") {
+		return Err(String::from("Unsupported WebSocket version"));
+	}
+	return header_value(lines, "Sec-WebSocket-Key").map(|key| key.to_owned()).ok_or(String::from("Missing Sec-WebSocket-Key header"));
+}
+
+pub fn check_server_response(lines: &[String], key: &str) -> Result<(), String> {
+	if lines[0].split_whitespace().nth(1) != Some("101") {
+		return Err(format!("WebSocket upgrade refused: '{}'", lines[0]));
+	}
+	if header_value(lines, "Sec-WebSocket-Accept") != Some(accept_key(key).as_str()) {
+		return Err(String::from("Invalid Sec-WebSocket-Accept header"));
+	}
+	return Ok(());
+}
+
+// Clients must mask their frames, servers must not
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+	let mut frame = Vec::with_capacity(payload.len() + 14);
+	frame.push(0x80 | opcode);
+
+	let mask_bit = if mask.is_some() { 0x80 } else { 0x00 };
+	if payload.len() < 126 {
+		frame.push(mask_bit | payload.len() as u8);
+	}
+	else if payload.len() <= u16::MAX as usize {
+		frame.push(mask_bit | 126);
+		frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+	}
+	else {
+		frame.push(mask_bit | 127);
+		frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+	}
+
+	match mask {
+		Some(mask) => {
+			frame.extend_from_slice(&mask);
+			frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+		},
+		None => {
+			frame.extend_from_slice(payload);
+		},
+	}
+	return frame;
+}
+
+// Returns the frame at the start of `buffer`, received by `role`, and its length, None if it is not complete yet.
+// Frames with a payload longer than `maximum_len` or not masked as they should be are an error, the stream can not be trusted anymore.
+pub fn decode_frame(buffer: &[u8], role: NetworkRole, maximum_len: usize) -> Result<Option<(Frame, usize)>, String> {
+	if buffer.len() < 2 {
+		return Ok(None);
+	}
+
+	let fin = buffer[0] & 0x80 != 0;
+	let opcode = buffer[0] & 0x0F;
+	let masked = buffer[1] & 0x80 != 0;
+	if buffer[0] & 0x70 != 0 {
+		return Err(String::from("WebSocket extensions are not supported"));
+	}
+	match (role, masked) {
+		(NetworkRole::Server, false) => {
+			return Err(String::from("Unmasked WebSocket frame from the client"));
+		},
+		(NetworkRole::Client, true) => {
+			return Err(String::from("Masked WebSocket frame from the server"));
+		},
+		_ => {},
+	}
+
+	let (payload_len, mut offset) = match buffer[1] & 0x7F {
+		126 => {
+			if buffer.len() < 4 {
+				return Ok(None);
+			}
+			(u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4)
+		},
+		127 => {
+			if buffer.len() < 10 {
+				return Ok(None);
+			}
+			(u64::from_be_bytes(buffer[2..10].try_into().unwrap()), 10)
+		},
+		len => { (len as u64, 2) },
+	};
+	if payload_len > maximum_len as u64 {
+		return Err(format!("WebSocket frame too long ({} bytes)", payload_len));
+	}
+
+	let mut mask = None;
+	if masked {
+		if buffer.len() < offset + 4 {
+			return Ok(None);
+		}
+		mask = Some([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+		offset += 4;
+	}
+
+	let end = offset + payload_len as usize;
+	if buffer.len() < end {
+		return Ok(None);
+	}
+
+	let mut payload = buffer[offset..end].to_vec();
+	if let Some(mask) = mask {
+		for (i, byte) in payload.iter_mut().enumerate() {
+			*byte ^= mask[i % 4];
+		}
+	}
+	return Ok(Some((Frame { fin, opcode, payload }, end)));
+}
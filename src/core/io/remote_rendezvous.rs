@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+
+use n3t1r_relay::{Message, MAXIMUM_MESSAGE_LEN};
+
+use super::super::traits::{check_buffer_len, IOHandler};
+use super::super::statistics::StatisticsRecorder;
+use super::super::ConnectionState;
+use super::super::MAXIMUM_DATA_LEN;
+use super::network::{NetworkRole, NetworkTransport, StreamLink};
+use super::rendezvous::MAXIMUM_PENDING_FRAMES;
+use super::frame_ring::FrameRing;
+
+mod hole_punch;
+use self::hole_punch::HolePunch;
+
+// Rendez-vous through a relay server, for peers on different machines: the server pairs the clients of a room,
+// and forwards frames between them until they reach each other directly. The room is joined again after reconnecting to the server.
+pub struct RemoteRendezVousBackend {
+	server_host: String,
+	server_port: u16,
+	room_name: String,
+	link: Option<StreamLink>,
+	// None when UDP is not available, frames then always go through the relay
+	hole_punch: Option<HolePunch>,
+	// Where BIND datagrams are sent, the server itself unless overridden
+	udp_server_address: Option<SocketAddr>,
+	joined: bool,
+	paired: bool,
+	// Frames sent while waiting for a peer, delivered once it shows up
+	pending_frames: VecDeque<Vec<u8>>,
+	// Frames received while sending
+	received_frames: FrameRing,
+	statistics: StatisticsRecorder,
+}
+
+impl RemoteRendezVousBackend {
+	pub fn new(server_host: &str, server_port: u16, room_name: &str, statistics: StatisticsRecorder) -> Self {
+		return Self {
+			server_host: server_host.to_owned(),
+			server_port,
+			room_name: room_name.to_owned(),
+			link: None,
+			hole_punch: None,
+			udp_server_address: None,
+			joined: false,
+			paired: false,
+			pending_frames: VecDeque::new(),
+			received_frames: FrameRing::new(MAXIMUM_PENDING_FRAMES),
+			statistics,
+		};
+	}
+
+	// Stands in for a NAT between this client and the server in the tests
+	#[cfg(test)]
+	pub fn set_udp_server_address(&mut self, udp_server_address: SocketAddr) {
+		self.udp_server_address = Some(udp_server_address);
+	}
+
+	fn open_hole_punch(&self) -> Result<HolePunch, String> {
+		let server_address = match self.udp_server_address {
+			Some(udp_server_address) => { udp_server_address },
+			None => {
+				(self.server_host.as_str(), self.server_port).to_socket_addrs()
+					.map_err(|e| format!("Fail on resolve '{}': {}", self.server_host, e))?
+					.next()
+					.ok_or(format!("No address found for '{}'", self.server_host))?
+			},
+		};
+		return HolePunch::new(server_address);
+	}
+
+	// Handles everything the server and the peer sent so far
+	fn poll(&mut self) -> Result<(), String> {
+		let now = Instant::now();
+
+		if let Some(link) = &mut self.link {
+			while let Some(bytes) = link.receive(MAXIMUM_MESSAGE_LEN, &self.statistics)? {
+				match Message::from_bytes(&bytes) {
+					Ok(Message::Paired) => {
+						log_info!("Met peer in remote room '{}'", self.room_name);
+						self.paired = true;
+					},
+					Ok(Message::PeerLeft) => {
+						log_info!("Peer left remote room '{}'", self.room_name);
+						self.paired = false;
+						if let Some(hole_punch) = &mut self.hole_punch {
+							hole_punch.reset_peer();
+						}
+					},
+					Ok(Message::Reflexive(address)) => {
+						if let Some(hole_punch) = &mut self.hole_punch {
+							hole_punch.set_reflexive_address(address);
+						}
+					},
+					Ok(Message::PeerEndpoint(pair_id, address)) => {
+						if let Some(hole_punch) = &mut self.hole_punch {
+							hole_punch.set_peer(pair_id, address, now);
+						}
+					},
+					Ok(Message::Data(data)) => {
+						if data.len() > MAXIMUM_DATA_LEN {
+							self.statistics.record_framing_error();
+						}
+						else {
+							queue_received_frame(&mut self.received_frames, &data, &self.statistics);
+						}
+					},
+					Ok(Message::Error(error)) => {
+						return Err(format!("Relay server error: {}", error));
+					},
+					Ok(message) => {
+						log_info!("Unexpected relay message {:?}", message);
+						self.statistics.record_framing_error();
+					},
+					Err(e) => {
+						log_info!("{}", e);
+						self.statistics.record_framing_error();
+					},
+				}
+			}
+
+			// Popped once sent, so that a failing send leaves them queued for the next call
+			if self.paired {
+				while let Some(data) = self.pending_frames.front() {
+					link.send(&Message::Data(data.clone()).to_bytes())?;
+					self.pending_frames.pop_front();
+				}
+			}
+
+			if link.state() != ConnectionState::Connected {
+				self.joined = false;
+				self.paired = false;
+				if let Some(hole_punch) = &mut self.hole_punch {
+					hole_punch.reset_peer();
+				}
+			}
+			else if !self.joined {
+				link.send(&Message::Join(self.room_name.clone()).to_bytes())?;
+				if let Some(hole_punch) = &mut self.hole_punch {
+					link.send(&hole_punch.bind_message().to_bytes())?;
+				}
+				self.joined = true;
+			}
+		}
+
+		if let Some(hole_punch) = &mut self.hole_punch {
+			hole_punch.poll(now);
+			while let Some(data) = hole_punch.receive(now, &self.statistics) {
+				queue_received_frame(&mut self.received_frames, &data, &self.statistics);
+			}
+		}
+		return Ok(());
+	}
+
+	#[cfg(test)]
+	pub fn is_direct(&self) -> bool {
+		return self.hole_punch.as_ref().is_some_and(|hole_punch| hole_punch.is_direct());
+	}
+
+	pub fn pending_frames(&self) -> Vec<Vec<u8>> {
+		return self.pending_frames.iter().cloned().collect();
+	}
+
+	pub fn take_pending_frames(&mut self) -> Vec<Vec<u8>> {
+		return self.pending_frames.drain(..).collect();
+	}
+}
+
+// The oldest frame is lost past MAXIMUM_PENDING_FRAMES
+fn queue_received_frame(received_frames: &mut FrameRing, data: &[u8], statistics: &StatisticsRecorder) {
+	if received_frames.push(data) {
+		statistics.record_lost_frames(1);
+	}
+}
+
+impl IOHandler for RemoteRendezVousBackend {
+	fn enable(&mut self) -> Result<(), String> {
+		if self.link.is_none() {
+			log_info!("Enabling remote rendez-vous protocol on room '{}' of {}:{}", self.room_name, self.server_host, self.server_port);
+
+			self.link = Some(StreamLink::new(NetworkTransport::Tcp, NetworkRole::Client, 0, &self.server_host, self.server_port)?);
+
+			match self.open_hole_punch() {
+				Ok(hole_punch) => { self.hole_punch = Some(hole_punch); },
+				Err(e) => { log_info!("Hole punching unavailable, frames go through the relay: {}", e); },
+			}
+		}
+		return Ok(());
+	}
+
+	fn disable(&mut self) {
+		if self.link.is_some() {
+			log_info!("Disabling remote rendez-vous protocol");
+
+			self.link = None;
+			self.hole_punch = None;
+			self.joined = false;
+			self.paired = false;
+			self.pending_frames.clear();
+			self.received_frames.clear();
+		}
+	}
+
+	// Frames sent before a peer joined the room are queued, the oldest ones being dropped past MAXIMUM_PENDING_FRAMES
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		self.poll()?;
+
+		if let Some(link) = &mut self.link {
+			if !self.paired {
+				if self.pending_frames.len() >= MAXIMUM_PENDING_FRAMES {
+					self.pending_frames.pop_front();
+					self.statistics.record_dropped();
+				}
+				self.pending_frames.push_back(data.to_vec());
+				return Ok(());
+			}
+			if let Some(hole_punch) = self.hole_punch.as_mut().filter(|hole_punch| hole_punch.is_direct()) {
+				return hole_punch.send(data, Instant::now());
+			}
+			return link.send(&Message::Data(data.to_vec()).to_bytes());
+		}
+		return Err(String::from("Remote rendez-vous protocol is not enabled"));
+	}
+
+	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		self.poll()?;
+		return Ok(self.received_frames.pop());
+	}
+
+	// A frame longer than the buffer stays queued
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		self.poll()?;
+		if let Some(len) = self.received_frames.front_len() {
+			check_buffer_len(len, buffer)?;
+		}
+		return Ok(self.received_frames.pop_into(buffer));
+	}
+
+	fn state(&self) -> ConnectionState {
+		return match &self.link {
+			Some(_) if self.paired => { ConnectionState::Connected },
+			Some(link) if link.state() == ConnectionState::Reconnecting => { ConnectionState::Reconnecting },
+			Some(_) => { ConnectionState::WaitingForPeer },
+			None => { ConnectionState::Disabled },
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,179 @@
+// UDP hole punching between the peers of a remote rendez-vous room, frames are relayed by the server until it succeeds.
+//
+// The client sends BIND datagrams to the relay server, which replies over TCP with the address it saw them coming from,
+// that is the public address of the client behind its NAT. Once both peers have one, the server sends each one
+// the address of the other, and both send PUNCH datagrams to each other at the same time: the outgoing PUNCH opens
+// the NAT of the sender for the replies of the peer. The direct path is kept open by keepalives, and given up
+// when nothing comes from the peer anymore, going back to the relay.
+//
+// Direct datagram: kind (1) | pair id (8, LE) | frame
+// The pair id, given by the server, tells the datagrams of the peer apart from stray ones. The peer is also accepted
+// from another address than the announced one, which is what it looks like from here when both are behind the same NAT.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use n3t1r_relay::{bind_datagram, Message};
+
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::MAXIMUM_DATA_LEN;
+use super::super::network::bind_udp_socket;
+
+const BIND_INTERVAL: Duration = Duration::from_millis(200);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(50);
+// Time given to the peers to reach each other, before settling on the relay
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(3);
+// Well below the usual UDP NAT mapping timeouts
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(4);
+
+const PUNCH: u8 = 0;
+const PUNCH_ACK: u8 = 1;
+const KEEPALIVE: u8 = 2;
+const DATA: u8 = 3;
+const HEADER_LEN: usize = 1 + 8;
+
+struct Peer {
+	pair_id: u64,
+	address: SocketAddr,
+	punch_started: Instant,
+	last_punch: Option<Instant>,
+	last_sent: Instant,
+	// When the last datagram came from the peer, once it did
+	last_heard: Option<Instant>,
+}
+
+fn send_direct(socket: &UdpSocket, peer: &Peer, kind: u8, data: &[u8]) -> Result<(), String> {
+	let mut datagram = Vec::with_capacity(HEADER_LEN + data.len());
+	datagram.push(kind);
+	datagram.extend_from_slice(&peer.pair_id.to_le_bytes());
+	datagram.extend_from_slice(data);
+
+	if let Err(e) = socket.send_to(&datagram, peer.address) {
+		return Err(format!("Fail on send_to: {}", e));
+	}
+	return Ok(());
+}
+
+pub struct HolePunch {
+	socket: UdpSocket,
+	server_address: SocketAddr,
+	token: u64,
+	reflexive_address: Option<SocketAddr>,
+	last_bind: Option<Instant>,
+	peer: Option<Peer>,
+}
+
+impl HolePunch {
+	pub fn new(server_address: SocketAddr) -> Result<Self, String> {
+		let mut token = [0u8; 8];
+		getrandom::fill(&mut token).map_err(|e| format!("Fail on getrandom: {}", e))?;
+
+		return Ok(Self { socket: bind_udp_socket(0, &server_address)?, server_address, token: u64::from_le_bytes(token), reflexive_address: None, last_bind: None, peer: None });
+	}
+
+	// Sent to the server on every connection, the reflexive address is learnt again
+	pub fn bind_message(&mut self) -> Message {
+		self.reflexive_address = None;
+		self.last_bind = None;
+		return Message::Bind(self.token);
+	}
+
+	pub fn set_reflexive_address(&mut self, address: SocketAddr) {
+		log_info!("Reflexive address is {}", address);
+		self.reflexive_address = Some(address);
+	}
+
+	pub fn set_peer(&mut self, pair_id: u64, address: SocketAddr, now: Instant) {
+		log_info!("Trying to reach peer {} directly", address);
+		self.peer = Some(Peer { pair_id, address, punch_started: now, last_punch: None, last_sent: now, last_heard: None });
+	}
+
+	pub fn reset_peer(&mut self) {
+		self.peer = None;
+	}
+
+	pub fn is_direct(&self) -> bool {
+		return self.peer.as_ref().is_some_and(|peer| peer.last_heard.is_some());
+	}
+
+	// Only once is_direct()
+	pub fn send(&mut self, data: &[u8], now: Instant) -> Result<(), String> {
+		if let Some(peer) = &mut self.peer {
+			send_direct(&self.socket, peer, DATA, data)?;
+			peer.last_sent = now;
+		}
+		return Ok(());
+	}
+
+	// Binds, punches and keeps the direct path alive, as needed. Errors are not fatal, the relay is still there.
+	pub fn poll(&mut self, now: Instant) {
+		if self.reflexive_address.is_none() && self.last_bind.is_none_or(|last_bind| now.duration_since(last_bind) >= BIND_INTERVAL) {
+			self.last_bind = Some(now);
+			let _ = self.socket.send_to(&bind_datagram(self.token), self.server_address);
+		}
+
+		if let Some(peer) = &mut self.peer {
+			match peer.last_heard {
+				None => {
+					if now.duration_since(peer.punch_started) < PUNCH_TIMEOUT {
+						if peer.last_punch.is_none_or(|last_punch| now.duration_since(last_punch) >= PUNCH_INTERVAL) {
+							peer.last_punch = Some(now);
+							let _ = send_direct(&self.socket, peer, PUNCH, &[]);
+						}
+					}
+					else if peer.last_punch.is_some() {
+						log_info!("Could not reach peer {} directly, frames go through the relay", peer.address);
+						peer.last_punch = None;
+					}
+				},
+				Some(last_heard) => {
+					if now.duration_since(last_heard) >= DIRECT_TIMEOUT {
+						log_info!("Lost direct path to peer {}, frames go through the relay", peer.address);
+						self.peer = None;
+					}
+					else if now.duration_since(peer.last_sent) >= KEEPALIVE_INTERVAL {
+						peer.last_sent = now;
+						let _ = send_direct(&self.socket, peer, KEEPALIVE, &[]);
+					}
+				},
+			}
+		}
+	}
+
+	// Frames received directly from the peer
+	pub fn receive(&mut self, now: Instant, statistics: &StatisticsRecorder) -> Option<Vec<u8>> {
+		// One extra byte to detect datagrams that are too long
+		let mut buffer = [0u8; HEADER_LEN + MAXIMUM_DATA_LEN + 1];
+
+		while let Ok((len, source)) = self.socket.recv_from(&mut buffer) {
+			if let Some(peer) = &mut self.peer {
+				if len < HEADER_LEN || u64::from_le_bytes(buffer[1..HEADER_LEN].try_into().unwrap()) != peer.pair_id {
+					continue;
+				}
+
+				if peer.last_heard.is_none() {
+					log_info!("Reached peer {} directly", source);
+				}
+				peer.last_heard = Some(now);
+				peer.address = source;
+
+				match buffer[0] {
+					PUNCH => {
+						let _ = send_direct(&self.socket, peer, PUNCH_ACK, &[]);
+					},
+					DATA => {
+						if len > HEADER_LEN + MAXIMUM_DATA_LEN {
+							statistics.record_framing_error();
+						}
+						else {
+							return Some(buffer[HEADER_LEN..len].to_vec());
+						}
+					},
+					_ => {},
+				}
+			}
+		}
+		return None;
+	}
+}
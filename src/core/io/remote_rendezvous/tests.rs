@@ -0,0 +1,220 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use n3t1r_relay::query_rooms;
+use n3t1r_relay::server::RelayServer;
+
+use super::RemoteRendezVousBackend;
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::ConnectionState;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Relay server polled on a background thread until dropped
+struct TestServer {
+	port: u16,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+	fn start() -> Self {
+		let mut server = RelayServer::bind(0).unwrap();
+		let port = server.local_port().unwrap();
+		let stop = Arc::new(AtomicBool::new(false));
+
+		let thread_stop = stop.clone();
+		let thread = thread::spawn(move || {
+			while !thread_stop.load(Ordering::Relaxed) {
+				if !server.poll().unwrap() {
+					thread::sleep(Duration::from_millis(1));
+				}
+			}
+		});
+		return Self { port, stop, thread: Some(thread) };
+	}
+}
+
+impl Drop for TestServer {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			thread.join().unwrap();
+		}
+	}
+}
+
+fn join(server: &TestServer, room_name: &str) -> RemoteRendezVousBackend {
+	let mut backend = RemoteRendezVousBackend::new("127.0.0.1", server.port, room_name, StatisticsRecorder::new());
+	backend.enable().unwrap();
+	return backend;
+}
+
+// Loopback stand-in for a NAT that lets nothing in but the replies of the relay server:
+// datagrams of the client are forwarded to the server, and datagrams from anywhere else are dropped
+struct NatStandIn {
+	address: SocketAddr,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl NatStandIn {
+	fn start(server: &TestServer) -> Self {
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+		let address = socket.local_addr().unwrap();
+		let server_address = SocketAddr::from(([127, 0, 0, 1], server.port));
+		let stop = Arc::new(AtomicBool::new(false));
+
+		let thread_stop = stop.clone();
+		let thread = thread::spawn(move || {
+			let mut buffer = [0u8; 2048];
+			let mut client_address = None;
+			while !thread_stop.load(Ordering::Relaxed) {
+				if let Ok((len, source)) = socket.recv_from(&mut buffer) {
+					if source == server_address {
+						if let Some(client_address) = client_address {
+							let _ = socket.send_to(&buffer[..len], client_address);
+						}
+					}
+					else if client_address.is_none_or(|client_address| client_address == source) {
+						client_address = Some(source);
+						let _ = socket.send_to(&buffer[..len], server_address);
+					}
+				}
+			}
+		});
+		return Self { address, stop, thread: Some(thread) };
+	}
+}
+
+impl Drop for NatStandIn {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			thread.join().unwrap();
+		}
+	}
+}
+
+fn join_behind(server: &TestServer, nat: &NatStandIn, room_name: &str) -> RemoteRendezVousBackend {
+	let mut backend = RemoteRendezVousBackend::new("127.0.0.1", server.port, room_name, StatisticsRecorder::new());
+	backend.set_udp_server_address(nat.address);
+	backend.enable().unwrap();
+	return backend;
+}
+
+fn wait_until(backends: &mut [&mut RemoteRendezVousBackend], state: ConnectionState) {
+	let start = Instant::now();
+	while backends.iter().any(|backend| backend.state() != state) {
+		assert!(start.elapsed() < TEST_TIMEOUT, "not {:?}", state);
+		for backend in backends.iter_mut() {
+			backend.receive().unwrap();
+		}
+	}
+}
+
+fn receive_within(backend: &mut RemoteRendezVousBackend, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = backend.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn remote_peers_meet_and_exchange_frames() {
+	let server = TestServer::start();
+	let mut left = join(&server, "remote");
+	assert_eq!(left.state(), ConnectionState::WaitingForPeer);
+
+	// Sent before the peer joins, delivered once it does
+	left.send(&[0x01]).unwrap();
+
+	let start = Instant::now();
+	while query_rooms("127.0.0.1", server.port).unwrap().is_empty() {
+		assert!(start.elapsed() < TEST_TIMEOUT);
+		left.receive().unwrap();
+	}
+	assert_eq!(query_rooms("127.0.0.1", server.port).unwrap(), vec![String::from("remote")]);
+
+	let mut right = join(&server, "remote");
+	wait_until(&mut [&mut left, &mut right], ConnectionState::Connected);
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01]));
+
+	right.send(&[0xBE, 0xEF]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0xBE, 0xEF]));
+	left.send(&[0x42; 255]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x42; 255]));
+
+	right.disable();
+	wait_until(&mut [&mut left], ConnectionState::WaitingForPeer);
+}
+
+#[test]
+fn full_remote_room_is_reported() {
+	let server = TestServer::start();
+	let mut left = join(&server, "crowded");
+	let mut right = join(&server, "crowded");
+	wait_until(&mut [&mut left, &mut right], ConnectionState::Connected);
+
+	let mut third = join(&server, "crowded");
+	let start = Instant::now();
+	loop {
+		assert!(start.elapsed() < TEST_TIMEOUT);
+		if let Err(error) = third.receive() {
+			assert!(error.contains("is full"), "{}", error);
+			break;
+		}
+	}
+	assert_ne!(third.state(), ConnectionState::Connected);
+}
+
+#[test]
+fn peers_reach_each_other_directly() {
+	let server = TestServer::start();
+	let mut left = join(&server, "direct");
+	let mut right = join(&server, "direct");
+	wait_until(&mut [&mut left, &mut right], ConnectionState::Connected);
+
+	let start = Instant::now();
+	while !left.is_direct() || !right.is_direct() {
+		assert!(start.elapsed() < TEST_TIMEOUT, "no direct path");
+		left.receive().unwrap();
+		right.receive().unwrap();
+	}
+
+	left.send(&[0x01, 0x02]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01, 0x02]));
+	right.send(&[0x42; 255]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0x42; 255]));
+}
+
+#[test]
+fn frames_go_through_the_relay_when_peers_can_not_reach_each_other() {
+	let server = TestServer::start();
+	let (left_nat, right_nat) = (NatStandIn::start(&server), NatStandIn::start(&server));
+
+	let mut left = join_behind(&server, &left_nat, "firewalled");
+	let mut right = join_behind(&server, &right_nat, "firewalled");
+	wait_until(&mut [&mut left, &mut right], ConnectionState::Connected);
+
+	// Punches keep being dropped by the stand-ins
+	let start = Instant::now();
+	while start.elapsed() < Duration::from_millis(500) {
+		left.receive().unwrap();
+		right.receive().unwrap();
+		assert!(!left.is_direct() && !right.is_direct());
+	}
+
+	left.send(&[0x01, 0x02]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01, 0x02]));
+	right.send(&[0xBE, 0xEF]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0xBE, 0xEF]));
+}
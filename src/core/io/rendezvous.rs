@@ -1,32 +1,263 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+
 use super::super::traits::IOHandler;
+use super::super::statistics::StatisticsRecorder;
+use super::super::ConnectionState;
+use super::super::MAXIMUM_DATA_LEN;
+use super::super::rendezvous::RendezVous;
+use super::super::discovery::{Discovery, DiscoveryEndpoint};
+use super::network::{bind_udp_socket, receive_datagram_into};
+#[cfg(unix)]
+use super::unix_socket::UnixSocketBackend;
+
+pub(super) const MAXIMUM_PENDING_FRAMES: usize = 64;
+
+// How peers of this machine are met
+enum LocalRendezVous {
+	// The UDP port is advertised in the room directory
+	Udp(RendezVous),
+	// Both peers are connected through a Unix socket of the room directory, without any port
+	#[cfg(unix)]
+	UnixSocket(UnixSocketBackend),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Peer {
+	Udp(SocketAddr),
+	// Through the Unix socket
+	Local,
+}
+
+impl fmt::Display for Peer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		return match self {
+			Self::Udp(address) => { write!(f, "udp:{}", address) },
+			Self::Local => { write!(f, "unix socket") },
+		};
+	}
+}
 
 pub struct RendezVousBackend {
 	room_name: String,
+	rendezvous_folder: PathBuf,
+	// Also used by LAN discovery, even when peers of this machine are met through a Unix socket
+	socket: Option<UdpSocket>,
+	local_rendezvous: Option<LocalRendezVous>,
+	// Peers of other machines, until one is met
+	discovery: Option<Discovery>,
+	discovery_endpoint: DiscoveryEndpoint,
+	peer: Option<Peer>,
+	// Frames sent while waiting for a peer, delivered once it shows up
+	pending_frames: VecDeque<Vec<u8>>,
+	statistics: StatisticsRecorder,
 }
 
 impl RendezVousBackend {
-	pub fn new(room_name: &str) -> Self {
-		return Self { room_name: room_name.to_owned() };
+	pub fn new(room_name: &str, rendezvous_folder: &Path, statistics: StatisticsRecorder) -> Self {
+		return Self::with_discovery_endpoint(room_name, rendezvous_folder, DiscoveryEndpoint::default(), statistics);
+	}
+
+	pub fn with_discovery_endpoint(room_name: &str, rendezvous_folder: &Path, discovery_endpoint: DiscoveryEndpoint, statistics: StatisticsRecorder) -> Self {
+		return Self {
+			room_name: room_name.to_owned(),
+			rendezvous_folder: rendezvous_folder.to_owned(),
+			socket: None,
+			local_rendezvous: None,
+			discovery: None,
+			discovery_endpoint,
+			peer: None,
+			pending_frames: VecDeque::new(),
+			statistics,
+		};
+	}
+
+	// Unix sockets are preferred, the UDP port is advertised when they are not available (e.g. path too long)
+	fn advertise(&self, local_udp_port: u16) -> Result<LocalRendezVous, String> {
+		#[cfg(unix)]
+		{
+			let mut unix_socket = UnixSocketBackend::new(&self.rendezvous_folder, &self.room_name, self.statistics.clone());
+			match unix_socket.enable() {
+				Ok(()) => {
+					return Ok(LocalRendezVous::UnixSocket(unix_socket));
+				},
+				Err(e) => {
+					log_info!("Unix socket unavailable ({}), advertising udp:{} instead", e, local_udp_port);
+				},
+			}
+		}
+
+		match RendezVous::new(&self.rendezvous_folder, &self.room_name, local_udp_port) {
+			Ok(rendezvous) => {
+				return Ok(LocalRendezVous::Udp(rendezvous));
+			},
+			Err(e) => {
+				return Err(format!("Fail on advertise: {}", e));
+			},
+		}
+	}
+
+	// Peers of this machine are met through the rendez-vous folder, the others through LAN discovery
+	fn try_to_meet(&mut self) -> Result<(), String> {
+		let previous_peer = self.peer;
+
+		// Still polled once a peer is met through LAN discovery, which may find peers of this machine too:
+		// the Unix socket takes over once connected, on both sides
+		#[cfg(unix)]
+		if let Some(LocalRendezVous::UnixSocket(unix_socket)) = &mut self.local_rendezvous {
+			unix_socket.poll()?;
+			let is_connected = unix_socket.state() == ConnectionState::Connected;
+
+			match self.peer {
+				Some(Peer::Local) => {
+					if !is_connected {
+						self.peer = None;
+					}
+				},
+				_ => {
+					if is_connected {
+						self.peer = Some(Peer::Local);
+					}
+				},
+			}
+		}
+
+		if self.peer.is_none() {
+			if let Some(LocalRendezVous::Udp(rendezvous)) = &self.local_rendezvous {
+				self.peer = rendezvous.try_to_meet().map(|peer_udp_port| Peer::Udp((Ipv4Addr::LOCALHOST, peer_udp_port).into()));
+			}
+			if let (None, Some(discovery)) = (self.peer, &mut self.discovery) {
+				self.peer = discovery.poll().map(Peer::Udp);
+			}
+		}
+
+		if let (Some(peer), true) = (self.peer, self.peer != previous_peer) {
+			log_info!("Met peer on {} in room '{}'", peer, self.room_name);
+			self.discovery = None;
+		}
+
+		// Popped once sent, so that a failing send leaves them queued for the next call
+		if self.peer.is_some() {
+			while let Some(data) = self.pending_frames.front().cloned() {
+				self.send_to_peer(&data)?;
+				self.pending_frames.pop_front();
+			}
+		}
+		return Ok(());
+	}
+
+	fn send_to_peer(&mut self, data: &[u8]) -> Result<(), String> {
+		match (self.peer, &mut self.local_rendezvous) {
+			(Some(Peer::Udp(peer)), _) => {
+				if let Some(socket) = &self.socket {
+					if let Err(e) = socket.send_to(data, peer) {
+						return Err(format!("Fail on send_to: {}", e));
+					}
+				}
+			},
+			#[cfg(unix)]
+			(Some(Peer::Local), Some(LocalRendezVous::UnixSocket(unix_socket))) => {
+				return unix_socket.send(data);
+			},
+			_ => {},
+		}
+		return Ok(());
+	}
+
+	pub fn pending_frames(&self) -> Vec<Vec<u8>> {
+		return self.pending_frames.iter().cloned().collect();
+	}
+
+	pub fn take_pending_frames(&mut self) -> Vec<Vec<u8>> {
+		return self.pending_frames.drain(..).collect();
 	}
 }
 
 impl IOHandler for RendezVousBackend {
 	fn enable(&mut self) -> Result<(), String> {
-		println!("Enabling rendez-vous protocol on room '{}'", self.room_name);
+		if self.socket.is_none() {
+			log_info!("Enabling rendez-vous protocol on room '{}'", self.room_name);
+
+			let socket = bind_udp_socket(0, &(Ipv4Addr::LOCALHOST, 0).into())?;
+			let local_udp_port = socket.local_addr().map_err(|e| format!("Fail on local_addr: {}", e))?.port();
+
+			self.local_rendezvous = Some(self.advertise(local_udp_port)?);
+			self.socket = Some(socket);
+
+			// Without it, only peers of this machine can be met
+			match Discovery::new(self.discovery_endpoint, &self.room_name, local_udp_port) {
+				Ok(discovery) => { self.discovery = Some(discovery); },
+				Err(e) => { log_info!("LAN discovery unavailable: {}", e); },
+			}
+		}
 		return Ok(());
 	}
 
 	fn disable(&mut self) {
-		println!("Disabling rendez-vous protocol");
+		if self.socket.is_some() {
+			log_info!("Disabling rendez-vous protocol");
+
+			self.local_rendezvous = None;
+			self.discovery = None;
+			self.socket = None;
+			self.peer = None;
+			self.pending_frames.clear();
+		}
 	}
 
+	// Frames sent before a peer joined the room are queued, the oldest ones being dropped past MAXIMUM_PENDING_FRAMES
 	fn send(&mut self, data: &[u8]) -> Result<(), String> {
-		println!("Sending via rendez-vous on room '{}': {:?}", self.room_name, data);
-		return Ok(());
+		self.try_to_meet()?;
+
+		if self.socket.is_none() {
+			return Err(String::from("Rendez-vous protocol is not enabled"));
+		}
+		else if self.peer.is_none() {
+			if self.pending_frames.len() >= MAXIMUM_PENDING_FRAMES {
+				self.pending_frames.pop_front();
+				self.statistics.record_dropped();
+			}
+			self.pending_frames.push_back(data.to_vec());
+			return Ok(());
+		}
+		return self.send_to_peer(data);
 	}
 
 	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
-		println!("Trying to receive via rendez-vous on room '{}'...", self.room_name);
-		return Ok(Some(format!("Hello back from '{}'", self.room_name).as_bytes().to_vec()));
+		let mut buffer = [0u8; MAXIMUM_DATA_LEN];
+		return Ok(self.receive_into(&mut buffer)?.map(|len| buffer[..len].to_vec()));
+	}
+
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		self.try_to_meet()?;
+
+		match (self.peer, &mut self.local_rendezvous) {
+			(Some(Peer::Udp(peer)), _) => {
+				if let Some(socket) = &self.socket {
+					return receive_datagram_into(socket, &peer, buffer, &self.statistics);
+				}
+			},
+			#[cfg(unix)]
+			(Some(Peer::Local), Some(LocalRendezVous::UnixSocket(unix_socket))) => {
+				return unix_socket.receive_into(buffer);
+			},
+			_ => {},
+		}
+		return Ok(None);
+	}
+
+	fn state(&self) -> ConnectionState {
+		if self.peer.is_some() {
+			return ConnectionState::Connected;
+		}
+		else if self.socket.is_some() {
+			return ConnectionState::WaitingForPeer;
+		}
+		return ConnectionState::Disabled;
 	}
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use super::RendezVousBackend;
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::discovery::DiscoveryEndpoint;
+use super::super::super::ConnectionState;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+const ROOM: &str = "room";
+
+fn test_folder(name: &str) -> PathBuf {
+	return env::temp_dir().join(format!("n3t1r-rendezvous-backend-test-{}-{}", process::id(), name));
+}
+
+// LAN discovery on a loopback port of its own, so that peers only meet through the rendez-vous folder
+fn isolated_backend(folder: &Path) -> RendezVousBackend {
+	let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+	let discovery_endpoint = DiscoveryEndpoint { address: Ipv4Addr::LOCALHOST, port: port.min(u16::MAX - 16) };
+	return RendezVousBackend::with_discovery_endpoint(ROOM, folder, discovery_endpoint, StatisticsRecorder::new());
+}
+
+fn receive_within(backend: &mut RendezVousBackend, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = backend.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn peers_of_this_machine_meet() {
+	let folder = test_folder("meet");
+	let (mut left, mut right) = (isolated_backend(&folder), isolated_backend(&folder));
+
+	// Delivered once the peer joins
+	left.enable().unwrap();
+	left.send(&[0x01]).unwrap();
+	assert_eq!(left.state(), ConnectionState::WaitingForPeer);
+	assert_eq!(left.pending_frames(), vec![vec![0x01]]);
+
+	right.enable().unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), None);
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT / 10), None);
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01]));
+	assert_eq!((left.state(), right.state()), (ConnectionState::Connected, ConnectionState::Connected));
+
+	right.send(&[0x02, 0x03]).unwrap();
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0x02, 0x03]));
+
+	left.disable();
+	right.disable();
+	let _ = fs::remove_dir_all(&folder);
+}
@@ -1,38 +1,277 @@
-use std::collections::HashMap;
-use std::time::Instant;
+use std::cmp::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use serialport;
 
-use super::super::traits::IOHandler;
+use super::super::traits::{copy_frame, IOHandler};
+use super::super::statistics::StatisticsRecorder;
+use super::super::clock::Clock;
+use super::super::ConnectionState;
 use super::super::MAXIMUM_DATA_LEN;
 use super::super::SERIAL_RX_TIMEOUT;
 
+const DEFAULT_BAUD_RATE: u32 = 115200;
+const RECONNECT_SCAN_INTERVAL: Duration = Duration::from_millis(500);
+
+// (VID, PID) of the boards the NTiR-11 dongle firmware is known to run on
+const COMPATIBLE_DONGLES: &[(u16, u16)] = &[
+	(0x2E8A, 0x000A),	// Raspberry Pi Pico
+	(0x2341, 0x8036),	// Arduino Leonardo
+	(0x2341, 0x8037),	// Arduino Micro
+	(0x1B4F, 0x9206),	// SparkFun Pro Micro
+];
+// Product names the dongle firmware may announce itself with
+const COMPATIBLE_PRODUCT_NAMES: &[&str] = &["NTiR-11", "N3T1R"];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialPortKind {
+	Usb,
+	Pci,
+	Bluetooth,
+	Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerialPortInfo {
+	pub name: String,
+	pub port_type: SerialPortKind,
+	pub vid: Option<u16>,
+	pub pid: Option<u16>,
+	pub serial_number: Option<String>,
+	pub manufacturer: Option<String>,
+	pub product: Option<String>,
+	pub is_compatible: bool,
+}
+
+impl SerialPortInfo {
+	fn from_serialport(port: serialport::SerialPortInfo) -> Self {
+		let mut info = Self { name: port.port_name, port_type: SerialPortKind::Unknown, vid: None, pid: None, serial_number: None, manufacturer: None, product: None, is_compatible: false };
+
+		match port.port_type {
+			serialport::SerialPortType::UsbPort(usb_info) => {
+				info.port_type = SerialPortKind::Usb;
+				info.vid = Some(usb_info.vid);
+				info.pid = Some(usb_info.pid);
+				info.serial_number = usb_info.serial_number;
+				info.manufacturer = usb_info.manufacturer;
+				info.product = usb_info.product;
+				info.is_compatible = is_compatible_dongle(&info);
+			},
+			serialport::SerialPortType::PciPort => { info.port_type = SerialPortKind::Pci },
+			serialport::SerialPortType::BluetoothPort => { info.port_type = SerialPortKind::Bluetooth },
+			serialport::SerialPortType::Unknown => {},
+		}
+
+		return info;
+	}
+
+	// Best human-readable label, used where only one string fits (e.g. a dropdown)
+	pub fn description(&self) -> String {
+		return match (&self.manufacturer, &self.product) {
+			(_, Some(product)) => { product.clone() },
+			(Some(manufacturer), None) => { manufacturer.clone() },
+			(None, None) => {
+				match self.port_type {
+					SerialPortKind::Usb => { format!("USB device {:04x}:{:04x}", self.vid.unwrap_or(0), self.pid.unwrap_or(0)) },
+					SerialPortKind::Pci => { String::from("PCI serial port") },
+					SerialPortKind::Bluetooth => { String::from("Bluetooth serial port") },
+					SerialPortKind::Unknown => { String::from("") },
+				}
+			},
+		};
+	}
+}
+
+// Snapshot of the available ports, tagged with a generation that only changes when the ports do
+#[derive(Clone, Debug, Default)]
+pub struct SerialPortList {
+	pub ports: Vec<SerialPortInfo>,
+	pub generation: u64,
+}
+
+static LAST_ENUMERATION: Mutex<(Vec<SerialPortInfo>, u64)> = Mutex::new((Vec::new(), 0));
+
+fn track_generation(ports: &[SerialPortInfo]) -> u64 {
+	let mut last_enumeration = LAST_ENUMERATION.lock().unwrap();
+	if last_enumeration.0 != ports {
+		last_enumeration.0 = ports.to_vec();
+		last_enumeration.1 += 1;
+	}
+	return last_enumeration.1;
+}
+
+// Orders digit runs by value, so that COM2 comes before COM10
+fn natural_cmp(left: &str, right: &str) -> Ordering {
+	let mut left_chars = left.chars().peekable();
+	let mut right_chars = right.chars().peekable();
+
+	loop {
+		match (left_chars.peek().copied(), right_chars.peek().copied()) {
+			(None, None) => { return Ordering::Equal },
+			(None, Some(_)) => { return Ordering::Less },
+			(Some(_), None) => { return Ordering::Greater },
+			(Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+				let mut left_number = String::new();
+				while let Some(digit) = left_chars.next_if(char::is_ascii_digit) {
+					left_number.push(digit);
+				}
+				let mut right_number = String::new();
+				while let Some(digit) = right_chars.next_if(char::is_ascii_digit) {
+					right_number.push(digit);
+				}
+
+				let left_number = left_number.trim_start_matches('0');
+				let right_number = right_number.trim_start_matches('0');
+				let ordering = left_number.len().cmp(&right_number.len()).then_with(|| left_number.cmp(right_number));
+				if ordering != Ordering::Equal {
+					return ordering;
+				}
+			},
+			(Some(l), Some(r)) => {
+				if l != r {
+					return l.cmp(&r);
+				}
+				left_chars.next();
+				right_chars.next();
+			},
+		}
+	}
+}
+
+fn is_compatible_dongle(info: &SerialPortInfo) -> bool {
+	if let (Some(vid), Some(pid)) = (info.vid, info.pid) {
+		if COMPATIBLE_DONGLES.contains(&(vid, pid)) {
+			return true;
+		}
+	}
+	if let Some(product) = &info.product {
+		return COMPATIBLE_PRODUCT_NAMES.iter().any(|name| product.contains(name));
+	}
+	return false;
+}
+
+// What identifies a USB dongle across unplug/replug, even if the OS gives it another port name
+#[derive(Clone, Debug, PartialEq)]
+struct DeviceIdentity {
+	vid: u16,
+	pid: u16,
+	serial_number: Option<String>,
+}
+
+impl DeviceIdentity {
+	fn of(info: &SerialPortInfo) -> Option<Self> {
+		if let (Some(vid), Some(pid)) = (info.vid, info.pid) {
+			return Some(Self { vid, pid, serial_number: info.serial_number.clone() });
+		}
+		return None;
+	}
+
+	fn of_port(port_name: &str) -> Option<Self> {
+		let ports = SerialBackend::get_available_serial_ports().ok()?;
+		return ports.iter().find(|info| info.name == port_name).and_then(Self::of);
+	}
+
+	fn find_port(&self, previous_port_name: &str) -> Option<String> {
+		return self.find_port_in(SerialBackend::get_available_serial_ports().ok()?, previous_port_name);
+	}
+
+	// Identical dongles without a serial number share the same identity: the port name it had before is the best guess
+	fn find_port_in(&self, ports: Vec<SerialPortInfo>, previous_port_name: &str) -> Option<String> {
+		let matching_ports: Vec<String> = ports.into_iter().filter(|info| Self::of(info).as_ref() == Some(self)).map(|info| info.name).collect();
+
+		if matching_ports.iter().any(|name| name == previous_port_name) {
+			return Some(previous_port_name.to_owned());
+		}
+		return matching_ports.into_iter().next();
+	}
+}
+
+// The dongle firmware runs at DEFAULT_BAUD_RATE, other values are for other devices
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SerialOptions {
+	pub baud_rate: u32,
+	// Longest silence allowed between two bytes of a frame
+	pub rx_timeout: Duration,
+}
+
+impl Default for SerialOptions {
+	fn default() -> Self {
+		return Self { baud_rate: DEFAULT_BAUD_RATE, rx_timeout: SERIAL_RX_TIMEOUT };
+	}
+}
+
+fn open_serial_port(port_name: &str, baud_rate: u32) -> Result<Box<dyn serialport::SerialPort>, String> {
+	return serialport::new(port_name, baud_rate).open().map_err(|e| e.to_string());
+}
+
+// Frame being received into SerialBackend.frame, kept across receive() calls until it is complete or the rx timeout expires
+struct PartialFrame {
+	expected_len: usize,
+	received_len: usize,
+	last_received_ts: Instant,
+}
+
 pub struct SerialBackend {
 	port_name: String,
+	options: SerialOptions,
 	serial_port: Option<Box<dyn serialport::SerialPort>>,
+	device_identity: Option<DeviceIdentity>,
+	// Set while the port is lost and looked for
+	last_reconnect_attempt: Option<Instant>,
+	partial_frame: Option<PartialFrame>,
+	frame: [u8; MAXIMUM_DATA_LEN],
+	statistics: StatisticsRecorder,
+	// The rx timeout is evaluated against it, reconnection against the wall clock
+	clock: Clock,
 }
 
 impl SerialBackend {
-	pub fn new(port_name: &str) -> Self {
-		return Self { port_name: port_name.to_owned(), serial_port: None };
+	pub fn new(port_name: &str, options: &SerialOptions, statistics: StatisticsRecorder, clock: Clock) -> Self {
+		return Self { port_name: port_name.to_owned(), options: *options, serial_port: None, device_identity: None, last_reconnect_attempt: None, partial_frame: None, frame: [0; MAXIMUM_DATA_LEN], statistics, clock };
 	}
 
-	pub fn get_available_serial_ports() -> Result<HashMap<String, String>, String> {
+	fn connection_lost(&mut self, error: &str) {
+		log_info!("Lost serial port '{}' ({}), waiting for it to come back", self.port_name, error);
+
+		self.serial_port = None;
+		self.last_reconnect_attempt = Some(Instant::now());
+		self.partial_frame = None;
+	}
+
+	fn try_to_reconnect(&mut self) {
+		if let Some(last_reconnect_attempt) = self.last_reconnect_attempt {
+			if last_reconnect_attempt.elapsed() < RECONNECT_SCAN_INTERVAL {
+				return;
+			}
+			self.last_reconnect_attempt = Some(Instant::now());
+
+			// Non-USB ports have nothing better than their name to be recognized with
+			let port_name = match &self.device_identity {
+				Some(device_identity) => { device_identity.find_port(&self.port_name) },
+				None => { Some(self.port_name.clone()) },
+			};
+
+			if let Some(port_name) = port_name {
+				if let Ok(serial_port) = open_serial_port(&port_name, self.options.baud_rate) {
+					log_info!("Reconnected serial port '{}'", port_name);
+
+					self.port_name = port_name;
+					self.serial_port = Some(serial_port);
+					self.last_reconnect_attempt = None;
+				}
+			}
+		}
+	}
+
+	// Sorted by name, so that the order does not depend on how the OS enumerates ports
+	pub fn get_available_serial_ports() -> Result<Vec<SerialPortInfo>, String> {
 		match serialport::available_ports() {
 			Ok(ports) => {
-				return Ok(HashMap::from_iter(ports.into_iter().map(|port| {
-					let name: String = port.port_name;
-					let description: String = match port.port_type {
-						serialport::SerialPortType::UsbPort(info) => {
-							match info.product {
-								Some(string) => { string },
-								None => { String::from("") },
-							}
-						},
-						_ => { String::from("") },
-					};
-					return (name, description);
-				})));
+				let mut ports: Vec<SerialPortInfo> = ports.into_iter().map(SerialPortInfo::from_serialport).collect();
+				ports.sort_by(|left, right| natural_cmp(&left.name, &right.name).then_with(|| left.name.cmp(&right.name)));
+				return Ok(ports);
 			},
 			Err(e) => {
 				return Err(e.to_string());
@@ -40,6 +279,12 @@ impl SerialBackend {
 		}
 	}
 
+	pub fn get_serial_port_list() -> Result<SerialPortList, String> {
+		let ports = Self::get_available_serial_ports()?;
+		let generation = track_generation(&ports);
+		return Ok(SerialPortList { ports, generation });
+	}
+
 	fn do_send(&mut self, data: &[u8]) -> Result<(), String> {
 		let serial_port = self.serial_port.as_mut().unwrap();
 		
@@ -54,108 +299,150 @@ impl SerialBackend {
 						return Ok(());
 					},
 					Err(e) => {
-						return Err(format!("Fail on flush: {}", e.to_string()));
+						return Err(format!("Fail on flush: {}", e));
 					},
 				}
 			},
 			Err(e) => {
-				return Err(format!("Fail on write_all: {}", e.to_string()));
+				return Err(format!("Fail on write_all: {}", e));
 			},
 		}
 	}
 
-	fn do_receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+	// Returns None as soon as no byte is available, a partial frame waits for the next call.
+	// Complete frames are left in self.frame, their length is returned.
+	fn do_receive(&mut self) -> Result<Option<usize>, String> {
 		let serial_port = self.serial_port.as_mut().unwrap();
-		let mut buffer: Vec<u8> = Vec::with_capacity(MAXIMUM_DATA_LEN);
-		let mut expected_len: usize = 0;
-		let mut last_received_ts: Instant = Instant::now();
 
 		loop {
 			match serial_port.bytes_to_read() {
+				Ok(0) => {
+					if self.partial_frame.as_ref().is_some_and(|partial_frame| self.clock.now().duration_since(partial_frame.last_received_ts) > self.options.rx_timeout) {
+						// Header received but the rx timeout expired before the end of the frame
+						self.partial_frame = None;
+						self.statistics.record_lost_frames(1);
+					}
+					return Ok(None);
+				},
 				Ok(available_bytes) => {
-					if available_bytes > 0 {
-						// Receive size header
-						if expected_len == 0 {
-							let mut len_buf: Vec<u8> = vec![0];
-							
-							if let Err(e) = serial_port.read(&mut len_buf) {
-								return Err(format!("Fail on read: {}", e.to_string()));
-							}
-							else {
-								expected_len = len_buf[0] as usize;
-							}
+					if let Some(partial_frame) = &mut self.partial_frame {
+						// Receive actual data, without reading past the end of this frame
+						let start = partial_frame.received_len;
+						let wanted_len = (available_bytes as usize).min(partial_frame.expected_len - start);
+
+						match serial_port.read(&mut self.frame[start..start + wanted_len]) {
+							Ok(read_len) => {
+								partial_frame.received_len += read_len;
+							},
+							Err(e) => {
+								return Err(format!("Fail on read: {}", e));
+							},
 						}
-						// Receive actual data
-						else {
-							let start = buffer.len();
-							buffer.extend(vec![0; available_bytes as usize]);
-							let stop = buffer.len();
-							
-							if let Err(e) = serial_port.read(&mut buffer[start..stop]) {
-								return Err(format!("Fail on read: {}", e.to_string()));
-							}
-
-							last_received_ts = Instant::now();
-
-							if buffer.len() >= expected_len {
-								break;
-							}
+
+						partial_frame.last_received_ts = self.clock.now();
+
+						if partial_frame.received_len == partial_frame.expected_len {
+							return Ok(self.partial_frame.take().map(|partial_frame| partial_frame.expected_len));
 						}
 					}
-					else if expected_len == 0 || last_received_ts.elapsed() > SERIAL_RX_TIMEOUT {
-						break;
+					// Receive size header
+					else {
+						let mut len_buf = [0u8; 1];
+
+						if let Err(e) = serial_port.read(&mut len_buf) {
+							return Err(format!("Fail on read: {}", e));
+						}
+
+						// Empty frames are never sent, skip this header
+						if len_buf[0] == 0 {
+							self.statistics.record_framing_error();
+						}
+						else {
+							self.partial_frame = Some(PartialFrame { expected_len: len_buf[0] as usize, received_len: 0, last_received_ts: self.clock.now() });
+						}
 					}
 				},
 				Err(e) => {
-					return Err(format!("Fail on bytes_to_read: {}", e.to_string()));
+					return Err(format!("Fail on bytes_to_read: {}", e));
 				},
 			}
 		}
-
-		if buffer.len() > 0 && buffer.len() == expected_len {
-			return Ok(Some(buffer));
-		}
-
-		return Ok(None);
 	}
 }
 
 impl IOHandler for SerialBackend {
 	fn enable(&mut self) -> Result<(), String> {
 		if self.serial_port.is_none() {
-			println!("~~~NTiR-11~~~: Enabling serial port '{}' with SERIAL_RX_TIMEOUT set to '{:?}'", self.port_name, SERIAL_RX_TIMEOUT);
+			log_info!("Enabling serial port '{}' at {} baud with rx timeout set to '{:?}'", self.port_name, self.options.baud_rate, self.options.rx_timeout);
 
-			match serialport::new(&self.port_name, 115200).open() {
-				Ok(serial_port) => {
-					self.serial_port = Some(serial_port);
-				},
-				Err(e) => {
-					return Err(e.to_string());
-				},
-			}
+			self.serial_port = Some(open_serial_port(&self.port_name, self.options.baud_rate)?);
+			self.device_identity = DeviceIdentity::of_port(&self.port_name);
+			self.last_reconnect_attempt = None;
 		}
 		return Ok(());
 	}
 
 	fn disable(&mut self) {
-		if self.serial_port.is_some() {
-			println!("~~~NTiR-11~~~: Disabling serial port");
+		if self.serial_port.is_some() || self.last_reconnect_attempt.is_some() {
+			log_info!("Disabling serial port");
 
 			self.serial_port = None;
+			self.last_reconnect_attempt = None;
+			self.partial_frame = None;
 		}
 	}
 
 	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		self.try_to_reconnect();
+
 		if self.serial_port.is_some() {
-			return self.do_send(data);
+			let ret = self.do_send(data);
+			if let Err(error) = &ret {
+				self.connection_lost(error);
+			}
+			return ret;
 		}
-		return Ok(());
+		else if self.last_reconnect_attempt.is_some() {
+			return Err(format!("Serial port '{}' is disconnected", self.port_name));
+		}
+		return Err(format!("Serial port '{}' is not open", self.port_name));
 	}
 
 	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		let mut buffer = [0u8; MAXIMUM_DATA_LEN];
+		return Ok(self.receive_into(&mut buffer)?.map(|len| buffer[..len].to_vec()));
+	}
+
+	// Losing the port is reported through state(), not as a receive error
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		self.try_to_reconnect();
+
 		if self.serial_port.is_some() {
-			return self.do_receive();
+			match self.do_receive() {
+				Ok(Some(len)) => {
+					return Ok(Some(copy_frame(&self.frame[..len], buffer)?));
+				},
+				Ok(None) => {
+					return Ok(None);
+				},
+				Err(error) => {
+					self.connection_lost(&error);
+				},
+			}
 		}
 		return Ok(None);
 	}
+
+	fn state(&self) -> ConnectionState {
+		if self.serial_port.is_some() {
+			return ConnectionState::Connected;
+		}
+		else if self.last_reconnect_attempt.is_some() {
+			return ConnectionState::Reconnecting;
+		}
+		return ConnectionState::Disabled;
+	}
 }
+
+#[cfg(all(test, unix))]
+mod tests;
@@ -0,0 +1,383 @@
+// Drives SerialBackend against the slave side of a pseudo-terminal,
+// while the tests play the dongle on the master side.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::pty::openpty;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::ttyname;
+
+use super::{is_compatible_dongle, natural_cmp, DeviceIdentity, SerialBackend, SerialOptions, SerialPortInfo, SerialPortKind, RECONNECT_SCAN_INTERVAL};
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::clock::Clock;
+use super::super::super::ConnectionState;
+use super::super::super::SERIAL_RX_TIMEOUT;
+
+struct VirtualSerialPort {
+	master: File,
+	// Kept open so that the master side does not hang up between backend reopenings
+	_slave: OwnedFd,
+	slave_path: PathBuf,
+}
+
+impl VirtualSerialPort {
+	fn new() -> Self {
+		let pty = openpty(None, None).expect("openpty failed");
+		let master = unsafe { File::from_raw_fd(pty.master) };
+		let slave = unsafe { OwnedFd::from_raw_fd(pty.slave) };
+
+		let mut termios = tcgetattr(pty.slave).expect("tcgetattr failed");
+		cfmakeraw(&mut termios);
+		tcsetattr(pty.slave, SetArg::TCSANOW, &termios).expect("tcsetattr failed");
+
+		let slave_path = ttyname(pty.slave).expect("ttyname failed");
+		return Self { master, _slave: slave, slave_path };
+	}
+
+	fn open_backend(&self) -> (SerialBackend, StatisticsRecorder) {
+		return self.open_backend_with_clock(Clock::new());
+	}
+
+	fn open_backend_with_clock(&self, clock: Clock) -> (SerialBackend, StatisticsRecorder) {
+		return self.open_backend_with_options(&SerialOptions::default(), clock);
+	}
+
+	fn open_backend_with_options(&self, options: &SerialOptions, clock: Clock) -> (SerialBackend, StatisticsRecorder) {
+		let statistics = StatisticsRecorder::new();
+		let mut backend = SerialBackend::new(self.slave_path.to_str().unwrap(), options, statistics.clone(), clock);
+		backend.enable().expect("enable failed");
+		return (backend, statistics);
+	}
+
+	fn dongle_write(&mut self, bytes: &[u8]) {
+		self.master.write_all(bytes).unwrap();
+		self.master.flush().unwrap();
+	}
+
+	fn dongle_read_frame(&mut self) -> Vec<u8> {
+		let mut len_buf = [0u8; 1];
+		self.master.read_exact(&mut len_buf).unwrap();
+
+		let mut frame = vec![0u8; len_buf[0] as usize];
+		self.master.read_exact(&mut frame).unwrap();
+		return frame;
+	}
+
+	// Simulated dongle echoing back every frame it is sent, until the pty is closed
+	fn spawn_echo_dongle(&self) {
+		let mut master = self.master.try_clone().unwrap();
+
+		thread::spawn(move || {
+			let mut len_buf = [0u8; 1];
+			while master.read_exact(&mut len_buf).is_ok() {
+				let mut frame = vec![0u8; len_buf[0] as usize];
+				if master.read_exact(&mut frame).is_err() {
+					break;
+				}
+
+				let mut reply = vec![len_buf[0]];
+				reply.extend(frame.iter().map(|byte| byte.wrapping_add(1)));
+				if master.write_all(&reply).is_err() {
+					break;
+				}
+			}
+		});
+	}
+}
+
+fn receive_within(backend: &mut SerialBackend, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = backend.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[test]
+fn send_prefixes_frame_with_length() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, _) = port.open_backend();
+
+	backend.send(&[0x01, 0x02, 0x03]).unwrap();
+
+	assert_eq!(port.dongle_read_frame(), vec![0x01, 0x02, 0x03]);
+}
+
+#[test]
+fn receive_complete_frame() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, statistics) = port.open_backend();
+
+	port.dongle_write(&[4, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+	assert_eq!(statistics.snapshot().frames_dropped, 0);
+}
+
+#[test]
+fn receive_nothing_when_idle() {
+	let port = VirtualSerialPort::new();
+	let (mut backend, _) = port.open_backend();
+
+	let start = Instant::now();
+	assert_eq!(backend.receive().unwrap(), None);
+	assert!(start.elapsed() < SERIAL_RX_TIMEOUT);
+}
+
+#[test]
+fn receive_back_to_back_frames_separately() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, statistics) = port.open_backend();
+
+	port.dongle_write(&[2, 0x10, 0x11, 3, 0x20, 0x21, 0x22]);
+
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x10, 0x11]));
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x20, 0x21, 0x22]));
+	assert_eq!(statistics.snapshot().framing_errors, 0);
+}
+
+#[test]
+fn receive_frame_split_across_writes() {
+	let port = VirtualSerialPort::new();
+	let (mut backend, _) = port.open_backend();
+	let mut master = port.master.try_clone().unwrap();
+
+	let writer = thread::spawn(move || {
+		master.write_all(&[4, 0x01]).unwrap();
+		thread::sleep(SERIAL_RX_TIMEOUT / 4);
+		master.write_all(&[0x02, 0x03]).unwrap();
+		thread::sleep(SERIAL_RX_TIMEOUT / 4);
+		master.write_all(&[0x04]).unwrap();
+	});
+
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x01, 0x02, 0x03, 0x04]));
+	writer.join().unwrap();
+}
+
+#[test]
+fn partial_frame_is_dropped_on_timeout() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, statistics) = port.open_backend();
+
+	port.dongle_write(&[5, 0x01, 0x02]);
+
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT * 5), None);
+	assert_eq!(statistics.snapshot().frames_dropped, 1);
+
+	// The link recovers with the next complete frame
+	port.dongle_write(&[1, 0x42]);
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x42]));
+}
+
+#[test]
+fn partial_frame_times_out_in_emulated_time() {
+	let mut port = VirtualSerialPort::new();
+	let clock = Clock::new();
+	clock.advance(Duration::ZERO);
+	let (mut backend, statistics) = port.open_backend_with_clock(clock.clone());
+
+	// The emulator is paused: no emulated time passes, however long the wait
+	port.dongle_write(&[3, 0x01]);
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT * 5), None);
+	port.dongle_write(&[0x02, 0x03]);
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x01, 0x02, 0x03]));
+
+	port.dongle_write(&[5, 0x01, 0x02]);
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT), None);
+	assert_eq!(statistics.snapshot().frames_dropped, 0);
+
+	clock.advance(SERIAL_RX_TIMEOUT * 2);
+	assert_eq!(backend.receive().unwrap(), None);
+	assert_eq!(statistics.snapshot().frames_dropped, 1);
+}
+
+#[test]
+fn rx_timeout_is_configurable() {
+	let mut port = VirtualSerialPort::new();
+	let clock = Clock::new();
+	clock.advance(Duration::ZERO);
+	let options = SerialOptions { rx_timeout: SERIAL_RX_TIMEOUT * 10, ..SerialOptions::default() };
+	let (mut backend, statistics) = port.open_backend_with_options(&options, clock.clone());
+
+	port.dongle_write(&[3, 0x01]);
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT), None);
+	clock.advance(SERIAL_RX_TIMEOUT * 5);
+	port.dongle_write(&[0x02, 0x03]);
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x01, 0x02, 0x03]));
+
+	port.dongle_write(&[2, 0x01]);
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT), None);
+	clock.advance(SERIAL_RX_TIMEOUT * 11);
+	assert_eq!(backend.receive().unwrap(), None);
+	assert_eq!(statistics.snapshot().frames_dropped, 1);
+}
+
+#[test]
+fn zero_length_headers_are_skipped() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, statistics) = port.open_backend();
+
+	port.dongle_write(&[0, 0, 2, 0xAB, 0xCD]);
+
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0xAB, 0xCD]));
+	assert_eq!(statistics.snapshot().framing_errors, 2);
+}
+
+#[test]
+fn garbage_is_dropped_then_link_recovers() {
+	let mut port = VirtualSerialPort::new();
+	let (mut backend, statistics) = port.open_backend();
+
+	// Line noise decoded as a 0xFF bytes long frame that never completes
+	port.dongle_write(&[0xFF, 0x13, 0x37]);
+	assert_eq!(receive_within(&mut backend, SERIAL_RX_TIMEOUT * 5), None);
+
+	port.dongle_write(&[3, 0x01, 0x02, 0x03]);
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x01, 0x02, 0x03]));
+	assert_eq!(statistics.snapshot().frames_dropped, 1);
+}
+
+#[test]
+fn round_trip_with_simulated_dongle() {
+	let port = VirtualSerialPort::new();
+	let (mut backend, _) = port.open_backend();
+	port.spawn_echo_dongle();
+
+	for frame in [vec![0x00], vec![0x10, 0x20, 0x30], vec![0xFE; 255]] {
+		backend.send(&frame).unwrap();
+
+		let expected: Vec<u8> = frame.iter().map(|byte| byte.wrapping_add(1)).collect();
+		assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(expected));
+	}
+
+	backend.disable();
+}
+
+#[test]
+fn lost_port_switches_to_reconnecting() {
+	let port = VirtualSerialPort::new();
+	let (mut backend, _) = port.open_backend();
+	assert_eq!(backend.state(), ConnectionState::Connected);
+
+	// Hanging up the pty is the closest thing to unplugging the dongle
+	drop(port);
+
+	assert!(backend.send(&[0x01]).is_err());
+	assert_eq!(backend.state(), ConnectionState::Reconnecting);
+	assert_eq!(backend.receive().unwrap(), None);
+
+	backend.disable();
+	assert_eq!(backend.state(), ConnectionState::Disabled);
+}
+
+#[test]
+fn lost_port_reconnects_when_it_comes_back() {
+	// A stable name for the port, like the one udev gives to a dongle, while the pty behind it changes
+	let port_name = env::temp_dir().join(format!("n3t1r-serial-test-{}", process::id()));
+	let _ = fs::remove_file(&port_name);
+	let first_port = VirtualSerialPort::new();
+	symlink(&first_port.slave_path, &port_name).unwrap();
+
+	let mut backend = SerialBackend::new(port_name.to_str().unwrap(), &SerialOptions::default(), StatisticsRecorder::new(), Clock::new());
+	backend.enable().unwrap();
+
+	// Unplugged
+	drop(first_port);
+	fs::remove_file(&port_name).unwrap();
+	assert!(backend.send(&[0x01]).is_err());
+	assert_eq!(backend.state(), ConnectionState::Reconnecting);
+	thread::sleep(RECONNECT_SCAN_INTERVAL);
+	assert_eq!(backend.receive().unwrap(), None);
+	assert_eq!(backend.state(), ConnectionState::Reconnecting);
+
+	// Plugged back in
+	let second_port = VirtualSerialPort::new();
+	symlink(&second_port.slave_path, &port_name).unwrap();
+	second_port.spawn_echo_dongle();
+
+	let start = Instant::now();
+	while backend.state() != ConnectionState::Connected && start.elapsed() < RECONNECT_SCAN_INTERVAL + TEST_TIMEOUT {
+		assert_eq!(backend.receive().unwrap(), None);
+		thread::sleep(Duration::from_millis(10));
+	}
+	assert_eq!(backend.state(), ConnectionState::Connected);
+
+	backend.send(&[0x01, 0x02]).unwrap();
+	assert_eq!(receive_within(&mut backend, TEST_TIMEOUT), Some(vec![0x02, 0x03]));
+
+	backend.disable();
+	fs::remove_file(&port_name).unwrap();
+}
+
+fn usb_port(name: &str, serial_number: Option<&str>) -> SerialPortInfo {
+	return SerialPortInfo { name: name.to_owned(), port_type: SerialPortKind::Usb, vid: Some(0x2E8A), pid: Some(0x000A), serial_number: serial_number.map(str::to_owned), manufacturer: None, product: None, is_compatible: true };
+}
+
+#[test]
+fn identical_dongles_reconnect_to_their_previous_port() {
+	let identity = DeviceIdentity::of(&usb_port("/dev/ttyACM1", None)).unwrap();
+	let ports = vec![usb_port("/dev/ttyACM0", None), usb_port("/dev/ttyACM1", None), usb_port("/dev/ttyACM2", Some("E66138935F2A"))];
+
+	assert_eq!(identity.find_port_in(ports.clone(), "/dev/ttyACM1"), Some(String::from("/dev/ttyACM1")));
+	// Renamed by the OS, any identical dongle will do
+	assert_eq!(identity.find_port_in(ports.clone(), "/dev/ttyACM5"), Some(String::from("/dev/ttyACM0")));
+	assert_eq!(identity.find_port_in(ports[2..].to_vec(), "/dev/ttyACM1"), None);
+
+	// A serial number tells them apart
+	let identity = DeviceIdentity::of(&usb_port("/dev/ttyACM7", Some("E66138935F2A"))).unwrap();
+	assert_eq!(identity.find_port_in(ports, "/dev/ttyACM7"), Some(String::from("/dev/ttyACM2")));
+}
+
+#[test]
+fn compatible_dongles_are_recognized_by_ids_or_product_name() {
+	let mut port = usb_port("/dev/ttyACM0", None);
+	assert!(is_compatible_dongle(&port));
+
+	// Arduino Uno, which the firmware does not run on
+	port.pid = Some(0x0043);
+	port.vid = Some(0x2341);
+	assert!(!is_compatible_dongle(&port));
+	port.product = Some(String::from("Generic NTiR-11 adapter"));
+	assert!(is_compatible_dongle(&port));
+
+	let other_port = SerialPortInfo { name: String::from("COM1"), port_type: SerialPortKind::Pci, vid: None, pid: None, serial_number: None, manufacturer: None, product: None, is_compatible: false };
+	assert!(!is_compatible_dongle(&other_port));
+}
+
+#[test]
+fn description_falls_back_on_what_is_known() {
+	let mut port = usb_port("/dev/ttyACM0", None);
+	assert_eq!(port.description(), "USB device 2e8a:000a");
+	port.manufacturer = Some(String::from("Raspberry Pi"));
+	assert_eq!(port.description(), "Raspberry Pi");
+	port.product = Some(String::from("Pico"));
+	assert_eq!(port.description(), "Pico");
+
+	let mut other_port = SerialPortInfo { name: String::from("COM1"), port_type: SerialPortKind::Pci, vid: None, pid: None, serial_number: None, manufacturer: None, product: None, is_compatible: false };
+	assert_eq!(other_port.description(), "PCI serial port");
+	other_port.port_type = SerialPortKind::Bluetooth;
+	assert_eq!(other_port.description(), "Bluetooth serial port");
+	other_port.port_type = SerialPortKind::Unknown;
+	assert_eq!(other_port.description(), "");
+}
+
+#[test]
+fn port_names_sort_naturally() {
+	let mut names = vec!["COM10", "/dev/ttyUSB1", "COM2", "/dev/ttyACM0", "COM1", "/dev/ttyUSB10", "/dev/ttyUSB2"];
+	names.sort_by(|left, right| natural_cmp(left, right));
+
+	assert_eq!(names, vec!["/dev/ttyACM0", "/dev/ttyUSB1", "/dev/ttyUSB2", "/dev/ttyUSB10", "COM1", "COM2", "COM10"]);
+}
@@ -0,0 +1,293 @@
+// Link between two peers of this machine through a SOCK_SEQPACKET Unix socket, which keeps frame boundaries.
+// Each peer listens on a socket of the room directory, the one whose socket name sorts first connects to the other.
+// Once paired, both stop listening and remove their socket, so that the room is free again.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use libc::c_void;
+
+use super::super::traits::{copy_frame, IOHandler};
+use super::super::statistics::StatisticsRecorder;
+use super::super::rendezvous::create_room_directory;
+use super::super::ConnectionState;
+use super::super::MAXIMUM_DATA_LEN;
+
+const SOCKET_EXTENSION: &str = "sock";
+// Every packet starts with it, a zero-length read meaning that the peer closed the connection
+const FRAME: u8 = 0x00;
+const HEADER_LEN: usize = 1;
+const LISTEN_BACKLOG: i32 = 4;
+
+#[cfg(not(target_vendor = "apple"))]
+const SEND_FLAGS: i32 = libc::MSG_NOSIGNAL;
+// SO_NOSIGPIPE is set on the socket instead
+#[cfg(target_vendor = "apple")]
+const SEND_FLAGS: i32 = 0;
+
+// Handlers of the same process get sockets of their own
+static NEXT_SOCKET_ID: AtomicU32 = AtomicU32::new(0);
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+	if ret < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	return Ok(ret);
+}
+
+fn socket_address(path: &Path) -> Result<(libc::sockaddr_un, libc::socklen_t), String> {
+	let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+	address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+	let bytes = path.as_os_str().as_bytes();
+	// Room for the terminating null byte
+	if bytes.len() >= address.sun_path.len() {
+		return Err(format!("Socket path too long ({} bytes, at most {}): '{}'", bytes.len(), address.sun_path.len() - 1, path.display()));
+	}
+	for (target, byte) in address.sun_path.iter_mut().zip(bytes) {
+		*target = *byte as libc::c_char;
+	}
+	return Ok((address, (mem::offset_of!(libc::sockaddr_un, sun_path) + bytes.len() + 1) as libc::socklen_t));
+}
+
+// Non-blocking, and closed on exec
+fn configure_socket(fd: RawFd) -> io::Result<()> {
+	let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+	check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+	check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+	#[cfg(target_vendor = "apple")]
+	{
+		let enabled: libc::c_int = 1;
+		check(unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_NOSIGPIPE, &enabled as *const libc::c_int as *const c_void, mem::size_of::<libc::c_int>() as libc::socklen_t) })?;
+	}
+	return Ok(());
+}
+
+fn new_socket() -> io::Result<OwnedFd> {
+	let fd = unsafe { OwnedFd::from_raw_fd(check(libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0))?) };
+	configure_socket(fd.as_raw_fd())?;
+	return Ok(fd);
+}
+
+fn listen(path: &Path) -> Result<OwnedFd, String> {
+	let (address, address_len) = socket_address(path)?;
+	let listener = new_socket().map_err(|e| format!("Fail on socket: {}", e))?;
+
+	// Left behind by a handler of this process that was not dropped
+	let _ = fs::remove_file(path);
+	check(unsafe { libc::bind(listener.as_raw_fd(), &address as *const libc::sockaddr_un as *const libc::sockaddr, address_len) })
+		.map_err(|e| format!("Fail on bind '{}': {}", path.display(), e))?;
+	check(unsafe { libc::listen(listener.as_raw_fd(), LISTEN_BACKLOG) }).map_err(|e| format!("Fail on listen: {}", e))?;
+	return Ok(listener);
+}
+
+fn accept(listener: &OwnedFd) -> io::Result<OwnedFd> {
+	let connection = unsafe { OwnedFd::from_raw_fd(check(libc::accept(listener.as_raw_fd(), std::ptr::null_mut(), std::ptr::null_mut()))?) };
+	configure_socket(connection.as_raw_fd())?;
+	return Ok(connection);
+}
+
+fn connect(path: &Path) -> io::Result<OwnedFd> {
+	let (address, address_len) = socket_address(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+	let connection = new_socket()?;
+	check(unsafe { libc::connect(connection.as_raw_fd(), &address as *const libc::sockaddr_un as *const libc::sockaddr, address_len) })?;
+	return Ok(connection);
+}
+
+pub struct UnixSocketBackend {
+	rendezvous_folder: PathBuf,
+	room_name: String,
+	room_directory: PathBuf,
+	socket_path: PathBuf,
+	// Set while waiting for a peer
+	listener: Option<OwnedFd>,
+	connection: Option<OwnedFd>,
+	// One extra byte to detect packets that are too long
+	packet: Box<[u8; HEADER_LEN + MAXIMUM_DATA_LEN + 1]>,
+	statistics: StatisticsRecorder,
+}
+
+impl UnixSocketBackend {
+	pub fn new(rendezvous_folder: &Path, room_name: &str, statistics: StatisticsRecorder) -> Self {
+		let room_directory = rendezvous_folder.join(room_name);
+		let socket_name = format!("{}-{}.{}", process::id(), NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed), SOCKET_EXTENSION);
+		let socket_path = room_directory.join(socket_name);
+
+		return Self { rendezvous_folder: rendezvous_folder.to_owned(), room_name: room_name.to_owned(), room_directory, socket_path, listener: None, connection: None, packet: Box::new([0; HEADER_LEN + MAXIMUM_DATA_LEN + 1]), statistics };
+	}
+
+	// Sockets of the room that this peer is to connect to
+	fn peer_sockets(&self) -> Vec<PathBuf> {
+		let mut peer_sockets = Vec::new();
+
+		if let Ok(entries) = fs::read_dir(&self.room_directory) {
+			for entry in entries.flatten() {
+				let path = entry.path();
+				if entry.file_type().is_ok_and(|file_type| file_type.is_socket()) && path.file_name() > self.socket_path.file_name() {
+					peer_sockets.push(path);
+				}
+			}
+		}
+		peer_sockets.sort();
+		return peer_sockets;
+	}
+
+	// Accepts a peer, or connects to one, while none is connected
+	pub fn poll(&mut self) -> Result<(), String> {
+		if self.connection.is_some() {
+			return Ok(());
+		}
+		if let Some(listener) = &self.listener {
+			match accept(listener) {
+				Ok(connection) => {
+					self.paired(connection, "Accepted");
+					return Ok(());
+				},
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+				Err(e) => {
+					return Err(format!("Fail on accept: {}", e));
+				},
+			}
+		}
+
+		for peer_socket in self.peer_sockets() {
+			match connect(&peer_socket) {
+				Ok(connection) => {
+					self.paired(connection, "Connected to");
+					return Ok(());
+				},
+				// Nobody listens on it anymore: its peer crashed, or paired with another one
+				Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+					let _ = fs::remove_file(&peer_socket);
+				},
+				// Backlog full, or still connecting: try again on the next poll
+				Err(_) => {},
+			}
+		}
+		return Ok(());
+	}
+
+	fn paired(&mut self, connection: OwnedFd, how: &str) {
+		log_info!("{} local peer through unix:{} in room '{}'", how, self.socket_path.display(), self.room_name);
+
+		self.connection = Some(connection);
+		self.stop_listening();
+	}
+
+	fn stop_listening(&mut self) {
+		if self.listener.take().is_some() {
+			let _ = fs::remove_file(&self.socket_path);
+		}
+	}
+
+	// Waits for another peer, e.g. when the peer closed its emulator
+	fn connection_lost(&mut self, error: &str) {
+		log_info!("Lost local peer in room '{}' ({}), waiting for another one", self.room_name, error);
+
+		self.connection = None;
+		match listen(&self.socket_path) {
+			Ok(listener) => { self.listener = Some(listener); },
+			Err(e) => { log_info!("Fail on listen again: {}", e); },
+		}
+	}
+}
+
+impl IOHandler for UnixSocketBackend {
+	fn enable(&mut self) -> Result<(), String> {
+		if self.listener.is_none() && self.connection.is_none() {
+			create_room_directory(&self.rendezvous_folder, &self.room_name).map_err(|e| format!("Fail on create room: {}", e))?;
+			self.listener = Some(listen(&self.socket_path)?);
+		}
+		return Ok(());
+	}
+
+	fn disable(&mut self) {
+		self.stop_listening();
+		self.connection = None;
+		// Only succeeds once the room is empty
+		let _ = fs::remove_dir(&self.room_directory);
+	}
+
+	// Frames sent while no peer is connected are dropped
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		self.poll()?;
+
+		if let Some(connection) = &self.connection {
+			let mut packet = Vec::with_capacity(HEADER_LEN + data.len());
+			packet.push(FRAME);
+			packet.extend_from_slice(data);
+
+			if unsafe { libc::send(connection.as_raw_fd(), packet.as_ptr() as *const c_void, packet.len(), SEND_FLAGS) } < 0 {
+				let error = io::Error::last_os_error();
+				self.statistics.record_dropped();
+				// The peer does not read fast enough
+				if error.kind() != io::ErrorKind::WouldBlock {
+					self.connection_lost(&error.to_string());
+				}
+			}
+		}
+		else {
+			self.statistics.record_dropped();
+		}
+		return Ok(());
+	}
+
+	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		let mut buffer = [0u8; MAXIMUM_DATA_LEN];
+		return Ok(self.receive_into(&mut buffer)?.map(|len| buffer[..len].to_vec()));
+	}
+
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		self.poll()?;
+
+		while let Some(connection) = &self.connection {
+			let len = unsafe { libc::recv(connection.as_raw_fd(), self.packet.as_mut_ptr() as *mut c_void, self.packet.len(), 0) };
+
+			if len < 0 {
+				let error = io::Error::last_os_error();
+				if error.kind() != io::ErrorKind::WouldBlock {
+					self.connection_lost(&error.to_string());
+				}
+				return Ok(None);
+			}
+			else if len == 0 {
+				self.connection_lost("closed by the peer");
+				return Ok(None);
+			}
+			else if len as usize > HEADER_LEN + MAXIMUM_DATA_LEN || self.packet[0] != FRAME {
+				self.statistics.record_framing_error();
+			}
+			else {
+				return Ok(Some(copy_frame(&self.packet[HEADER_LEN..len as usize], buffer)?));
+			}
+		}
+		return Ok(None);
+	}
+
+	fn state(&self) -> ConnectionState {
+		if self.connection.is_some() {
+			return ConnectionState::Connected;
+		}
+		else if self.listener.is_some() {
+			return ConnectionState::WaitingForPeer;
+		}
+		return ConnectionState::Disabled;
+	}
+}
+
+impl Drop for UnixSocketBackend {
+	fn drop(&mut self) {
+		self.disable();
+	}
+}
+
+#[cfg(test)]
+mod tests;
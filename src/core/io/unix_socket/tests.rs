@@ -0,0 +1,127 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use super::UnixSocketBackend;
+use super::super::super::traits::IOHandler;
+use super::super::super::statistics::StatisticsRecorder;
+use super::super::super::ConnectionState;
+use super::super::super::MAXIMUM_DATA_LEN;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+const ROOM: &str = "room";
+
+// Unique to the test, so that parallel runs do not collide
+fn test_folder(name: &str) -> PathBuf {
+	return env::temp_dir().join(format!("n3t1r-unix-socket-test-{}-{}", process::id(), name));
+}
+
+fn open_backend(folder: &Path) -> UnixSocketBackend {
+	let mut backend = UnixSocketBackend::new(folder, ROOM, StatisticsRecorder::new());
+	backend.enable().unwrap();
+	return backend;
+}
+
+// Polls all of them until the expected number is connected, or the timeout elapses
+fn poll_until_connected(backends: &mut [&mut UnixSocketBackend], expected: usize) -> usize {
+	let start = Instant::now();
+	loop {
+		for backend in backends.iter_mut() {
+			backend.poll().unwrap();
+		}
+		let connected = backends.iter().filter(|backend| backend.state() == ConnectionState::Connected).count();
+		if connected >= expected || start.elapsed() > TEST_TIMEOUT {
+			return connected;
+		}
+	}
+}
+
+fn receive_within(backend: &mut UnixSocketBackend, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = backend.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+fn room_sockets(folder: &Path) -> usize {
+	return fs::read_dir(folder.join(ROOM)).map_or(0, |entries| entries.flatten().filter(|entry| entry.file_type().unwrap().is_socket()).count());
+}
+
+#[test]
+fn frames_keep_their_boundaries() {
+	let folder = test_folder("boundaries");
+	let (mut left, mut right) = (open_backend(&folder), open_backend(&folder));
+	assert_eq!(left.state(), ConnectionState::WaitingForPeer);
+	assert_eq!(room_sockets(&folder), 2);
+
+	assert_eq!(poll_until_connected(&mut [&mut left, &mut right], 2), 2);
+	assert_eq!(room_sockets(&folder), 0);
+
+	// Empty frames are resync requests
+	for data in [vec![0x01], vec![0xFF; MAXIMUM_DATA_LEN], Vec::new(), vec![0x02, 0x03]] {
+		left.send(&data).unwrap();
+		assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(data.clone()));
+		right.send(&data).unwrap();
+		assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(data));
+	}
+
+	drop((left, right));
+	assert!(!folder.join(ROOM).exists());
+	let _ = fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn peers_left_alone_pair_with_the_next_one() {
+	let folder = test_folder("pairs");
+	let mut backends = [open_backend(&folder), open_backend(&folder), open_backend(&folder)];
+	let [first, second, third] = &mut backends;
+	assert_eq!(poll_until_connected(&mut [first, second, third], 2), 2);
+
+	// Only one of them is left waiting, and only its socket remains in the room
+	let waiting = backends.iter().position(|backend| backend.state() == ConnectionState::WaitingForPeer).unwrap();
+	assert_eq!(room_sockets(&folder), 1);
+
+	let mut backends: Vec<UnixSocketBackend> = backends.into_iter().collect();
+	let mut waiting_backend = backends.remove(waiting);
+	let mut abandoned_backend = backends.pop().unwrap();
+	drop(backends);
+
+	// The abandoned peer notices, and listens again
+	assert_eq!(receive_within(&mut abandoned_backend, TEST_TIMEOUT / 10), None);
+	assert_eq!(poll_until_connected(&mut [&mut waiting_backend, &mut abandoned_backend], 2), 2);
+
+	waiting_backend.send(&[0x42]).unwrap();
+	assert_eq!(receive_within(&mut abandoned_backend, TEST_TIMEOUT), Some(vec![0x42]));
+
+	drop((waiting_backend, abandoned_backend));
+	let _ = fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn stale_sockets_are_removed() {
+	let folder = test_folder("stale");
+	let mut backend = open_backend(&folder);
+
+	// Left behind by a crashed peer, nobody listens on it anymore
+	let stale_socket = folder.join(ROOM).join("zzz.sock");
+	drop(UnixListener::bind(&stale_socket).unwrap());
+	assert!(stale_socket.exists());
+
+	backend.poll().unwrap();
+	assert!(!stale_socket.exists());
+	assert_eq!(backend.state(), ConnectionState::WaitingForPeer);
+
+	// Frames sent without peer are dropped
+	backend.send(&[0x01]).unwrap();
+	assert_eq!(backend.statistics.snapshot().frames_dropped, 1);
+
+	drop(backend);
+	let _ = fs::remove_dir_all(&folder);
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Process-wide, like the stderr it prints to, so that it does not mix with the output of the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+	Quiet,
+	// Connection events
+	Info,
+	// Every frame sent and received
+	Debug,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Debug as u8);
+
+impl LogLevel {
+	pub fn parse(name: &str) -> Option<Self> {
+		return match name.to_ascii_lowercase().as_str() {
+			"quiet" => { Some(Self::Quiet) },
+			"info" => { Some(Self::Info) },
+			"debug" => { Some(Self::Debug) },
+			_ => { None },
+		};
+	}
+}
+
+pub fn set_log_level(level: LogLevel) {
+	LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+	return match LOG_LEVEL.load(Ordering::Relaxed) {
+		0 => { LogLevel::Quiet },
+		1 => { LogLevel::Info },
+		_ => { LogLevel::Debug },
+	};
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+	return level <= log_level();
+}
+
+macro_rules! log_info {
+	($($arg:tt)*) => {
+		if $crate::core::log::is_enabled($crate::core::log::LogLevel::Info) {
+			eprintln!("~~~NTiR-11~~~: {}", format_args!($($arg)*));
+		}
+	};
+}
+
+macro_rules! log_debug {
+	($($arg:tt)*) => {
+		if $crate::core::log::is_enabled($crate::core::log::LogLevel::Debug) {
+			eprintln!($($arg)*);
+		}
+	};
+}
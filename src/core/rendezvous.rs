@@ -1,12 +1,96 @@
 use std::env;
+use std::ffi::OsString;
 use std::fs;
+use std::io;
 use std::path;
 use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+const RDV_DIR: &str = "ntir11-rdv";
 
-fn get_rendezvous_folder() -> path::PathBuf {
-	const RDV_DIR: &str = "ntir11-rdv";
-	return env::temp_dir().join(RDV_DIR);
+// Handlers of the same process get advertisements of their own
+static NEXT_ADVERTISEMENT_ID: AtomicU32 = AtomicU32::new(0);
+
+// XDG_RUNTIME_DIR is private to the user already, the temporary directory may be shared by every user
+fn rendezvous_folder_in(runtime_dir: Option<OsString>) -> path::PathBuf {
+	if let Some(runtime_dir) = runtime_dir.filter(|runtime_dir| !runtime_dir.is_empty()) {
+		return path::PathBuf::from(runtime_dir).join(RDV_DIR);
+	}
+	return env::temp_dir().join(user_namespace());
+}
+
+#[cfg(unix)]
+fn user_namespace() -> String {
+	return format!("{}-{}", RDV_DIR, unsafe { libc::getuid() });
+}
+
+// The temporary directory is per-user already
+#[cfg(not(unix))]
+fn user_namespace() -> String {
+	return String::from(RDV_DIR);
+}
+
+// Used unless another folder is given, peers only meet in rooms of the same folder
+pub fn default_rendezvous_folder() -> path::PathBuf {
+	return rendezvous_folder_in(env::var_os("XDG_RUNTIME_DIR"));
+}
+
+// Created accessible to the user only. A folder of another user is refused: they could read and forge advertisements.
+// An existing folder that other users can access (e.g. created by an older version, or under another umask) is made private.
+#[cfg(unix)]
+fn create_private_folder(folder: &path::Path) -> io::Result<()> {
+	use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+	fs::DirBuilder::new().recursive(true).mode(0o700).create(folder) ?;
+	let metadata = fs::metadata(folder) ?;
+	if metadata.uid() != unsafe { libc::getuid() } {
+		return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("'{}' belongs to another user", folder.display())));
+	}
+	if metadata.mode() & 0o077 != 0 {
+		fs::set_permissions(folder, fs::Permissions::from_mode(0o700)) ?;
+	}
+	return Ok(());
+}
+
+#[cfg(not(unix))]
+fn create_private_folder(folder: &path::Path) -> io::Result<()> {
+	return fs::create_dir_all(folder);
+}
+
+fn is_valid_room_name(room_name: &str) -> bool {
+	return !room_name.is_empty() && room_name != "." && room_name != ".." && !room_name.contains(['/', '\\']);
+}
+
+// Rooms are the sub-directories of the rendez-vous folder with at least one peer advertising in them
+pub fn get_available_rooms(rendezvous_folder: &path::Path) -> io::Result<Vec<String>> {
+	let mut rooms: Vec<String> = Vec::new();
+
+	if let Ok(entries) = fs::read_dir(rendezvous_folder) {
+		for entry in entries.flatten() {
+			if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+				let has_peers = fs::read_dir(entry.path()).is_ok_and(|mut advertisements| advertisements.next().is_some());
+
+				if has_peers {
+					rooms.push(entry.file_name().to_string_lossy().into_owned());
+				}
+			}
+		}
+	}
+
+	rooms.sort();
+	return Ok(rooms);
+}
+
+// The room directory, in which peers of this machine advertise themselves
+pub fn create_room_directory(rendezvous_folder: &path::Path, room_name: &str) -> io::Result<path::PathBuf> {
+	if !is_valid_room_name(room_name) {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid room name '{}'", room_name)));
+	}
+
+	let rendezvous_directory = rendezvous_folder.join(room_name);
+	create_private_folder(rendezvous_folder) ?;
+	create_private_folder(&rendezvous_directory) ?;
+	return Ok(rendezvous_directory);
 }
 
 pub struct RendezVous {
@@ -15,31 +99,22 @@ pub struct RendezVous {
 }
 
 impl RendezVous {
-	fn advertise(&self, local_udp_port: u16) -> std::io::Result<()> {
-		if !self.rendezvous_directory.exists() {
-			fs::create_dir(&self.rendezvous_directory) ?;
-		}
-
+	fn advertise(&self, local_udp_port: u16) -> io::Result<()> {
 		fs::write(&self.advertisement_filename, local_udp_port.to_string()) ?;
 		return Ok(());
 	}
 
 	fn look_for_peer_advertisement(&self) -> Option<u16> {
 		if let Ok(entries) = fs::read_dir(&self.rendezvous_directory) {	// For each entry in the directory...
-			for entry in entries {										//
-				if let Ok(entry) = entry {								//
-					if let Ok(file_type) = entry.file_type() {			//
-						let filename = entry.path();
-						
-						if file_type.is_file() && filename != self.advertisement_filename {
-							if let Ok(content) = fs::read(&filename) {
-								let _ = fs::remove_file(&filename);
-
-								return match String::from_utf8_lossy(&content).parse() {
-									Ok(udp_port) => Some(udp_port),
-									Err(_) => None,
-								}
-							}
+			for entry in entries.flatten() {							//
+				if let Ok(file_type) = entry.file_type() {				//
+					let filename = entry.path();
+					
+					if file_type.is_file() && filename != self.advertisement_filename {
+						if let Ok(content) = fs::read(&filename) {
+							let _ = fs::remove_file(&filename);
+
+							return String::from_utf8_lossy(&content).parse().ok();
 						}
 					}
 				}
@@ -48,9 +123,27 @@ impl RendezVous {
 		return None;
 	}
 
-	pub fn new(local_udp_port: u16) -> std::io::Result<RendezVous> {
-		let rendezvous_directory = get_rendezvous_folder();
-		let advertisement_filename = rendezvous_directory.join(process::id().to_string());
+	// Ports of the other peers advertising in the room, which are left in place
+	pub fn advertised_ports(&self) -> Vec<u16> {
+		let mut ports = Vec::new();
+
+		if let Ok(entries) = fs::read_dir(&self.rendezvous_directory) {
+			for entry in entries.flatten() {
+				let filename = entry.path();
+
+				if entry.file_type().is_ok_and(|file_type| file_type.is_file()) && filename != self.advertisement_filename {
+					if let Some(port) = fs::read(&filename).ok().and_then(|content| String::from_utf8_lossy(&content).parse().ok()) {
+						ports.push(port);
+					}
+				}
+			}
+		}
+		return ports;
+	}
+
+	pub fn new(rendezvous_folder: &path::Path, room_name: &str, local_udp_port: u16) -> io::Result<RendezVous> {
+		let rendezvous_directory = create_room_directory(rendezvous_folder, room_name) ?;
+		let advertisement_filename = rendezvous_directory.join(format!("{}-{}", process::id(), NEXT_ADVERTISEMENT_ID.fetch_add(1, Ordering::Relaxed)));
 		let rendezvous = RendezVous { rendezvous_directory, advertisement_filename };
 
 		rendezvous.advertise(local_udp_port) ?;
@@ -69,6 +162,8 @@ impl RendezVous {
 		if self.advertisement_filename.exists() {
 			let _ = fs::remove_file(&self.advertisement_filename);
 		}
+		// Only succeeds once the room is empty
+		let _ = fs::remove_dir(&self.rendezvous_directory);
 	}
 }
 
@@ -76,4 +171,7 @@ impl Drop for RendezVous {
 	fn drop(&mut self) {
 	   self.cleanup();
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,81 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use super::{get_available_rooms, rendezvous_folder_in, RendezVous, RDV_DIR};
+
+// Unique to the test, so that parallel runs do not collide
+fn test_folder(name: &str) -> PathBuf {
+	return env::temp_dir().join(format!("n3t1r-rendezvous-test-{}-{}", process::id(), name));
+}
+
+#[test]
+fn default_folder_follows_xdg_runtime_dir() {
+	assert_eq!(rendezvous_folder_in(Some(OsString::from("/run/user/1000"))), PathBuf::from("/run/user/1000").join(RDV_DIR));
+
+	for runtime_dir in [None, Some(OsString::new())] {
+		let folder = rendezvous_folder_in(runtime_dir);
+		assert_eq!(folder.parent(), Some(env::temp_dir().as_path()));
+		assert!(folder.file_name().unwrap().to_string_lossy().starts_with(RDV_DIR));
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn folders_are_private_to_the_user() {
+	use std::os::unix::fs::PermissionsExt;
+
+	let folder = test_folder("private").join("nested");
+	let rendezvous = RendezVous::new(&folder, "room", 4242).unwrap();
+
+	for created_folder in [folder.parent().unwrap(), &folder, &folder.join("room")] {
+		assert_eq!(fs::metadata(created_folder).unwrap().permissions().mode() & 0o777, 0o700, "{}", created_folder.display());
+	}
+
+	drop(rendezvous);
+	let _ = fs::remove_dir_all(test_folder("private"));
+}
+
+#[cfg(unix)]
+#[test]
+fn existing_folders_are_made_private() {
+	use std::os::unix::fs::PermissionsExt;
+
+	let folder = test_folder("shared");
+	for shared_folder in [&folder, &folder.join("room")] {
+		fs::create_dir_all(shared_folder).unwrap();
+		fs::set_permissions(shared_folder, fs::Permissions::from_mode(0o777)).unwrap();
+	}
+
+	let rendezvous = RendezVous::new(&folder, "room", 4242).unwrap();
+	for shared_folder in [&folder, &folder.join("room")] {
+		assert_eq!(fs::metadata(shared_folder).unwrap().permissions().mode() & 0o777, 0o700, "{}", shared_folder.display());
+	}
+
+	drop(rendezvous);
+	let _ = fs::remove_dir_all(folder);
+}
+
+#[test]
+fn peers_only_meet_in_the_same_folder() {
+	let (folder, other_folder) = (test_folder("meet"), test_folder("meet-other"));
+	let rendezvous = RendezVous::new(&folder, "room", 4242).unwrap();
+	let other_rendezvous = RendezVous::new(&other_folder, "room", 4343).unwrap();
+	assert_eq!(get_available_rooms(&folder).unwrap(), vec![String::from("room")]);
+
+	// Advertisement of a peer of another process
+	fs::write(folder.join("room").join("0"), "5151").unwrap();
+	assert_eq!(other_rendezvous.try_to_meet(), None);
+	assert_eq!(rendezvous.try_to_meet(), Some(5151));
+	assert_eq!(rendezvous.try_to_meet(), None);
+
+	drop(rendezvous);
+	assert!(get_available_rooms(&folder).unwrap().is_empty());
+	assert!(RendezVous::new(&folder, "../room", 4242).is_err());
+
+	drop(other_rendezvous);
+	let _ = fs::remove_dir_all(&folder);
+	let _ = fs::remove_dir_all(&other_folder);
+}
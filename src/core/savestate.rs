@@ -0,0 +1,97 @@
+// State of an IRCommunicationHandler saved along with an emulator savestate, and restored when it is loaded.
+// Only what does not depend on the connection is kept: frames sent by the game that the peer did not get yet,
+// and frames received for the game that it did not get yet. Partial frames and transport sessions are not,
+// what comes next on the connection would not line up with them.
+//
+// Blob: magic "N3T1R" | version (1) | outgoing frames | incoming frames
+// Outgoing frames: count (2, LE), then for each: frame
+// Incoming frames: count (2, LE), then for each: sender (4, LE) | frame
+// Frame: delay (8, LE, nanoseconds it was still held for) | length (2, LE) | data
+
+use std::time::Duration;
+
+use super::timing::{HeldFrames, HeldIncomingFrames};
+use super::{PeerId, MAXIMUM_DATA_LEN};
+
+const MAGIC: &[u8] = b"N3T1R";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SavedState {
+	pub outgoing_frames: HeldFrames,
+	pub incoming_frames: HeldIncomingFrames,
+}
+
+fn write_frame(bytes: &mut Vec<u8>, delay: Duration, data: &[u8]) {
+	bytes.extend_from_slice(&(delay.as_nanos() as u64).to_le_bytes());
+	bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+	bytes.extend_from_slice(data);
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+	if bytes.len() < len {
+		return Err(String::from("Truncated state"));
+	}
+	let (taken, rest) = bytes.split_at(len);
+	*bytes = rest;
+	return Ok(taken);
+}
+
+fn read_count(bytes: &mut &[u8]) -> Result<usize, String> {
+	return Ok(u16::from_le_bytes(take(bytes, 2)?.try_into().unwrap()) as usize);
+}
+
+fn read_frame(bytes: &mut &[u8]) -> Result<(Duration, Vec<u8>), String> {
+	let delay = Duration::from_nanos(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()));
+	let len = read_count(bytes)?;
+	if len > MAXIMUM_DATA_LEN {
+		return Err(format!("Frame too long in state ({} bytes)", len));
+	}
+	return Ok((delay, take(bytes, len)?.to_vec()));
+}
+
+impl SavedState {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		bytes.extend_from_slice(MAGIC);
+		bytes.push(VERSION);
+		bytes.extend_from_slice(&(self.outgoing_frames.len() as u16).to_le_bytes());
+		for (delay, data) in &self.outgoing_frames {
+			write_frame(&mut bytes, *delay, data);
+		}
+		bytes.extend_from_slice(&(self.incoming_frames.len() as u16).to_le_bytes());
+		for (delay, sender, data) in &self.incoming_frames {
+			bytes.extend_from_slice(&sender.to_le_bytes());
+			write_frame(&mut bytes, *delay, data);
+		}
+		return bytes;
+	}
+
+	pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, String> {
+		if take(&mut bytes, MAGIC.len()).ok() != Some(MAGIC) {
+			return Err(String::from("Not a n3t1r state"));
+		}
+		let version = take(&mut bytes, 1)?[0];
+		if version != VERSION {
+			return Err(format!("Unsupported state version {}, expected {}", version, VERSION));
+		}
+
+		let mut outgoing_frames: HeldFrames = Vec::new();
+		for _ in 0..read_count(&mut bytes)? {
+			outgoing_frames.push(read_frame(&mut bytes)?);
+		}
+		let mut incoming_frames: HeldIncomingFrames = Vec::new();
+		for _ in 0..read_count(&mut bytes)? {
+			let sender = PeerId::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap());
+			let (delay, data) = read_frame(&mut bytes)?;
+			incoming_frames.push((delay, sender, data));
+		}
+		if !bytes.is_empty() {
+			return Err(format!("{} unexpected bytes after the state", bytes.len()));
+		}
+		return Ok(Self { outgoing_frames, incoming_frames });
+	}
+}
+
+#[cfg(test)]
+mod tests;
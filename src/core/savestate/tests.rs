@@ -0,0 +1,118 @@
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use super::SavedState;
+use super::super::traits::IOHandler;
+use super::super::{IRCommunicationHandler, NetworkOptions, TimingOptions};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+// Two handlers talking over the loopback interface
+fn handler_pair() -> (IRCommunicationHandler, IRCommunicationHandler) {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let mut left = IRCommunicationHandler::new();
+	let mut right = IRCommunicationHandler::new();
+
+	left.select_network_backend(left_port, "127.0.0.1", right_port, &NetworkOptions::default()).unwrap();
+	right.select_network_backend(right_port, "127.0.0.1", left_port, &NetworkOptions::default()).unwrap();
+	left.enable().unwrap();
+	right.enable().unwrap();
+	return (left, right);
+}
+
+fn receive_within(handler: &mut IRCommunicationHandler, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = handler.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn states_round_trip() {
+	let state = SavedState {
+		outgoing_frames: vec![(Duration::ZERO, vec![0x01]), (Duration::from_millis(5), vec![0xFF; 255])],
+		incoming_frames: vec![(Duration::from_nanos(1), 0x12345678, vec![0x02, 0x03])],
+	};
+	assert_eq!(SavedState::from_bytes(&state.to_bytes()).unwrap(), state);
+	assert_eq!(SavedState::from_bytes(&SavedState::default().to_bytes()).unwrap(), SavedState::default());
+}
+
+#[test]
+fn invalid_states_are_rejected() {
+	let bytes = SavedState { outgoing_frames: vec![(Duration::ZERO, vec![0x01, 0x02])], incoming_frames: Vec::new() }.to_bytes();
+
+	assert!(SavedState::from_bytes(&[]).is_err());
+	assert!(SavedState::from_bytes(b"savestate").is_err());
+	for len in 0..bytes.len() {
+		assert!(SavedState::from_bytes(&bytes[..len]).is_err(), "truncated to {} bytes", len);
+	}
+
+	let mut newer = bytes.clone();
+	newer[5] += 1;
+	assert!(SavedState::from_bytes(&newer).unwrap_err().contains("version"));
+
+	let mut trailing = bytes.clone();
+	trailing.push(0);
+	assert!(SavedState::from_bytes(&trailing).is_err());
+}
+
+#[test]
+fn loading_restores_held_frames_and_asks_the_peer_to_resync() {
+	let (mut left, mut right) = handler_pair();
+	// Frozen, so that the held frames keep the same delay
+	left.advance_clock(Duration::ZERO);
+	left.set_timing_options(TimingOptions { send_delay: Duration::from_secs(60), ..TimingOptions::default() });
+
+	// Still held by the timing layer when saved
+	left.send(&[0x01]).unwrap();
+	let state = left.save_state();
+
+	// Sent after the save, forgotten by the load
+	left.send(&[0x02]).unwrap();
+	left.load_state(&state).unwrap();
+	assert_eq!(left.save_state(), state);
+
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), None);
+	assert!(right.take_resync_request());
+	assert!(!right.take_resync_request());
+	assert!(!left.take_resync_request());
+
+	// The restored frame goes out when its time comes
+	left.advance_clock(Duration::from_secs(60));
+	assert_eq!(left.receive().unwrap(), None);
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x01]));
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT / 10), None);
+
+	assert!(left.load_state(&state[1..]).is_err());
+	assert!(left.send(&[]).is_err());
+}
+
+#[test]
+fn peeked_frames_are_saved() {
+	let (mut left, mut right) = handler_pair();
+
+	left.send(&[0x01, 0x02]).unwrap();
+	let start = Instant::now();
+	let mut len = None;
+	while len.is_none() && start.elapsed() < TEST_TIMEOUT {
+		len = right.peek_frame_len().unwrap();
+	}
+	assert_eq!(len, Some(2));
+
+	// Not received by the game yet
+	let state = right.save_state();
+	assert!(right.receive_into(&mut [0u8; 1]).is_err());
+	assert_eq!(right.receive().unwrap(), Some(vec![0x01, 0x02]));
+
+	right.load_state(&state).unwrap();
+	assert_eq!(right.peek_frame_len().unwrap(), Some(2));
+	assert_eq!(right.receive().unwrap(), Some(vec![0x01, 0x02]));
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT / 10), None);
+}
@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkStatistics {
+	pub frames_sent: u64,
+	pub bytes_sent: u64,
+	pub frames_received: u64,
+	pub bytes_received: u64,
+	pub frames_dropped: u64,
+	// Frames sent again by the reliable network mode
+	pub retransmissions: u64,
+	pub oversize_sends: u64,
+	pub framing_errors: u64,
+	// Datagrams rejected by the secure network mode, either unauthenticated or replayed
+	pub authentication_failures: u64,
+	pub last_sent: Option<Instant>,
+	pub last_received: Option<Instant>,
+	pub last_round_trip: Option<Duration>,
+	pub smoothed_round_trip: Option<Duration>,
+}
+
+struct RecorderState {
+	statistics: LinkStatistics,
+	awaiting_reply_since: Option<Instant>,
+	// Frames lost on reception, not reset so that handlers can tell when it changed
+	losses: u64,
+}
+
+// Shared between the handler and its backend, so that events only the backend can see
+// (timeouts, framing errors) end up in the same snapshot as the frames counted by the handler.
+#[derive(Clone)]
+pub struct StatisticsRecorder {
+	state: Arc<Mutex<RecorderState>>,
+}
+
+impl StatisticsRecorder {
+	pub fn new() -> Self {
+		return Self { state: Arc::new(Mutex::new(RecorderState { statistics: LinkStatistics::default(), awaiting_reply_since: None, losses: 0 })) };
+	}
+
+	pub fn snapshot(&self) -> LinkStatistics {
+		return self.state.lock().unwrap().statistics.clone();
+	}
+
+	pub fn reset(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.statistics = LinkStatistics::default();
+		state.awaiting_reply_since = None;
+	}
+
+	pub fn record_sent(&self, len: usize) {
+		let mut state = self.state.lock().unwrap();
+		let now = Instant::now();
+
+		state.statistics.frames_sent += 1;
+		state.statistics.bytes_sent += len as u64;
+		state.statistics.last_sent = Some(now);
+
+		if state.awaiting_reply_since.is_none() {
+			state.awaiting_reply_since = Some(now);
+		}
+	}
+
+	pub fn record_received(&self, len: usize) {
+		let mut state = self.state.lock().unwrap();
+		let now = Instant::now();
+
+		state.statistics.frames_received += 1;
+		state.statistics.bytes_received += len as u64;
+		state.statistics.last_received = Some(now);
+
+		// Round-trip is measured from the first unanswered send to the next received frame
+		if let Some(sent_ts) = state.awaiting_reply_since.take() {
+			let round_trip = now.duration_since(sent_ts);
+
+			state.statistics.last_round_trip = Some(round_trip);
+			state.statistics.smoothed_round_trip = Some(match state.statistics.smoothed_round_trip {
+				// Same 1/8 gain as TCP's SRTT
+				Some(smoothed) => { (smoothed * 7 + round_trip) / 8 },
+				None => { round_trip },
+			});
+		}
+	}
+
+	pub fn record_dropped(&self) {
+		self.state.lock().unwrap().statistics.frames_dropped += 1;
+	}
+
+	// Frames of the peer lost on reception, e.g. given up by the reliable mode
+	pub fn record_lost_frames(&self, count: u64) {
+		let mut state = self.state.lock().unwrap();
+		state.statistics.frames_dropped += count;
+		state.losses += count;
+	}
+
+	pub fn record_retransmission(&self) {
+		self.state.lock().unwrap().statistics.retransmissions += 1;
+	}
+
+	pub fn record_oversize_send(&self) {
+		self.state.lock().unwrap().statistics.oversize_sends += 1;
+	}
+
+	pub fn record_framing_error(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.statistics.framing_errors += 1;
+		state.losses += 1;
+	}
+
+	pub fn record_authentication_failure(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.statistics.authentication_failures += 1;
+		state.losses += 1;
+	}
+
+	// Frames lost on reception so far, including framing errors and authentication failures
+	pub fn losses(&self) -> u64 {
+		return self.state.lock().unwrap().losses;
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,86 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::StatisticsRecorder;
+
+#[test]
+fn counters_add_up() {
+	let recorder = StatisticsRecorder::new();
+	let before = Instant::now();
+
+	recorder.record_sent(3);
+	recorder.record_sent(5);
+	recorder.record_received(7);
+	recorder.record_dropped();
+	recorder.record_lost_frames(2);
+	recorder.record_retransmission();
+	recorder.record_oversize_send();
+	recorder.record_framing_error();
+	recorder.record_authentication_failure();
+
+	let statistics = recorder.snapshot();
+	assert_eq!((statistics.frames_sent, statistics.bytes_sent), (2, 8));
+	assert_eq!((statistics.frames_received, statistics.bytes_received), (1, 7));
+	assert_eq!(statistics.frames_dropped, 3);
+	assert_eq!(statistics.retransmissions, 1);
+	assert_eq!(statistics.oversize_sends, 1);
+	assert_eq!(statistics.framing_errors, 1);
+	assert_eq!(statistics.authentication_failures, 1);
+	assert!(statistics.last_sent.unwrap() >= before);
+	assert!(statistics.last_received.unwrap() >= statistics.last_sent.unwrap());
+
+	// Lost frames, framing errors and authentication failures
+	assert_eq!(recorder.losses(), 4);
+}
+
+#[test]
+fn clones_share_the_same_counters() {
+	let recorder = StatisticsRecorder::new();
+	recorder.clone().record_sent(1);
+
+	assert_eq!(recorder.snapshot().frames_sent, 1);
+}
+
+#[test]
+fn round_trip_is_smoothed() {
+	let recorder = StatisticsRecorder::new();
+	assert_eq!(recorder.snapshot().smoothed_round_trip, None);
+
+	// Measured from the first unanswered send
+	recorder.record_sent(1);
+	thread::sleep(Duration::from_millis(5));
+	recorder.record_sent(1);
+	recorder.record_received(1);
+	let first = recorder.snapshot();
+	assert!(first.last_round_trip.unwrap() >= Duration::from_millis(5));
+	assert_eq!(first.smoothed_round_trip, first.last_round_trip);
+
+	// Nothing was awaiting a reply
+	recorder.record_received(1);
+	assert_eq!(recorder.snapshot().last_round_trip, first.last_round_trip);
+
+	recorder.record_sent(1);
+	recorder.record_received(1);
+	let second = recorder.snapshot();
+	assert!(second.last_round_trip.unwrap() < first.last_round_trip.unwrap());
+	assert_eq!(second.smoothed_round_trip, Some((first.smoothed_round_trip.unwrap() * 7 + second.last_round_trip.unwrap()) / 8));
+}
+
+#[test]
+fn reset_clears_everything_but_losses() {
+	let recorder = StatisticsRecorder::new();
+	recorder.record_sent(1);
+	recorder.record_received(1);
+	recorder.record_framing_error();
+	recorder.record_sent(1);
+	recorder.reset();
+
+	let statistics = recorder.snapshot();
+	assert_eq!((statistics.frames_sent, statistics.frames_received, statistics.framing_errors), (0, 0, 0));
+	assert_eq!((statistics.last_sent, statistics.last_round_trip, statistics.smoothed_round_trip), (None, None, None));
+	assert_eq!(recorder.losses(), 1);
+
+	// The send before the reset is not awaiting a reply anymore
+	recorder.record_received(1);
+	assert_eq!(recorder.snapshot().last_round_trip, None);
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::net::UdpSocket;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::{ConnectionState, IRCommunicationHandler, NetworkOptions, TimingOptions};
+use super::traits::IOHandler;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+// States reported to the callback, in order
+fn record_states(handler: &mut IRCommunicationHandler) -> Rc<RefCell<Vec<ConnectionState>>> {
+	let states = Rc::new(RefCell::new(Vec::new()));
+	let recorded_states = states.clone();
+	handler.set_state_callback(Some(Box::new(move |state| recorded_states.borrow_mut().push(state))));
+	return states;
+}
+
+fn receive_within(handler: &mut IRCommunicationHandler, timeout: Duration) -> Option<Vec<u8>> {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if let Some(data) = handler.receive().unwrap() {
+			return Some(data);
+		}
+	}
+	return None;
+}
+
+#[test]
+fn enabling_without_backend_fails() {
+	let mut handler = IRCommunicationHandler::new();
+	let states = record_states(&mut handler);
+	assert_eq!(handler.state(), ConnectionState::Disabled);
+
+	assert!(handler.enable().is_err());
+	assert_eq!(handler.state(), ConnectionState::Disabled);
+	assert!(states.borrow().is_empty());
+}
+
+#[test]
+fn udp_peer_is_waited_for_until_it_is_heard() {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let (mut left, mut right) = (IRCommunicationHandler::new(), IRCommunicationHandler::new());
+	left.select_network_backend(left_port, "127.0.0.1", right_port, &NetworkOptions::default()).unwrap();
+	right.select_network_backend(right_port, "127.0.0.1", left_port, &NetworkOptions::default()).unwrap();
+	let states = record_states(&mut right);
+
+	left.enable().unwrap();
+	right.enable().unwrap();
+	assert_eq!(right.state(), ConnectionState::WaitingForPeer);
+	assert_eq!(right.receive().unwrap(), None);
+	assert_eq!(right.state(), ConnectionState::WaitingForPeer);
+
+	// Sending does not need the peer to be there
+	right.send(&[0x01]).unwrap();
+	assert_eq!(right.state(), ConnectionState::WaitingForPeer);
+	assert_eq!(receive_within(&mut left, TEST_TIMEOUT), Some(vec![0x01]));
+
+	left.send(&[0x02]).unwrap();
+	assert_eq!(receive_within(&mut right, TEST_TIMEOUT), Some(vec![0x02]));
+	assert_eq!(right.state(), ConnectionState::Connected);
+
+	right.disable();
+	assert_eq!(right.state(), ConnectionState::Disabled);
+	assert_eq!(*states.borrow(), vec![ConnectionState::Connecting, ConnectionState::WaitingForPeer, ConnectionState::Connected, ConnectionState::Disabled]);
+
+	// Not heard since enabled again
+	states.borrow_mut().clear();
+	right.enable().unwrap();
+	assert_eq!(*states.borrow(), vec![ConnectionState::Connecting, ConnectionState::WaitingForPeer]);
+}
+
+#[test]
+fn failed_enable_is_an_error_state() {
+	// Already taken
+	let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+	let mut handler = IRCommunicationHandler::new();
+	handler.select_network_backend(socket.local_addr().unwrap().port(), "127.0.0.1", free_udp_port(), &NetworkOptions::default()).unwrap();
+	let states = record_states(&mut handler);
+
+	assert!(handler.enable().is_err());
+	assert_eq!(handler.state(), ConnectionState::Error);
+	assert!(handler.send(&[0x01]).is_err());
+
+	handler.disable();
+	assert_eq!(*states.borrow(), vec![ConnectionState::Connecting, ConnectionState::Error, ConnectionState::Disabled]);
+}
+
+#[test]
+fn removed_callback_is_not_called() {
+	let mut handler = IRCommunicationHandler::new();
+	handler.select_network_backend(free_udp_port(), "127.0.0.1", free_udp_port(), &NetworkOptions::default()).unwrap();
+	let states = record_states(&mut handler);
+	handler.set_state_callback(None);
+
+	handler.enable().unwrap();
+	handler.disable();
+	assert!(states.borrow().is_empty());
+}
+
+// An enabled handler on a free port, talking to `port`
+fn peer_of(port: u16) -> (IRCommunicationHandler, u16) {
+	let peer_port = free_udp_port();
+	let mut peer = IRCommunicationHandler::new();
+	peer.select_network_backend(peer_port, "127.0.0.1", port, &NetworkOptions::default()).unwrap();
+	peer.enable().unwrap();
+	return (peer, peer_port);
+}
+
+// Enabled, with two frames held by a send delay that never elapses until the clock is advanced
+fn handler_with_pending_frames(port: u16, peer_port: u16) -> IRCommunicationHandler {
+	let mut handler = IRCommunicationHandler::new();
+	handler.select_network_backend(port, "127.0.0.1", peer_port, &NetworkOptions::default()).unwrap();
+	handler.enable().unwrap();
+	handler.advance_clock(Duration::ZERO);
+	handler.set_timing_options(TimingOptions { send_delay: Duration::from_millis(10), ..TimingOptions::default() });
+
+	handler.send(&[0x01]).unwrap();
+	handler.send(&[0x02]).unwrap();
+	return handler;
+}
+
+// Lets the held frames go
+fn flush_pending_frames(handler: &mut IRCommunicationHandler) {
+	handler.advance_clock(Duration::from_millis(10));
+	assert_eq!(handler.receive().unwrap(), None);
+}
+
+#[test]
+fn pending_frames_are_sent_through_the_new_backend() {
+	let (old_port, new_port) = (free_udp_port(), free_udp_port());
+	let (mut old_peer, old_peer_port) = peer_of(old_port);
+	let (mut new_peer, new_peer_port) = peer_of(new_port);
+	let mut handler = handler_with_pending_frames(old_port, old_peer_port);
+
+	handler.select_network_backend(new_port, "127.0.0.1", new_peer_port, &NetworkOptions::default()).unwrap();
+	assert_eq!(handler.state(), ConnectionState::WaitingForPeer);
+	flush_pending_frames(&mut handler);
+
+	assert_eq!(receive_within(&mut new_peer, TEST_TIMEOUT), Some(vec![0x01]));
+	assert_eq!(receive_within(&mut new_peer, TEST_TIMEOUT), Some(vec![0x02]));
+	assert_eq!(receive_within(&mut old_peer, TEST_TIMEOUT / 10), None);
+	assert_eq!(handler.stats().frames_dropped, 0);
+}
+
+#[test]
+fn pending_frames_are_dropped_when_not_transferred() {
+	let (old_port, new_port) = (free_udp_port(), free_udp_port());
+	let (_old_peer, old_peer_port) = peer_of(old_port);
+	let (mut new_peer, new_peer_port) = peer_of(new_port);
+	let mut handler = handler_with_pending_frames(old_port, old_peer_port);
+	handler.set_transfer_pending_frames(false);
+
+	handler.select_network_backend(new_port, "127.0.0.1", new_peer_port, &NetworkOptions::default()).unwrap();
+	flush_pending_frames(&mut handler);
+
+	assert_eq!(receive_within(&mut new_peer, TEST_TIMEOUT / 10), None);
+	assert_eq!(handler.stats().frames_dropped, 2);
+
+	// Only the frames pending during the switch
+	handler.send(&[0x03]).unwrap();
+	flush_pending_frames(&mut handler);
+	assert_eq!(receive_within(&mut new_peer, TEST_TIMEOUT), Some(vec![0x03]));
+}
+
+#[test]
+fn failed_switch_restores_the_previous_backend() {
+	let old_port = free_udp_port();
+	let (mut old_peer, old_peer_port) = peer_of(old_port);
+	let mut handler = handler_with_pending_frames(old_port, old_peer_port);
+	let states = record_states(&mut handler);
+
+	// Already taken
+	let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+	assert!(handler.select_network_backend(socket.local_addr().unwrap().port(), "127.0.0.1", free_udp_port(), &NetworkOptions::default()).is_err());
+	assert_eq!(*states.borrow(), vec![ConnectionState::Connecting, ConnectionState::WaitingForPeer]);
+
+	// Still working, pending frames included
+	flush_pending_frames(&mut handler);
+	assert_eq!(receive_within(&mut old_peer, TEST_TIMEOUT), Some(vec![0x01]));
+	assert_eq!(receive_within(&mut old_peer, TEST_TIMEOUT), Some(vec![0x02]));
+	old_peer.send(&[0x03]).unwrap();
+	assert_eq!(receive_within(&mut handler, TEST_TIMEOUT), Some(vec![0x03]));
+	assert_eq!(handler.state(), ConnectionState::Connected);
+}
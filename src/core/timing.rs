@@ -0,0 +1,213 @@
+// Timing emulation around any IOHandler, for testing how games cope with slow or jittery links,
+// and for presenting them frames with an IR-like arrival timing whatever the transport underneath.
+//
+// Outgoing frames are held for send_delay plus a random part of send_jitter, in their sending order.
+// Incoming frames are held for receive_delay (a playout buffer absorbing the jitter of the link), then released
+// as if they were coming out of an IR transceiver: one after the other, taking byte_time per byte,
+// with at least turnaround between two frames.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::traits::{copy_frame, IOHandler};
+use super::frame::ReceivedFrame;
+use super::{ConnectionState, PeerId};
+
+// Time to transfer a byte at 115200 baud with 8N1 framing (10 bits per byte), as the dongle does
+pub const IR_BYTE_TIME_NS: u64 = 86_806;
+
+// Frames with how long they are still held for
+pub type HeldFrames = Vec<(Duration, Vec<u8>)>;
+// Along with their sender
+pub type HeldIncomingFrames = Vec<(Duration, PeerId, Vec<u8>)>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimingOptions {
+	pub send_delay: Duration,
+	pub send_jitter: Duration,
+	pub receive_delay: Duration,
+	pub byte_time: Duration,
+	pub turnaround: Duration,
+}
+
+impl TimingOptions {
+	// Frames go through untouched
+	pub fn is_transparent(&self) -> bool {
+		return *self == Self::default();
+	}
+}
+
+fn random_duration(maximum: Duration) -> Duration {
+	let mut bytes = [0u8; 8];
+	if maximum.is_zero() || getrandom::fill(&mut bytes).is_err() {
+		return Duration::ZERO;
+	}
+	return Duration::from_nanos(u64::from_le_bytes(bytes) % (maximum.as_nanos() as u64 + 1));
+}
+
+pub struct TimingLayer<H: IOHandler> {
+	inner: H,
+	options: TimingOptions,
+	// Frames with the time at which they are sent or delivered
+	outgoing: VecDeque<(Instant, Vec<u8>)>,
+	incoming: VecDeque<(Instant, ReceivedFrame)>,
+	last_release: Option<Instant>,
+}
+
+impl<H: IOHandler> TimingLayer<H> {
+	pub fn new(inner: H) -> Self {
+		return Self { inner, options: TimingOptions::default(), outgoing: VecDeque::new(), incoming: VecDeque::new(), last_release: None };
+	}
+
+	pub fn inner(&self) -> &H {
+		return &self.inner;
+	}
+
+	pub fn inner_mut(&mut self) -> &mut H {
+		return &mut self.inner;
+	}
+
+	// Frames held by the layer stay held, returns the previous handler
+	pub fn replace_inner(&mut self, inner: H) -> H {
+		return std::mem::replace(&mut self.inner, inner);
+	}
+
+	pub fn options(&self) -> TimingOptions {
+		return self.options;
+	}
+
+	// Applies to the frames sent and received from now on
+	pub fn set_options(&mut self, options: TimingOptions) {
+		self.options = options;
+	}
+
+	// Outgoing frames not handed to the inner handler yet
+	pub fn take_delayed_frames(&mut self) -> Vec<Vec<u8>> {
+		return self.outgoing.drain(..).map(|(_, data)| data).collect();
+	}
+
+	// Outgoing and incoming frames still held
+	pub fn held_frames(&self, now: Instant) -> (HeldFrames, HeldIncomingFrames) {
+		let outgoing = self.outgoing.iter().map(|(due, data)| (due.saturating_duration_since(now), data.clone())).collect();
+		let incoming = self.incoming.iter().map(|(release, frame)| (release.saturating_duration_since(now), frame.source, frame.data.clone())).collect();
+		return (outgoing, incoming);
+	}
+
+	// Replaces the held frames, e.g. with the ones of a savestate. Incoming frames are timestamped with now.
+	pub fn restore_held_frames(&mut self, outgoing: HeldFrames, incoming: HeldIncomingFrames, now: Instant) {
+		self.outgoing = outgoing.into_iter().map(|(delay, data)| (now + delay, data)).collect();
+		self.incoming = incoming.into_iter().map(|(delay, source, data)| (now + delay, ReceivedFrame::new(data, now, source))).collect();
+		self.last_release = self.incoming.back().map(|(release, _)| *release);
+	}
+
+	// Hands the due outgoing frames to the inner handler. A frame the inner handler rejects is lost, and its error returned.
+	fn flush(&mut self, now: Instant) -> Result<(), String> {
+		while self.outgoing.front().is_some_and(|(due, _)| *due <= now) {
+			let (_, data) = self.outgoing.pop_front().unwrap();
+			self.inner.send(&data)?;
+		}
+		return Ok(());
+	}
+
+	pub fn send_at(&mut self, data: &[u8], now: Instant) -> Result<(), String> {
+		if self.options.is_transparent() && self.outgoing.is_empty() {
+			return self.inner.send(data);
+		}
+
+		let mut due = now + self.options.send_delay + random_duration(self.options.send_jitter);
+		// Jitter does not reorder frames
+		if let Some((last_due, _)) = self.outgoing.back() {
+			due = due.max(*last_due);
+		}
+		self.outgoing.push_back((due, data.to_vec()));
+		return self.flush(now);
+	}
+
+	pub fn receive_at(&mut self, now: Instant) -> Result<Option<Vec<u8>>, String> {
+		return Ok(self.receive_frame_at(now)?.map(|frame| frame.data));
+	}
+
+	// Same as receive_at into `buffer`, straight from the inner handler when there is nothing to emulate
+	pub fn receive_into_at(&mut self, buffer: &mut [u8], now: Instant) -> Result<Option<usize>, String> {
+		if self.options.is_transparent() && self.incoming.is_empty() {
+			self.flush(now)?;
+			return self.inner.receive_into(buffer);
+		}
+
+		return match self.receive_frame_at(now)? {
+			Some(frame) => { Ok(Some(copy_frame(&frame.data, buffer)?)) },
+			None => { Ok(None) },
+		};
+	}
+
+	// Length of the next frame, which is put back in front of the held frames for the next receive
+	pub fn peek_frame_len_at(&mut self, now: Instant) -> Result<Option<usize>, String> {
+		if let Some(frame) = self.receive_frame_at(now)? {
+			let len = frame.data.len();
+			self.incoming.push_front((now, frame));
+			return Ok(Some(len));
+		}
+		return Ok(None);
+	}
+
+	// Frames are timestamped with now, when they come out of the inner handler
+	pub fn receive_frame_at(&mut self, now: Instant) -> Result<Option<ReceivedFrame>, String> {
+		self.flush(now)?;
+
+		if self.options.is_transparent() && self.incoming.is_empty() {
+			return Ok(self.inner.receive_from()?.map(|(source, data)| ReceivedFrame::new(data, now, source)));
+		}
+
+		if let Some((source, data)) = self.inner.receive_from()? {
+			let mut start = now + self.options.receive_delay;
+			if let Some(last_release) = self.last_release {
+				start = start.max(last_release + self.options.turnaround);
+			}
+			let release = start + self.options.byte_time * data.len() as u32;
+
+			self.last_release = Some(release);
+			self.incoming.push_back((release, ReceivedFrame::new(data, now, source)));
+		}
+
+		if self.incoming.front().is_some_and(|(release, _)| *release <= now) {
+			return Ok(self.incoming.pop_front().map(|(_, frame)| frame));
+		}
+		return Ok(None);
+	}
+}
+
+impl<H: IOHandler> IOHandler for TimingLayer<H> {
+	fn enable(&mut self) -> Result<(), String> {
+		return self.inner.enable();
+	}
+
+	fn disable(&mut self) {
+		self.inner.disable();
+		self.outgoing.clear();
+		self.incoming.clear();
+		self.last_release = None;
+	}
+
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		return self.send_at(data, Instant::now());
+	}
+
+	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		return self.receive_at(Instant::now());
+	}
+
+	fn receive_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, String> {
+		return self.receive_into_at(buffer, Instant::now());
+	}
+
+	fn receive_from(&mut self) -> Result<Option<(PeerId, Vec<u8>)>, String> {
+		return Ok(self.receive_frame_at(Instant::now())?.map(|frame| (frame.source, frame.data)));
+	}
+
+	fn state(&self) -> ConnectionState {
+		return self.inner.state();
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::{TimingLayer, TimingOptions};
+use super::super::traits::IOHandler;
+use super::super::ConnectionState;
+
+// Records what is sent, and hands out what the test queued to be received
+#[derive(Default)]
+struct FakeHandler {
+	sent: Vec<Vec<u8>>,
+	to_receive: VecDeque<Vec<u8>>,
+}
+
+impl IOHandler for FakeHandler {
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		self.sent.push(data.to_vec());
+		return Ok(());
+	}
+
+	fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+		return Ok(self.to_receive.pop_front());
+	}
+
+	fn state(&self) -> ConnectionState {
+		return ConnectionState::Connected;
+	}
+}
+
+fn layer(options: TimingOptions) -> TimingLayer<FakeHandler> {
+	let mut layer = TimingLayer::new(FakeHandler::default());
+	layer.set_options(options);
+	return layer;
+}
+
+fn ms(milliseconds: u64) -> Duration {
+	return Duration::from_millis(milliseconds);
+}
+
+#[test]
+fn default_options_are_transparent() {
+	let mut layer = layer(TimingOptions::default());
+	let now = Instant::now();
+
+	layer.send_at(&[0x01], now).unwrap();
+	assert_eq!(layer.inner().sent, vec![vec![0x01]]);
+
+	layer.inner_mut().to_receive.push_back(vec![0x02]);
+	assert_eq!(layer.receive_at(now).unwrap(), Some(vec![0x02]));
+	assert_eq!(layer.state(), ConnectionState::Connected);
+}
+
+#[test]
+fn sent_frames_are_delayed() {
+	let mut layer = layer(TimingOptions { send_delay: ms(20), ..TimingOptions::default() });
+	let start = Instant::now();
+
+	layer.send_at(&[0x01], start).unwrap();
+	layer.send_at(&[0x02], start + ms(5)).unwrap();
+	assert!(layer.inner().sent.is_empty());
+
+	layer.receive_at(start + ms(19)).unwrap();
+	assert!(layer.inner().sent.is_empty());
+	layer.receive_at(start + ms(20)).unwrap();
+	assert_eq!(layer.inner().sent, vec![vec![0x01]]);
+	layer.receive_at(start + ms(25)).unwrap();
+	assert_eq!(layer.inner().sent, vec![vec![0x01], vec![0x02]]);
+}
+
+#[test]
+fn jitter_stays_within_bounds_and_keeps_order() {
+	let mut layer = layer(TimingOptions { send_delay: ms(10), send_jitter: ms(10), ..TimingOptions::default() });
+	let start = Instant::now();
+
+	for byte in 0..50u8 {
+		layer.send_at(&[byte], start).unwrap();
+	}
+	layer.receive_at(start + ms(9)).unwrap();
+	assert!(layer.inner().sent.is_empty());
+
+	layer.receive_at(start + ms(20)).unwrap();
+	assert_eq!(layer.inner().sent, (0..50u8).map(|byte| vec![byte]).collect::<Vec<Vec<u8>>>());
+}
+
+#[test]
+fn received_frames_are_released_with_ir_timing() {
+	let byte_time = Duration::from_micros(100);
+	let mut layer = layer(TimingOptions { receive_delay: ms(5), byte_time, turnaround: ms(1), ..TimingOptions::default() });
+	let start = Instant::now();
+
+	// Both arrive at once, e.g. after a network hiccup
+	layer.inner_mut().to_receive.extend([vec![0xAA; 10], vec![0xBB; 20]]);
+	assert_eq!(layer.receive_at(start).unwrap(), None);
+	assert_eq!(layer.receive_at(start).unwrap(), None);
+
+	// Held for receive_delay, then transferred at byte_time per byte
+	let first_release = start + ms(5) + byte_time * 10;
+	assert_eq!(layer.receive_at(first_release - Duration::from_micros(1)).unwrap(), None);
+	assert_eq!(layer.receive_at(first_release).unwrap(), Some(vec![0xAA; 10]));
+
+	// Then turnaround before the next one
+	let second_release = first_release + ms(1) + byte_time * 20;
+	assert_eq!(layer.receive_at(second_release - Duration::from_micros(1)).unwrap(), None);
+	assert_eq!(layer.receive_at(second_release).unwrap(), Some(vec![0xBB; 20]));
+}
+
+#[test]
+fn delayed_frames_can_be_taken_back() {
+	let mut layer = layer(TimingOptions { send_delay: ms(100), ..TimingOptions::default() });
+	let start = Instant::now();
+
+	layer.send_at(&[0x01], start).unwrap();
+	layer.send_at(&[0x02], start).unwrap();
+	assert_eq!(layer.take_delayed_frames(), vec![vec![0x01], vec![0x02]]);
+
+	layer.receive_at(start + ms(200)).unwrap();
+	assert!(layer.inner().sent.is_empty());
+}
+
+#[test]
+fn frames_are_received_into_buffers_through_the_layer() {
+	let mut layer = layer(TimingOptions::default());
+	let start = Instant::now();
+	let mut buffer = [0u8; 4];
+
+	layer.inner_mut().to_receive.push_back(vec![0x01, 0x02]);
+	assert_eq!(layer.receive_into_at(&mut buffer, start).unwrap(), Some(2));
+	assert_eq!(buffer[..2], [0x01, 0x02]);
+
+	// Held frames are copied out once released
+	layer.set_options(TimingOptions { receive_delay: ms(10), ..TimingOptions::default() });
+	layer.inner_mut().to_receive.push_back(vec![0x03]);
+	assert_eq!(layer.receive_into_at(&mut buffer, start).unwrap(), None);
+	assert_eq!(layer.receive_into_at(&mut buffer, start + ms(10)).unwrap(), Some(1));
+	assert_eq!(buffer[0], 0x03);
+
+	layer.set_options(TimingOptions::default());
+	layer.inner_mut().to_receive.push_back(vec![0x04; 5]);
+	assert!(layer.receive_into_at(&mut buffer, start).is_err());
+}
@@ -1,12 +1,18 @@
 use std::ffi::{CStr, CString};
+use std::ptr;
 use std::slice;
+use std::time::{Duration, Instant};
 
-use libc::{c_char, size_t};
+use libc::{c_char, c_void, size_t};
 
 mod core;
 use crate::core::IRCommunicationHandler;
-use crate::core::traits::IOHandler;
-use crate::core::MAXIMUM_DATA_LEN;
+use crate::core::{ConnectionState, StateCallback};
+use crate::core::{Config, NetworkOptions, NetworkRole, NetworkTransport, SerialOptions, SerialPortInfo, SerialPortKind, SerialPortList, TimingOptions};
+use crate::core::bridge::{Bridge, DirectionStatistics};
+use crate::core::traits::{copy_frame, IOHandler};
+use crate::core::{PeerId, MAXIMUM_DATA_LEN};
+pub use crate::core::IR_BYTE_TIME_NS;
 
 fn into_ptr<T>(instance: T) -> *mut T {
 	return Box::into_raw(Box::new(instance));
@@ -16,6 +22,13 @@ fn from_ptr<'a, T>(pointer: *mut T) -> Option<&'a mut T> {
 	return unsafe { pointer.as_mut() };
 }
 
+fn take_ptr<T>(pointer: *mut T) -> Option<T> {
+	if pointer.is_null() {
+		return None;
+	}
+	return Some(*unsafe { Box::from_raw(pointer) });
+}
+
 fn from_c_str<'a>(ptr: *const c_char) -> Option<&'a str> {
 	if ptr.is_null() {
 		return None;
@@ -36,6 +49,20 @@ fn into_c_str(string: &str) -> *mut c_char {
 	return c_str.into_raw();
 }
 
+fn age_ms(timestamp: Option<Instant>) -> u64 {
+	return match timestamp {
+		Some(timestamp) => { timestamp.elapsed().as_millis() as u64 },
+		None => { u64::MAX },
+	};
+}
+
+fn duration_us(duration: Option<Duration>) -> u64 {
+	return match duration {
+		Some(duration) => { duration.as_micros() as u64 },
+		None => { u64::MAX },
+	};
+}
+
 // === Public API ===
 
 // String FFI
@@ -75,7 +102,7 @@ pub extern "C" fn n3t1r_result_get_error_message(result_ptr: *mut Result<(), Str
 
 #[no_mangle]
 pub extern "C" fn n3t1r_result_free(result_ptr: *mut Result<(), String>) {
-	if let None = from_ptr(result_ptr) {
+	if from_ptr(result_ptr).is_none() {
 		panic!("n3t1r_result_free: null result_ptr");
 	}
 }
@@ -88,7 +115,7 @@ pub extern "C" fn n3t1r_vec_string_new() -> *mut Vec<String> {
 
 #[no_mangle]
 pub extern "C" fn n3t1r_vec_string_free(vector_ptr: *mut Vec<String>) {
-	if let None = from_ptr(vector_ptr) {
+	if from_ptr(vector_ptr).is_none() {
 		panic!("n3t1r_vec_string_free: null vector_ptr");
 	}
 }
@@ -118,32 +145,156 @@ pub extern "C" fn n3t1r_vec_string_get(vector_ptr: *mut Vec<String>, index: size
 	}
 }
 
+// SerialPortList FFI
+/// Strings are owned by the structure, release them with n3t1r_serial_port_info_free.
+/// Missing strings are empty, vid and pid are 0 for non-USB ports.
+#[repr(C)]
+pub struct IRSerialPortInfo {
+	pub name: *mut c_char,
+	pub description: *mut c_char,
+	pub manufacturer: *mut c_char,
+	pub product: *mut c_char,
+	pub serial_number: *mut c_char,
+	pub vid: u16,
+	pub pid: u16,
+	pub port_type: SerialPortKind,
+	pub is_compatible: bool,
+}
+
+fn get_serial_port<'a>(function_name: &str, list_ptr: *mut SerialPortList, index: size_t) -> &'a SerialPortInfo {
+	if let Some(list) = from_ptr(list_ptr) {
+		if index < list.ports.len() {
+			return &list.ports[index];
+		}
+		else {
+			panic!("{}: index out of range ({}), list length is {}", function_name, index, list.ports.len());
+		}
+	}
+	else {
+		panic!("{}: null list_ptr", function_name);
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_new() -> *mut SerialPortList {
+	return into_ptr(SerialPortList::default());
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_free(list_ptr: *mut SerialPortList) {
+	if take_ptr(list_ptr).is_none() {
+		panic!("n3t1r_serial_port_list_free: null list_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_len(list_ptr: *mut SerialPortList) -> size_t {
+	if let Some(list) = from_ptr(list_ptr) {
+		return list.ports.len() as size_t;
+	}
+	else {
+		panic!("n3t1r_serial_port_list_len: null list_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_generation(list_ptr: *mut SerialPortList) -> u64 {
+	if let Some(list) = from_ptr(list_ptr) {
+		return list.generation;
+	}
+	else {
+		panic!("n3t1r_serial_port_list_generation: null list_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_get_name(list_ptr: *mut SerialPortList, index: size_t) -> *mut c_char {
+	return into_c_str(&get_serial_port("n3t1r_serial_port_list_get_name", list_ptr, index).name);
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_get_description(list_ptr: *mut SerialPortList, index: size_t) -> *mut c_char {
+	return into_c_str(&get_serial_port("n3t1r_serial_port_list_get_description", list_ptr, index).description());
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_list_get_info(list_ptr: *mut SerialPortList, index: size_t, info_ptr: *mut IRSerialPortInfo) {
+	let info = get_serial_port("n3t1r_serial_port_list_get_info", list_ptr, index);
+
+	if let Some(info_target) = from_ptr(info_ptr) {
+		*info_target = IRSerialPortInfo {
+			name: into_c_str(&info.name),
+			description: into_c_str(&info.description()),
+			manufacturer: into_c_str(info.manufacturer.as_deref().unwrap_or("")),
+			product: into_c_str(info.product.as_deref().unwrap_or("")),
+			serial_number: into_c_str(info.serial_number.as_deref().unwrap_or("")),
+			vid: info.vid.unwrap_or(0),
+			pid: info.pid.unwrap_or(0),
+			port_type: info.port_type,
+			is_compatible: info.is_compatible,
+		};
+	}
+	else {
+		panic!("n3t1r_serial_port_list_get_info: null info_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_serial_port_info_free(info_ptr: *mut IRSerialPortInfo) {
+	if let Some(info) = from_ptr(info_ptr) {
+		for str_ptr in [&mut info.name, &mut info.description, &mut info.manufacturer, &mut info.product, &mut info.serial_number] {
+			if !str_ptr.is_null() {
+				n3t1r_str_free(*str_ptr);
+				*str_ptr = ptr::null_mut();
+			}
+		}
+	}
+	else {
+		panic!("n3t1r_serial_port_info_free: null info_ptr");
+	}
+}
+
 // IRCommunicationHandler static FFI
+/// Replaces the content of the list, ports are sorted by name.
 #[no_mangle]
-pub extern "C" fn n3t1r_get_available_serial_ports(names_vector_ptr: *mut Vec<String>, descriptions_vector_ptr: *mut Vec<String>) -> *mut Result<(), String> {
-	if let Some(names_vector) = from_ptr(names_vector_ptr) {
+pub extern "C" fn n3t1r_get_available_serial_ports(list_ptr: *mut SerialPortList) -> *mut Result<(), String> {
+	if let Some(list) = from_ptr(list_ptr) {
 		match IRCommunicationHandler::get_available_serial_ports() {
 			Err(error) => {
 				return into_ptr(Err(error));
 			},
 			Ok(serial_ports) => {
-				names_vector.extend(serial_ports.clone().into_keys());
-				
-				if let Some(descriptions_vector) = from_ptr(descriptions_vector_ptr) {
-					for name in names_vector {
-						descriptions_vector.push(serial_ports[name].clone());
-					}
-				}
+				*list = serial_ports;
+				return into_ptr(Ok(()));
+			},
+		}
+	}
+	else {
+		panic!("n3t1r_get_available_serial_ports: null list_ptr");
+	}
+}
 
+/// Changes only when the available ports differ from the previous enumeration,
+/// compare it with n3t1r_serial_port_list_generation to know whether a list is outdated.
+#[no_mangle]
+pub extern "C" fn n3t1r_get_serial_ports_generation(generation_ptr: *mut u64) -> *mut Result<(), String> {
+	if let Some(generation) = from_ptr(generation_ptr) {
+		match IRCommunicationHandler::get_serial_ports_generation() {
+			Err(error) => {
+				return into_ptr(Err(error));
+			},
+			Ok(current_generation) => {
+				*generation = current_generation;
 				return into_ptr(Ok(()));
 			},
 		}
 	}
 	else {
-		panic!("n3t1r_get_available_serial_ports: null names_vector_ptr");
+		panic!("n3t1r_get_serial_ports_generation: null generation_ptr");
 	}
 }
 
+/// Rooms of this machine, and rooms with a peer waiting in them on the local network (found by UDP broadcast, this takes about 200 ms)
 #[no_mangle]
 pub extern "C" fn n3t1r_get_available_rooms(vector_ptr: *mut Vec<String>) -> *mut Result<(), String> {
 	if let Some(vector) = from_ptr(vector_ptr) {
@@ -162,6 +313,54 @@ pub extern "C" fn n3t1r_get_available_rooms(vector_ptr: *mut Vec<String>) -> *mu
 	}
 }
 
+/// Same as n3t1r_get_available_rooms, with the rooms of this machine looked for in rendezvous_folder (the default one if null)
+#[no_mangle]
+pub extern "C" fn n3t1r_get_available_rooms_in(rendezvous_folder_ptr: *const c_char, vector_ptr: *mut Vec<String>) -> *mut Result<(), String> {
+	if let Some(vector) = from_ptr(vector_ptr) {
+		let rooms = match from_c_str(rendezvous_folder_ptr) {
+			Some(rendezvous_folder) => { IRCommunicationHandler::get_available_rooms_in(rendezvous_folder.as_ref()) },
+			None => { IRCommunicationHandler::get_available_rooms() },
+		};
+
+		match rooms {
+			Err(error) => {
+				return into_ptr(Err(error));
+			},
+			Ok(rooms) => {
+				vector.extend(rooms.iter().cloned());
+				return into_ptr(Ok(()));
+			},
+		}
+	}
+	else {
+		panic!("n3t1r_get_available_rooms_in: null vector_ptr");
+	}
+}
+
+/// Rooms of the relay server with a peer waiting in them, a server_port of 0 selects the default relay port
+#[no_mangle]
+pub extern "C" fn n3t1r_get_available_remote_rooms(server_host_ptr: *const c_char, server_port: u16, vector_ptr: *mut Vec<String>) -> *mut Result<(), String> {
+	if let Some(vector) = from_ptr(vector_ptr) {
+		if let Some(server_host) = from_c_str(server_host_ptr) {
+			match IRCommunicationHandler::get_available_remote_rooms(server_host, server_port) {
+				Err(error) => {
+					return into_ptr(Err(error));
+				},
+				Ok(rooms) => {
+					vector.extend(rooms.iter().cloned());
+					return into_ptr(Ok(()));
+				},
+			}
+		}
+		else {
+			panic!("n3t1r_get_available_remote_rooms: null server_host_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_get_available_remote_rooms: null vector_ptr");
+	}
+}
+
 // IRCommunicationHandler instance FFI
 #[no_mangle]
 pub extern "C" fn n3t1r_irch_new() -> *mut IRCommunicationHandler {
@@ -170,16 +369,66 @@ pub extern "C" fn n3t1r_irch_new() -> *mut IRCommunicationHandler {
 
 #[no_mangle]
 pub extern "C" fn n3t1r_irch_free(instance_ptr: *mut IRCommunicationHandler) {
-	if let None = from_ptr(instance_ptr) {
+	if let Some(mut communication_handler) = take_ptr(instance_ptr) {
+		// Reported to the state callback, then its sockets and advertisements go away with it
+		communication_handler.disable();
+	}
+	else {
 		panic!("n3t1r_irch_free: null instance_ptr");
 	}
 }
 
+fn new_from_config(config: Result<Config, String>, instance_ptr_ptr: *mut *mut IRCommunicationHandler) -> *mut Result<(), String> {
+	if let Some(instance_ptr) = from_ptr(instance_ptr_ptr) {
+		*instance_ptr = ptr::null_mut();
+
+		match config.and_then(|config| IRCommunicationHandler::from_config(&config)) {
+			Err(error) => {
+				return into_ptr(Err(error));
+			},
+			Ok(communication_handler) => {
+				*instance_ptr = into_ptr(communication_handler);
+				return into_ptr(Ok(()));
+			},
+		}
+	}
+	else {
+		panic!("n3t1r_irch_new_from_config: null instance_ptr_ptr");
+	}
+}
+
+/// Creates an instance from a TOML configuration file, whose settings the N3T1R_* environment variables override
+/// (e.g. N3T1R_SERIAL_PORT for port in the [serial] section). The configured backend is selected, but not enabled.
+/// *instance_ptr_ptr is set to the new instance, or to null on failure.
 #[no_mangle]
-pub extern "C" fn n3t1r_irch_select_serial_backend(instance_ptr: *mut IRCommunicationHandler, port_name_ptr: *const c_char) {
+pub extern "C" fn n3t1r_irch_new_from_config_file(path_ptr: *const c_char, instance_ptr_ptr: *mut *mut IRCommunicationHandler) -> *mut Result<(), String> {
+	if let Some(path) = from_c_str(path_ptr) {
+		return new_from_config(Config::load_file(path.as_ref()), instance_ptr_ptr);
+	}
+	else {
+		panic!("n3t1r_irch_new_from_config_file: null path_ptr");
+	}
+}
+
+/// Same as n3t1r_irch_new_from_config_file, with the TOML configuration itself. An empty string only takes the environment into account.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_new_from_config_string(config_ptr: *const c_char, instance_ptr_ptr: *mut *mut IRCommunicationHandler) -> *mut Result<(), String> {
+	if let Some(config) = from_c_str(config_ptr) {
+		return new_from_config(Config::load_str(config), instance_ptr_ptr);
+	}
+	else {
+		panic!("n3t1r_irch_new_from_config_string: null config_ptr");
+	}
+}
+
+/// Selecting a backend while enabled disables the current backend and enables the new one.
+/// On failure the previous backend is restored and the error is returned.
+/// The n3t1r_irch_select_*_backend functions returned nothing before 0.2.0, see CHANGELOG.md.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_select_serial_backend(instance_ptr: *mut IRCommunicationHandler, port_name_ptr: *const c_char) -> *mut Result<(), String> {
 	if let Some(communication_handler) = from_ptr(instance_ptr) {
 		if let Some(port_name) = from_c_str(port_name_ptr) {
-			communication_handler.select_serial_backend(port_name);
+			return into_ptr(communication_handler.select_serial_backend(port_name, &SerialOptions::default()));
 		}
 		else {
 			panic!("n3t1r_irch_select_serial_backend: null port_name_ptr");
@@ -190,11 +439,13 @@ pub extern "C" fn n3t1r_irch_select_serial_backend(instance_ptr: *mut IRCommunic
 	}
 }
 
+/// Peers of this machine are met through the default rendez-vous folder, peers of the local network through UDP broadcast.
+/// The default folder is $XDG_RUNTIME_DIR/ntir11-rdv, or a folder of the user in the temporary directory without XDG_RUNTIME_DIR.
 #[no_mangle]
-pub extern "C" fn n3t1r_irch_select_rendezvous_backend(instance_ptr: *mut IRCommunicationHandler, room_name_ptr: *const c_char) {
+pub extern "C" fn n3t1r_irch_select_rendezvous_backend(instance_ptr: *mut IRCommunicationHandler, room_name_ptr: *const c_char) -> *mut Result<(), String> {
 	if let Some(communication_handler) = from_ptr(instance_ptr) {
 		if let Some(room_name) = from_c_str(room_name_ptr) {
-			communication_handler.select_rendezvous_backend(room_name);
+			return into_ptr(communication_handler.select_rendezvous_backend(room_name));
 		}
 		else {
 			panic!("n3t1r_irch_select_rendezvous_backend: null room_name_ptr");
@@ -205,11 +456,109 @@ pub extern "C" fn n3t1r_irch_select_rendezvous_backend(instance_ptr: *mut IRComm
 	}
 }
 
+/// Same as n3t1r_irch_select_rendezvous_backend, meeting peers of this machine in rendezvous_folder (the default one if null),
+/// e.g. for sandboxed emulators that do not share the temporary directory. The folder is created accessible to the user only.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_select_rendezvous_backend_in(instance_ptr: *mut IRCommunicationHandler, rendezvous_folder_ptr: *const c_char, room_name_ptr: *const c_char) -> *mut Result<(), String> {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if let Some(room_name) = from_c_str(room_name_ptr) {
+			return into_ptr(match from_c_str(rendezvous_folder_ptr) {
+				Some(rendezvous_folder) => { communication_handler.select_rendezvous_backend_in(rendezvous_folder.as_ref(), room_name) },
+				None => { communication_handler.select_rendezvous_backend(room_name) },
+			});
+		}
+		else {
+			panic!("n3t1r_irch_select_rendezvous_backend_in: null room_name_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_irch_select_rendezvous_backend_in: null instance_ptr");
+	}
+}
+
+/// Joins a broadcast room of rendezvous_folder (the default one if null): any number of peers join it,
+/// and every frame is delivered to all the others. Members of this machine are met through the folder,
+/// members of the local network through UDP broadcast. Broadcast rooms do not meet point-to-point rooms of the same name.
 #[no_mangle]
-pub extern "C" fn n3t1r_irch_select_network_backend(instance_ptr: *mut IRCommunicationHandler, source_port: u16, destination_host_ptr: *const c_char, destination_port: u16) {
+pub extern "C" fn n3t1r_irch_select_broadcast_rendezvous_backend_in(instance_ptr: *mut IRCommunicationHandler, rendezvous_folder_ptr: *const c_char, room_name_ptr: *const c_char) -> *mut Result<(), String> {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if let Some(room_name) = from_c_str(room_name_ptr) {
+			return into_ptr(match from_c_str(rendezvous_folder_ptr) {
+				Some(rendezvous_folder) => { communication_handler.select_broadcast_rendezvous_backend_in(rendezvous_folder.as_ref(), room_name) },
+				None => { communication_handler.select_broadcast_rendezvous_backend(room_name) },
+			});
+		}
+		else {
+			panic!("n3t1r_irch_select_broadcast_rendezvous_backend_in: null room_name_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_irch_select_broadcast_rendezvous_backend_in: null instance_ptr");
+	}
+}
+
+/// Meets the peer through the relay server at server_host:server_port (n3t1r-relay), instead of the local temporary directory.
+/// A server_port of 0 selects the default relay port.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_select_remote_rendezvous_backend(instance_ptr: *mut IRCommunicationHandler, server_host_ptr: *const c_char, server_port: u16, room_name_ptr: *const c_char) -> *mut Result<(), String> {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if let Some(server_host) = from_c_str(server_host_ptr) {
+			if let Some(room_name) = from_c_str(room_name_ptr) {
+				return into_ptr(communication_handler.select_remote_rendezvous_backend(server_host, server_port, room_name));
+			}
+			else {
+				panic!("n3t1r_irch_select_remote_rendezvous_backend: null room_name_ptr");
+			}
+		}
+		else {
+			panic!("n3t1r_irch_select_remote_rendezvous_backend: null server_host_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_irch_select_remote_rendezvous_backend: null instance_ptr");
+	}
+}
+
+/// Options of the network backend, a null pointer selects the defaults. Both peers must use the same options.
+/// A non-null passphrase enables the secure mode: frames are encrypted and authenticated,
+/// and datagrams that fail authentication are dropped and counted in authentication_failures.
+/// Secure peers first start a session with a handshake: the state is WaitingForPeer until then, and frames sent meanwhile wait for it.
+/// The reliable mode retransmits lost frames for up to one second, and delivers frames once and in order.
+/// With TCP and WebSocket, clients connect to destination_host:destination_port and reconnect when the connection is lost,
+/// servers listen on source_port and wait for a client; frames can only be sent once connected.
+/// The broadcast mode joins a broadcast room through destination_host, typically a broadcast or multicast address
+/// that every member listens on: frames are delivered to all the other members. It only works over UDP,
+/// without the secure and reliable modes.
+#[repr(C)]
+pub struct IRNetworkOptions {
+	pub passphrase: *const c_char,
+	pub reliable: bool,
+	pub transport: NetworkTransport,
+	pub role: NetworkRole,
+	pub broadcast: bool,
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_select_network_backend(instance_ptr: *mut IRCommunicationHandler, source_port: u16, destination_host_ptr: *const c_char, destination_port: u16, options_ptr: *const IRNetworkOptions) -> *mut Result<(), String> {
 	if let Some(communication_handler) = from_ptr(instance_ptr) {
 		if let Some(destination_host) = from_c_str(destination_host_ptr) {
-			communication_handler.select_network_backend(source_port, destination_host, destination_port);
+			let mut options = NetworkOptions::default();
+
+			if let Some(network_options) = unsafe { options_ptr.as_ref() } {
+				options.reliable = network_options.reliable;
+				options.transport = network_options.transport;
+				options.role = network_options.role;
+				options.broadcast = network_options.broadcast;
+				if !network_options.passphrase.is_null() {
+					if let Some(passphrase) = from_c_str(network_options.passphrase) {
+						options.passphrase = Some(passphrase.to_owned());
+					}
+					else {
+						return into_ptr(Err(String::from("Passphrase is not valid UTF-8")));
+					}
+				}
+			}
+			return into_ptr(communication_handler.select_network_backend(source_port, destination_host, destination_port, &options));
 		}
 		else {
 			panic!("n3t1r_irch_select_network_backend: null destination_host_ptr");
@@ -220,6 +569,73 @@ pub extern "C" fn n3t1r_irch_select_network_backend(instance_ptr: *mut IRCommuni
 	}
 }
 
+/// Whether frames still queued by the previous backend (e.g. a rendez-vous without peer yet) are sent through the new one, true by default.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_set_transfer_pending_frames(instance_ptr: *mut IRCommunicationHandler, transfer_pending_frames: bool) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		communication_handler.set_transfer_pending_frames(transfer_pending_frames);
+	}
+	else {
+		panic!("n3t1r_irch_set_transfer_pending_frames: null instance_ptr");
+	}
+}
+
+/// Timing emulation applied to the frames of every backend, a null pointer restores the defaults (no emulation).
+/// Outgoing frames are held for send_delay_us plus a random part of send_jitter_us, without being reordered.
+/// Incoming frames are held for receive_delay_us, then released one after the other, taking byte_time_ns per byte
+/// (N3T1R_IR_BYTE_TIME_NS for the timing of the dongle), with at least turnaround_us between two frames.
+#[repr(C)]
+pub struct IRTimingOptions {
+	pub send_delay_us: u64,
+	pub send_jitter_us: u64,
+	pub receive_delay_us: u64,
+	pub byte_time_ns: u64,
+	pub turnaround_us: u64,
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_set_timing_options(instance_ptr: *mut IRCommunicationHandler, options_ptr: *const IRTimingOptions) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		let mut options = TimingOptions::default();
+
+		if let Some(timing_options) = unsafe { options_ptr.as_ref() } {
+			options.send_delay = Duration::from_micros(timing_options.send_delay_us);
+			options.send_jitter = Duration::from_micros(timing_options.send_jitter_us);
+			options.receive_delay = Duration::from_micros(timing_options.receive_delay_us);
+			options.byte_time = Duration::from_nanos(timing_options.byte_time_ns);
+			options.turnaround = Duration::from_micros(timing_options.turnaround_us);
+		}
+		communication_handler.set_timing_options(options);
+	}
+	else {
+		panic!("n3t1r_irch_set_timing_options: null instance_ptr");
+	}
+}
+
+/// Reports the time the emulator emulated since the previous call. The first call switches the handler to emulated time:
+/// timing emulation and receive timeouts then follow the emulated clock instead of the wall clock,
+/// whether the emulator runs fast-forwarded, paused or frame by frame.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_advance_clock(instance_ptr: *mut IRCommunicationHandler, elapsed_ns: u64) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		communication_handler.advance_clock(Duration::from_nanos(elapsed_ns));
+	}
+	else {
+		panic!("n3t1r_irch_advance_clock: null instance_ptr");
+	}
+}
+
+/// Switches the handler back to the wall clock, which it follows by default.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_use_wall_clock(instance_ptr: *mut IRCommunicationHandler) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		communication_handler.use_wall_clock();
+	}
+	else {
+		panic!("n3t1r_irch_use_wall_clock: null instance_ptr");
+	}
+}
+
 #[no_mangle]
 pub extern "C" fn n3t1r_irch_enable(instance_ptr: *mut IRCommunicationHandler) -> *mut Result<(), String> {
 	if let Some(communication_handler) = from_ptr(instance_ptr) {
@@ -247,7 +663,7 @@ pub extern "C" fn n3t1r_irch_send(instance_ptr: *mut IRCommunicationHandler, dat
 			panic!("n3t1r_irch_send: null data_ptr");
 		}
 
-		let data = unsafe { slice::from_raw_parts(data_ptr, data_len as usize) };		
+		let data = unsafe { slice::from_raw_parts(data_ptr, data_len) };		
 		return into_ptr(communication_handler.send(data));
 	}
 	else {
@@ -255,42 +671,390 @@ pub extern "C" fn n3t1r_irch_send(instance_ptr: *mut IRCommunicationHandler, dat
 	}
 }
 
-#[no_mangle]
-pub extern "C" fn n3t1r_irch_receive(instance_ptr: *mut IRCommunicationHandler, data_ptr: *mut u8, data_len_ptr: *mut size_t) -> *mut Result<(), String> {
-	if let Some(communication_handler) = from_ptr(instance_ptr) {
-		if data_ptr.is_null() {
-			panic!("n3t1r_irch_receive: null data_ptr");
-		}
-		if data_len_ptr.is_null() {
-			panic!("n3t1r_irch_receive: null data_len_ptr");
-		}
+/// Metadata of a received frame:
+/// - timestamp_us: when the handler got it from its backend, in microseconds since the handler was created, on the handler clock
+///   (the emulated one after n3t1r_irch_advance_clock), before the playout delay of the timing emulation
+/// - source: member of the broadcast room that sent it, N3T1R_UNKNOWN_PEER on point-to-point links
+/// - sequence: counts the frames received through the handler, from 0
+/// - flags: N3T1R_FRAME_FLAG_FRAMES_LOST when frames were lost on reception since the previous one,
+///   N3T1R_FRAME_FLAG_AFTER_RESYNC for the first frame since the peer loaded a savestate
+#[repr(C)]
+pub struct IRFrameMetadata {
+	pub timestamp_us: u64,
+	pub source: PeerId,
+	pub sequence: u64,
+	pub flags: u32,
+}
 
-		let data_len = unsafe { &mut *data_len_ptr };
-		let max_data_len = *data_len;
+// Copies the received frame to data_ptr, its sender to sender_ptr and its metadata to metadata_ptr when not null.
+// Never panics: a buffer too small for the next frame fails with the frame length in *data_len_ptr, the frame staying queued.
+fn receive_frame(function_name: &str, instance_ptr: *mut IRCommunicationHandler, data_ptr: *mut u8, data_len_ptr: *mut size_t, sender_ptr: *mut PeerId, metadata_ptr: *mut IRFrameMetadata) -> *mut Result<(), String> {
+	if let (Some(communication_handler), Some(data_len)) = (from_ptr(instance_ptr), from_ptr(data_len_ptr)) {
+		// A null buffer holds nothing
+		let max_data_len = if data_ptr.is_null() { 0 } else { *data_len };
+		*data_len = 0;
 
+		// Any frame fits in MAXIMUM_DATA_LEN bytes, smaller buffers are checked against the next frame first
 		if max_data_len < MAXIMUM_DATA_LEN {
-			panic!("n3t1r_irch_receive: max_data_len too low ({}), should be at least {}", max_data_len, MAXIMUM_DATA_LEN);
+			match communication_handler.peek_frame_len() {
+				Err(error) => {
+					return into_ptr(Err(error));
+				},
+				Ok(None) => {
+					return into_ptr(Ok(()));
+				},
+				Ok(Some(len)) => {
+					if len > max_data_len {
+						*data_len = len;
+						return into_ptr(Err(format!("{}: buffer too small ({} bytes) for the next frame ({} bytes)", function_name, max_data_len, len)));
+					}
+				},
+			}
+		}
+
+		let data_target = if data_ptr.is_null() { &mut [] } else { unsafe { slice::from_raw_parts_mut(data_ptr, max_data_len) } };
+
+		// Straight into the caller's buffer when only the data is asked for
+		if sender_ptr.is_null() && metadata_ptr.is_null() {
+			match communication_handler.receive_into(data_target) {
+				Err(error) => {
+					return into_ptr(Err(error));
+				},
+				Ok(result) => {
+					*data_len = result.unwrap_or(0) as size_t;
+					return into_ptr(Ok(()));
+				},
+			}
 		}
 
-		let data_target = unsafe { slice::from_raw_parts_mut(data_ptr, max_data_len as usize) };
-		match communication_handler.receive() {
+		match communication_handler.receive_frame() {
 			Err(error) => {
-				*data_len = 0;
 				return into_ptr(Err(error));
 			},
 			Ok(result) => {
-				if let Some(data) = result {
-					data_target[..data.len()].clone_from_slice(&data);
-					*data_len = data.len() as size_t;
-				}
-				else {
-					*data_len = 0;
+				if let Some(frame) = result {
+					if let Err(error) = copy_frame(&frame.data, data_target) {
+						return into_ptr(Err(error));
+					}
+					*data_len = frame.data.len() as size_t;
+					if let Some(sender) = from_ptr(sender_ptr) {
+						*sender = frame.source;
+					}
+					if let Some(metadata) = from_ptr(metadata_ptr) {
+						*metadata = IRFrameMetadata {
+							timestamp_us: communication_handler.time_since_creation(frame.timestamp).as_micros() as u64,
+							source: frame.source,
+							sequence: frame.sequence,
+							flags: frame.flags,
+						};
+					}
 				}
 				return into_ptr(Ok(()));
 			}
 		}
 	}
+	else if instance_ptr.is_null() {
+		return into_ptr(Err(format!("{}: null instance_ptr", function_name)));
+	}
+	else {
+		return into_ptr(Err(format!("{}: null data_len_ptr", function_name)));
+	}
+}
+
+/// *data_len_ptr is the size of the buffer at data_ptr, and receives the length of the frame, 0 when none was received.
+/// A buffer too small for the next frame is an error, *data_len_ptr then receiving the length of the frame, which stays queued:
+/// buffers of N3T1R_MAXIMUM_DATA_LEN bytes always fit, see also n3t1r_irch_peek_len.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_receive(instance_ptr: *mut IRCommunicationHandler, data_ptr: *mut u8, data_len_ptr: *mut size_t) -> *mut Result<(), String> {
+	return receive_frame("n3t1r_irch_receive", instance_ptr, data_ptr, data_len_ptr, ptr::null_mut(), ptr::null_mut());
+}
+
+/// Same as n3t1r_irch_receive, also setting *sender_ptr to the sender of the frame when one is received:
+/// a member of the broadcast room, or N3T1R_UNKNOWN_PEER on point-to-point links. sender_ptr may be null.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_receive_from(instance_ptr: *mut IRCommunicationHandler, data_ptr: *mut u8, data_len_ptr: *mut size_t, sender_ptr: *mut PeerId) -> *mut Result<(), String> {
+	return receive_frame("n3t1r_irch_receive_from", instance_ptr, data_ptr, data_len_ptr, sender_ptr, ptr::null_mut());
+}
+
+/// Sets *len_ptr to the length of the next frame, 0 when none was received. The frame stays queued for the next receive.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_peek_len(instance_ptr: *mut IRCommunicationHandler, len_ptr: *mut size_t) -> *mut Result<(), String> {
+	if let (Some(communication_handler), Some(len)) = (from_ptr(instance_ptr), from_ptr(len_ptr)) {
+		*len = 0;
+		match communication_handler.peek_frame_len() {
+			Err(error) => {
+				return into_ptr(Err(error));
+			},
+			Ok(result) => {
+				*len = result.unwrap_or(0) as size_t;
+				return into_ptr(Ok(()));
+			},
+		}
+	}
+	else if instance_ptr.is_null() {
+		return into_ptr(Err(String::from("n3t1r_irch_peek_len: null instance_ptr")));
+	}
+	else {
+		return into_ptr(Err(String::from("n3t1r_irch_peek_len: null len_ptr")));
+	}
+}
+
+/// Same as n3t1r_irch_receive, also filling *metadata_ptr when a frame is received (see IRFrameMetadata).
+/// metadata_ptr may be null.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_receive_ex(instance_ptr: *mut IRCommunicationHandler, data_ptr: *mut u8, data_len_ptr: *mut size_t, metadata_ptr: *mut IRFrameMetadata) -> *mut Result<(), String> {
+	return receive_frame("n3t1r_irch_receive_ex", instance_ptr, data_ptr, data_len_ptr, ptr::null_mut(), metadata_ptr);
+}
+
+// Savestate FFI
+/// State of the handler to store along with an emulator savestate: frames the game sent that the peer did not get yet,
+/// and frames received that the game did not get yet. To be freed with n3t1r_state_free.
+/// Partial frames (bytes of a frame still being read from the serial port or a stream) and transport sessions
+/// (reliable and secure modes, relay pairing) are not saved: loading a state drops a frame that was only partly received.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_save_state(instance_ptr: *mut IRCommunicationHandler, state_len_ptr: *mut size_t) -> *mut u8 {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if let Some(state_len) = from_ptr(state_len_ptr) {
+			let state = communication_handler.save_state().into_boxed_slice();
+			*state_len = state.len() as size_t;
+			return Box::into_raw(state) as *mut u8;
+		}
+		else {
+			panic!("n3t1r_irch_save_state: null state_len_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_irch_save_state: null instance_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_state_free(state_ptr: *mut u8, state_len: size_t) {
+	if state_ptr.is_null() {
+		panic!("n3t1r_state_free: null state_ptr");
+	}
+	else {
+		unsafe { drop(Box::from_raw(ptr::slice_from_raw_parts_mut(state_ptr, state_len))) };
+	}
+}
+
+/// Restores a state saved by n3t1r_irch_save_state, when the emulator loads the matching savestate.
+/// Frames queued since are discarded, and the peer is told to resync (see n3t1r_irch_take_resync_request).
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_load_state(instance_ptr: *mut IRCommunicationHandler, state_ptr: *const u8, state_len: size_t) -> *mut Result<(), String> {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if state_ptr.is_null() {
+			panic!("n3t1r_irch_load_state: null state_ptr");
+		}
+
+		let state = unsafe { slice::from_raw_parts(state_ptr, state_len) };
+		return into_ptr(communication_handler.load_state(state));
+	}
+	else {
+		panic!("n3t1r_irch_load_state: null instance_ptr");
+	}
+}
+
+/// True once after the peer loaded a savestate: the frames it had in flight were discarded,
+/// the IR session is likely to need starting over.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_take_resync_request(instance_ptr: *mut IRCommunicationHandler) -> bool {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		return communication_handler.take_resync_request();
+	}
 	else {
-		panic!("n3t1r_irch_receive: null instance_ptr");
+		panic!("n3t1r_irch_take_resync_request: null instance_ptr");
 	}
 }
+
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_get_state(instance_ptr: *mut IRCommunicationHandler) -> ConnectionState {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		return communication_handler.state();
+	}
+	else {
+		panic!("n3t1r_irch_get_state: null instance_ptr");
+	}
+}
+
+/// The callback is called on the thread calling into the handler, from within n3t1r_irch_* functions, on every state change.
+/// Passing a null callback removes the current one.
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_set_state_callback(instance_ptr: *mut IRCommunicationHandler, callback: Option<extern "C" fn(state: ConnectionState, user_data: *mut c_void)>, user_data: *mut c_void) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		communication_handler.set_state_callback(callback.map(|callback| -> StateCallback {
+			return Box::new(move |state| callback(state, user_data));
+		}));
+	}
+	else {
+		panic!("n3t1r_irch_set_state_callback: null instance_ptr");
+	}
+}
+
+// Statistics FFI
+/// Snapshot of the link statistics of an IRCommunicationHandler.
+/// Ages and round-trip times are UINT64_MAX until the corresponding event happened at least once.
+#[repr(C)]
+pub struct IRLinkStatistics {
+	pub frames_sent: u64,
+	pub bytes_sent: u64,
+	pub frames_received: u64,
+	pub bytes_received: u64,
+	pub frames_dropped: u64,
+	pub oversize_sends: u64,
+	pub framing_errors: u64,
+	pub last_send_age_ms: u64,
+	pub last_receive_age_ms: u64,
+	pub last_round_trip_us: u64,
+	pub smoothed_round_trip_us: u64,
+	// New fields go last, so that the offsets of the older ones do not change
+	pub authentication_failures: u64,
+	pub retransmissions: u64,
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_get_stats(instance_ptr: *mut IRCommunicationHandler, stats_ptr: *mut IRLinkStatistics) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		if let Some(stats_target) = from_ptr(stats_ptr) {
+			let stats = communication_handler.stats();
+
+			*stats_target = IRLinkStatistics {
+				frames_sent: stats.frames_sent,
+				bytes_sent: stats.bytes_sent,
+				frames_received: stats.frames_received,
+				bytes_received: stats.bytes_received,
+				frames_dropped: stats.frames_dropped,
+				oversize_sends: stats.oversize_sends,
+				framing_errors: stats.framing_errors,
+				last_send_age_ms: age_ms(stats.last_sent),
+				last_receive_age_ms: age_ms(stats.last_received),
+				last_round_trip_us: duration_us(stats.last_round_trip),
+				smoothed_round_trip_us: duration_us(stats.smoothed_round_trip),
+				authentication_failures: stats.authentication_failures,
+				retransmissions: stats.retransmissions,
+			};
+		}
+		else {
+			panic!("n3t1r_irch_get_stats: null stats_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_irch_get_stats: null instance_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_irch_reset_stats(instance_ptr: *mut IRCommunicationHandler) {
+	if let Some(communication_handler) = from_ptr(instance_ptr) {
+		communication_handler.reset_stats();
+	}
+	else {
+		panic!("n3t1r_irch_reset_stats: null instance_ptr");
+	}
+}
+
+// Bridge FFI
+#[repr(C)]
+pub struct IRBridgeStatistics {
+	pub frames: u64,
+	pub bytes: u64,
+	pub send_errors: u64,
+}
+
+impl From<&DirectionStatistics> for IRBridgeStatistics {
+	fn from(statistics: &DirectionStatistics) -> Self {
+		return Self { frames: statistics.frames, bytes: statistics.bytes, send_errors: statistics.send_errors };
+	}
+}
+
+/// Takes ownership of both handlers, they must not be used nor freed afterwards.
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_new(left_instance_ptr: *mut IRCommunicationHandler, right_instance_ptr: *mut IRCommunicationHandler) -> *mut Bridge {
+	if left_instance_ptr == right_instance_ptr {
+		panic!("n3t1r_bridge_new: left_instance_ptr and right_instance_ptr are the same handler");
+	}
+
+	if let Some(left) = take_ptr(left_instance_ptr) {
+		if let Some(right) = take_ptr(right_instance_ptr) {
+			return into_ptr(Bridge::new(left, right));
+		}
+		else {
+			panic!("n3t1r_bridge_new: null right_instance_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_bridge_new: null left_instance_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_free(bridge_ptr: *mut Bridge) {
+	if take_ptr(bridge_ptr).is_none() {
+		panic!("n3t1r_bridge_free: null bridge_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_enable(bridge_ptr: *mut Bridge) -> *mut Result<(), String> {
+	if let Some(bridge) = from_ptr(bridge_ptr) {
+		return into_ptr(bridge.enable());
+	}
+	else {
+		panic!("n3t1r_bridge_enable: null bridge_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_disable(bridge_ptr: *mut Bridge) {
+	if let Some(bridge) = from_ptr(bridge_ptr) {
+		bridge.disable();
+	}
+	else {
+		panic!("n3t1r_bridge_disable: null bridge_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_step(bridge_ptr: *mut Bridge, forwarded_ptr: *mut bool) -> *mut Result<(), String> {
+	if let Some(bridge) = from_ptr(bridge_ptr) {
+		if let Some(forwarded) = from_ptr(forwarded_ptr) {
+			let result = bridge.step();
+			*forwarded = *result.as_ref().unwrap_or(&false);
+			return into_ptr(result.map(|_| ()));
+		}
+		else {
+			panic!("n3t1r_bridge_step: null forwarded_ptr");
+		}
+	}
+	else {
+		panic!("n3t1r_bridge_step: null bridge_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_get_stats(bridge_ptr: *mut Bridge, left_to_right_ptr: *mut IRBridgeStatistics, right_to_left_ptr: *mut IRBridgeStatistics) {
+	if let Some(bridge) = from_ptr(bridge_ptr) {
+		if let Some(left_to_right) = from_ptr(left_to_right_ptr) {
+			*left_to_right = bridge.left_to_right().into();
+		}
+		if let Some(right_to_left) = from_ptr(right_to_left_ptr) {
+			*right_to_left = bridge.right_to_left().into();
+		}
+	}
+	else {
+		panic!("n3t1r_bridge_get_stats: null bridge_ptr");
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn n3t1r_bridge_reset_stats(bridge_ptr: *mut Bridge) {
+	if let Some(bridge) = from_ptr(bridge_ptr) {
+		bridge.reset_stats();
+	}
+	else {
+		panic!("n3t1r_bridge_reset_stats: null bridge_ptr");
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -1,27 +1,602 @@
 #![allow(dead_code)]
 
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::UdpSocket;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use n3t1r_relay::DEFAULT_RELAY_PORT;
+
 mod core;
 use crate::core::IRCommunicationHandler;
+use crate::core::{NetworkOptions, NetworkRole, NetworkTransport, SerialOptions, SerialPortInfo, SerialPortKind, TimingOptions, IR_BYTE_TIME_NS, MAXIMUM_DATA_LEN, UNKNOWN_PEER};
+use crate::core::bridge::{Bridge, DirectionStatistics};
+use crate::core::log::{set_log_level, LogLevel};
 use crate::core::traits::IOHandler;
 
-fn main() {
-	println!("=== NTiR-11 § Test App ===");
+const IDLE_POLL_DELAY: Duration = Duration::from_millis(1);
+// Frames sent before receiving them, few enough for the socket buffers to hold them
+const BENCHMARK_BATCH: u64 = 64;
+// Frames of a batch not received by then are counted as lost
+const BENCHMARK_BATCH_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+	Hex,
+	Ascii,
+	Decoded,
+}
+
+impl OutputFormat {
+	fn from_matches(matches: &ArgMatches) -> Self {
+		return match matches.get_one::<String>("format").map(|format| format.as_str()) {
+			Some("ascii") => { Self::Ascii },
+			Some("decoded") => { Self::Decoded },
+			_ => { Self::Hex },
+		};
+	}
+
+	fn format(&self, data: &[u8]) -> String {
+		return match self {
+			Self::Hex => { to_hex(data) },
+			Self::Ascii => { to_ascii(data) },
+			Self::Decoded => { decode(data) },
+		};
+	}
+}
+
+fn to_hex(data: &[u8]) -> String {
+	return data.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(" ");
+}
+
+fn to_ascii(data: &[u8]) -> String {
+	return data.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+}
+
+// Pokéwalker-style frames: every byte is XORed with 0xAA, and an 8 bytes header
+// (command, extra, checksum, session id) precedes the payload
+fn decode(data: &[u8]) -> String {
+	const XOR_KEY: u8 = 0xAA;
+	const HEADER_LEN: usize = 8;
+
+	let plain: Vec<u8> = data.iter().map(|byte| byte ^ XOR_KEY).collect();
+	if plain.len() < HEADER_LEN {
+		return format!("[{} bytes, too short for a header] {}", plain.len(), to_hex(&plain));
+	}
+
+	return format!(
+		"[{} bytes] cmd={:02x} extra={:02x} checksum={:04x} session={:08x} payload={}",
+		plain.len(),
+		plain[0],
+		plain[1],
+		u16::from_le_bytes([plain[2], plain[3]]),
+		u32::from_le_bytes([plain[4], plain[5], plain[6], plain[7]]),
+		to_hex(&plain[HEADER_LEN..]),
+	);
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+	let digits: String = text.chars().filter(|c| !c.is_whitespace() && *c != ':' && *c != ',').collect();
+	if !digits.len().is_multiple_of(2) {
+		return Err(format!("Odd number of hex digits in '{}'", text));
+	}
+
+	return (0..digits.len()).step_by(2).map(|i| {
+		return u8::from_str_radix(&digits[i..i + 2], 16).map_err(|e| format!("Invalid hex byte '{}': {}", &digits[i..i + 2], e));
+	}).collect();
+}
+
+// === Backend selection ===
+
+fn backend_args() -> Vec<Arg> {
+	return vec![
+		Arg::new("serial")
+			.long("serial")
+			.value_name("PORT")
+			.help("Use the serial backend on PORT"),
+		Arg::new("rendezvous")
+			.long("rendezvous")
+			.value_name("ROOM")
+			.help("Use the rendez-vous backend in ROOM"),
+		relay_arg().conflicts_with_all(["serial", "network"]),
+		Arg::new("network")
+			.long("network")
+			.num_args(3)
+			.value_names(["SOURCE_PORT", "DESTINATION_HOST", "DESTINATION_PORT"])
+			.help("Use the network backend"),
+		passphrase_arg().conflicts_with_all(["serial", "rendezvous"]),
+		reliable_arg().conflicts_with_all(["serial", "rendezvous"]),
+		transport_arg().conflicts_with_all(["serial", "rendezvous"]),
+		listen_arg().conflicts_with_all(["serial", "rendezvous"]),
+		Arg::new("broadcast")
+			.long("broadcast")
+			.action(ArgAction::SetTrue)
+			.conflicts_with_all(["serial", "relay", "passphrase", "reliable", "listen"])
+			.help("Join a broadcast room, where frames are delivered to every other member: with --network, DESTINATION_HOST is a broadcast or multicast address"),
+		Arg::new("delay")
+			.long("delay")
+			.value_name("MS")
+			.value_parser(clap::value_parser!(u64))
+			.help("Hold sent frames for MS milliseconds"),
+		Arg::new("jitter")
+			.long("jitter")
+			.value_name("MS")
+			.value_parser(clap::value_parser!(u64))
+			.help("Hold sent frames for up to MS more milliseconds, at random"),
+		Arg::new("playout")
+			.long("playout")
+			.value_name("MS")
+			.value_parser(clap::value_parser!(u64))
+			.help("Hold received frames for MS milliseconds, absorbing the jitter of the link"),
+		Arg::new("ir-timing")
+			.long("ir-timing")
+			.action(ArgAction::SetTrue)
+			.help("Release received frames at the pace of the 115200 baud IR dongle"),
+	];
+}
+
+fn timing_options(matches: &ArgMatches) -> TimingOptions {
+	let milliseconds = |id: &str| Duration::from_millis(matches.get_one::<u64>(id).copied().unwrap_or(0));
+
+	return TimingOptions {
+		send_delay: milliseconds("delay"),
+		send_jitter: milliseconds("jitter"),
+		receive_delay: milliseconds("playout"),
+		byte_time: if matches.get_flag("ir-timing") { Duration::from_nanos(IR_BYTE_TIME_NS) } else { Duration::ZERO },
+		turnaround: Duration::ZERO,
+	};
+}
+
+fn relay_arg() -> Arg {
+	return Arg::new("relay")
+		.long("relay")
+		.value_name("HOST[:PORT]")
+		.help(format!("Meet through the n3t1r-relay server at HOST instead of on this machine, on port {} by default", DEFAULT_RELAY_PORT));
+}
+
+fn parse_relay_server(value: &str) -> Result<(String, u16), String> {
+	return match value.rsplit_once(':') {
+		Some((host, port)) if !host.contains(':') || host.ends_with(']') => { Ok((host.trim_matches(['[', ']']).to_owned(), parse_port(port)?)) },
+		_ => { Ok((value.trim_matches(['[', ']']).to_owned(), DEFAULT_RELAY_PORT)) },
+	};
+}
+
+fn passphrase_arg() -> Arg {
+	return Arg::new("passphrase")
+		.long("passphrase")
+		.value_name("PASSPHRASE")
+		.help("Encrypt and authenticate network frames with a passphrase shared with the peer");
+}
+
+fn reliable_arg() -> Arg {
+	return Arg::new("reliable")
+		.long("reliable")
+		.action(ArgAction::SetTrue)
+		.help("Retransmit lost network frames and deliver them in order, the peer must use it too");
+}
+
+fn transport_arg() -> Arg {
+	return Arg::new("transport")
+		.long("transport")
+		.value_parser(["udp", "tcp", "websocket"])
+		.default_value("udp")
+		.help("Network transport, TCP frames are length-prefixed and WebSocket ones are binary messages");
+}
+
+fn listen_arg() -> Arg {
+	return Arg::new("listen")
+		.long("listen")
+		.action(ArgAction::SetTrue)
+		.help("With TCP or WebSocket, wait for the peer to connect on SOURCE_PORT instead of connecting to it");
+}
+
+fn network_options(matches: &ArgMatches) -> NetworkOptions {
+	let transport = match matches.get_one::<String>("transport").map(|transport| transport.as_str()) {
+		Some("tcp") => { NetworkTransport::Tcp },
+		Some("websocket") => { NetworkTransport::WebSocket },
+		_ => { NetworkTransport::Udp },
+	};
+	let role = if matches.get_flag("listen") { NetworkRole::Server } else { NetworkRole::Client };
+
+	return NetworkOptions { passphrase: matches.get_one::<String>("passphrase").cloned(), reliable: matches.get_flag("reliable"), transport, role, broadcast: false };
+}
+
+fn backend_group() -> ArgGroup {
+	return ArgGroup::new("backend").args(["serial", "rendezvous", "network"]).required(true);
+}
+
+fn parse_port(value: &str) -> Result<u16, String> {
+	return value.parse().map_err(|e| format!("Invalid port '{}': {}", value, e));
+}
+
+fn open_handler(matches: &ArgMatches) -> Result<IRCommunicationHandler, String> {
+	let mut handler = IRCommunicationHandler::new();
+
+	if let Some(port_name) = matches.get_one::<String>("serial") {
+		handler.select_serial_backend(port_name, &SerialOptions::default())?;
+	}
+	else if let Some(room_name) = matches.get_one::<String>("rendezvous") {
+		if let Some(relay) = matches.get_one::<String>("relay") {
+			let (server_host, server_port) = parse_relay_server(relay)?;
+			handler.select_remote_rendezvous_backend(&server_host, server_port, room_name)?;
+		}
+		else if matches.get_flag("broadcast") {
+			handler.select_broadcast_rendezvous_backend(room_name)?;
+		}
+		else {
+			handler.select_rendezvous_backend(room_name)?;
+		}
+	}
+	else if let Some(values) = matches.get_many::<String>("network") {
+		let values: Vec<&String> = values.collect();
+		let options = NetworkOptions { broadcast: matches.get_flag("broadcast"), ..network_options(matches) };
+		handler.select_network_backend(parse_port(values[0])?, values[1], parse_port(values[2])?, &options)?;
+	}
 
-	println!("{:?}\n", IRCommunicationHandler::get_available_serial_ports().unwrap());
-	
-	println!("{:?}\n", IRCommunicationHandler::get_available_serial_ports().unwrap()["COM7"].as_bytes());
+	handler.set_timing_options(timing_options(matches));
+	handler.enable()?;
+	return Ok(handler);
+}
 
+// Backend specifications: serial:PORT, rendezvous:ROOM, relay:ROOM@HOST[:PORT] or network:SOURCE_PORT:DESTINATION_HOST:DESTINATION_PORT
+fn handler_from_spec(spec: &str, options: &NetworkOptions) -> Result<IRCommunicationHandler, String> {
 	let mut handler = IRCommunicationHandler::new();
-	handler.select_serial_backend("COM7");
-	handler.enable().expect("Ooops");
 
+	match spec.split_once(':') {
+		Some(("serial", port_name)) => {
+			handler.select_serial_backend(port_name, &SerialOptions::default())?;
+		},
+		Some(("rendezvous", room_name)) => {
+			handler.select_rendezvous_backend(room_name)?;
+		},
+		Some(("relay", room)) => {
+			let (room_name, relay) = room.rsplit_once('@').ok_or(format!("Invalid relay backend '{}'", spec))?;
+			let (server_host, server_port) = parse_relay_server(relay)?;
+			handler.select_remote_rendezvous_backend(&server_host, server_port, room_name)?;
+		},
+		Some(("network", endpoints)) => {
+			let (source_port, destination) = endpoints.split_once(':').ok_or(format!("Invalid network backend '{}'", spec))?;
+			let (destination_host, destination_port) = destination.rsplit_once(':').ok_or(format!("Invalid network backend '{}'", spec))?;
+			handler.select_network_backend(parse_port(source_port)?, destination_host.trim_matches(['[', ']']), parse_port(destination_port)?, options)?;
+		},
+		_ => {
+			return Err(format!("Invalid backend '{}', expected serial:PORT, rendezvous:ROOM, relay:ROOM@HOST[:PORT] or network:SOURCE_PORT:HOST:DESTINATION_PORT", spec));
+		},
+	}
+
+	return Ok(handler);
+}
+
+// === Subcommands ===
+
+fn format_port(port: &SerialPortInfo) -> String {
+	let ids = match (port.vid, port.pid) {
+		(Some(vid), Some(pid)) => { format!("{:04x}:{:04x}", vid, pid) },
+		_ => { String::from("-") },
+	};
+	let port_type = match port.port_type {
+		SerialPortKind::Usb => { "usb" },
+		SerialPortKind::Pci => { "pci" },
+		SerialPortKind::Bluetooth => { "bluetooth" },
+		SerialPortKind::Unknown => { "unknown" },
+	};
+
+	return format!("{}\t{}\t{}\t{}\t{}{}", port.name, port_type, ids, port.serial_number.as_deref().unwrap_or("-"), port.description(), if port.is_compatible { "\t[compatible]" } else { "" });
+}
+
+fn list_ports(matches: &ArgMatches) -> Result<(), String> {
+	let compatible_only = matches.get_flag("compatible");
+
+	for port in IRCommunicationHandler::get_available_serial_ports()?.ports {
+		if !compatible_only || port.is_compatible {
+			println!("{}", format_port(&port));
+		}
+	}
+	return Ok(());
+}
+
+fn list_rooms(matches: &ArgMatches) -> Result<(), String> {
+	let rooms = match matches.get_one::<String>("relay") {
+		Some(relay) => {
+			let (server_host, server_port) = parse_relay_server(relay)?;
+			IRCommunicationHandler::get_available_remote_rooms(&server_host, server_port)?
+		},
+		None => { IRCommunicationHandler::get_available_rooms()? },
+	};
+
+	for room in rooms {
+		println!("{}", room);
+	}
+	return Ok(());
+}
+
+fn monitor(matches: &ArgMatches) -> Result<(), String> {
+	let output_format = OutputFormat::from_matches(matches);
+	let max_count = matches.get_one::<u64>("count").copied();
+	let mut handler = open_handler(matches)?;
+	let mut count: u64 = 0;
+
+	while max_count.is_none_or(|max_count| count < max_count) {
+		if let Some((sender, data)) = handler.receive_from()? {
+			// Members of broadcast rooms are told apart
+			if sender == UNKNOWN_PEER {
+				println!("{}", output_format.format(&data));
+			}
+			else {
+				println!("{:08x}: {}", sender, output_format.format(&data));
+			}
+			count += 1;
+		}
+		else {
+			thread::sleep(IDLE_POLL_DELAY);
+		}
+	}
+
+	handler.disable();
+	return Ok(());
+}
+
+fn send(matches: &ArgMatches) -> Result<(), String> {
+	let output_format = OutputFormat::from_matches(matches);
+	let text = matches.get_one::<String>("data").unwrap();
+	let data = match output_format {
+		OutputFormat::Ascii => { text.as_bytes().to_vec() },
+		_ => { parse_hex(text)? },
+	};
+	let wait = Duration::from_millis(*matches.get_one::<u64>("wait").unwrap());
+
+	let mut handler = open_handler(matches)?;
+	handler.send(&data)?;
+
+	let start = Instant::now();
+	while start.elapsed() < wait {
+		if let Some(reply) = handler.receive()? {
+			println!("{}", output_format.format(&reply));
+		}
+		else {
+			thread::sleep(IDLE_POLL_DELAY);
+		}
+	}
+
+	handler.disable();
+	return Ok(());
+}
+
+// Capture files hold one frame per line: milliseconds since the start of the capture, then the frame in hex
+fn capture(matches: &ArgMatches) -> Result<(), String> {
+	let path = matches.get_one::<String>("output").unwrap();
+	let max_count = matches.get_one::<u64>("count").copied();
+	let duration = matches.get_one::<u64>("duration").map(|seconds| Duration::from_secs(*seconds));
+
+	let file = fs::File::create(path).map_err(|e| format!("Cannot create '{}': {}", path, e))?;
+	let mut writer = BufWriter::new(file);
+	let mut handler = open_handler(matches)?;
+	let start = Instant::now();
+	let mut count: u64 = 0;
+
+	while max_count.is_none_or(|max_count| count < max_count) && duration.is_none_or(|duration| start.elapsed() < duration) {
+		if let Some(data) = handler.receive()? {
+			writeln!(writer, "{} {}", start.elapsed().as_millis(), to_hex(&data)).map_err(|e| format!("Cannot write capture: {}", e))?;
+			writer.flush().map_err(|e| format!("Cannot write capture: {}", e))?;
+			count += 1;
+		}
+		else {
+			thread::sleep(IDLE_POLL_DELAY);
+		}
+	}
+
+	handler.disable();
+	eprintln!("Captured {} frames", count);
+	return Ok(());
+}
+
+fn replay(matches: &ArgMatches) -> Result<(), String> {
+	let path = matches.get_one::<String>("input").unwrap();
+	let keep_timing = !matches.get_flag("no-timing");
+
+	let file = fs::File::open(path).map_err(|e| format!("Cannot open '{}': {}", path, e))?;
+	let mut frames: Vec<(Duration, Vec<u8>)> = Vec::new();
+
+	for (line_number, line) in BufReader::new(file).lines().enumerate() {
+		let line = line.map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let (timestamp, data) = line.split_once(' ').unwrap_or((line, ""));
+		let timestamp: u64 = timestamp.parse().map_err(|e| format!("{}:{}: invalid timestamp: {}", path, line_number + 1, e))?;
+		let data = parse_hex(data).map_err(|e| format!("{}:{}: {}", path, line_number + 1, e))?;
+		frames.push((Duration::from_millis(timestamp), data));
+	}
+
+	let mut handler = open_handler(matches)?;
+	let start = Instant::now();
+
+	for (timestamp, data) in &frames {
+		if keep_timing {
+			if let Some(delay) = timestamp.checked_sub(start.elapsed()) {
+				thread::sleep(delay);
+			}
+		}
+		handler.send(data)?;
+	}
+
+	handler.disable();
+	eprintln!("Replayed {} frames", frames.len());
+	return Ok(());
+}
+
+fn print_bridge_stats(left: &str, right: &str, left_to_right: &DirectionStatistics, right_to_left: &DirectionStatistics) {
+	eprintln!("{} -> {}: {} frames, {} bytes, {} send errors", left, right, left_to_right.frames, left_to_right.bytes, left_to_right.send_errors);
+	eprintln!("{} -> {}: {} frames, {} bytes, {} send errors", right, left, right_to_left.frames, right_to_left.bytes, right_to_left.send_errors);
+}
+
+fn bridge(matches: &ArgMatches) -> Result<(), String> {
+	let left = matches.get_one::<String>("left").unwrap();
+	let right = matches.get_one::<String>("right").unwrap();
+	let stats_interval = Duration::from_secs(*matches.get_one::<u64>("stats-interval").unwrap());
+
+	let options = network_options(matches);
+
+	let mut bridge = Bridge::new(handler_from_spec(left, &options)?, handler_from_spec(right, &options)?);
+	bridge.enable()?;
+
+	let mut last_stats_ts = Instant::now();
 	loop {
-		if let Some(data) = handler.receive().expect("Receive crashed") {
-			println!("Received: {:?}", data);
-			//handler.send("Bonjour, le, monde !".as_bytes()).expect("Send crashed");
+		if !bridge.step()? {
+			thread::sleep(IDLE_POLL_DELAY);
+		}
+
+		if !stats_interval.is_zero() && last_stats_ts.elapsed() >= stats_interval {
+			print_bridge_stats(left, right, bridge.left_to_right(), bridge.right_to_left());
+			last_stats_ts = Instant::now();
 		}
-		//std::thread::sleep(std::time::Duration::from_secs(1));
 	}
+}
+
+fn free_udp_port() -> Result<u16, String> {
+	let socket = UdpSocket::bind("127.0.0.1:0").map_err(|e| format!("Fail on bind: {}", e))?;
+	return socket.local_addr().map(|address| address.port()).map_err(|e| format!("Fail on local_addr: {}", e));
+}
+
+// Sends batches of frames between two handlers over the loopback interface, and receives them with receive() then receive_into()
+fn benchmark(matches: &ArgMatches) -> Result<(), String> {
+	let frame_count = *matches.get_one::<u64>("frames").unwrap();
+	let frame = vec![0xA5; *matches.get_one::<u64>("size").unwrap() as usize];
+	// Frames are not printed while measuring
+	set_log_level(LogLevel::Quiet);
+
+	let (sender_port, receiver_port) = (free_udp_port()?, free_udp_port()?);
+	let (mut sender, mut receiver) = (IRCommunicationHandler::new(), IRCommunicationHandler::new());
+	sender.select_network_backend(sender_port, "127.0.0.1", receiver_port, &NetworkOptions::default())?;
+	receiver.select_network_backend(receiver_port, "127.0.0.1", sender_port, &NetworkOptions::default())?;
+	sender.enable()?;
+	receiver.enable()?;
+
+	// With room for the byte detecting datagrams that are too long
+	let mut buffer = [0u8; MAXIMUM_DATA_LEN + 1];
+	for (name, into_buffer) in [("receive", false), ("receive_into", true)] {
+		let start = Instant::now();
+		let (mut sent, mut received) = (0, 0);
+
+		while sent < frame_count {
+			let batch = BENCHMARK_BATCH.min(frame_count - sent);
+			for _ in 0..batch {
+				sender.send(&frame)?;
+			}
+			sent += batch;
+
+			let (batch_start, mut batch_received) = (Instant::now(), 0);
+			while batch_received < batch && batch_start.elapsed() < BENCHMARK_BATCH_TIMEOUT {
+				let is_received = if into_buffer { receiver.receive_into(&mut buffer)?.is_some() } else { receiver.receive()?.is_some() };
+				if is_received {
+					batch_received += 1;
+				}
+			}
+			received += batch_received;
+		}
+
+		let elapsed = start.elapsed();
+		println!(
+			"{}: {} frames of {} bytes in {:.3}s, {:.0} frames/s, {:.2}us per frame, {} lost",
+			name, received, frame.len(), elapsed.as_secs_f64(), received as f64 / elapsed.as_secs_f64(), elapsed.as_secs_f64() * 1e6 / received.max(1) as f64, sent - received,
+		);
+	}
+
+	sender.disable();
+	receiver.disable();
+	return Ok(());
+}
+
+fn format_arg(input: bool) -> Arg {
+	let formats: &[&str] = if input { &["hex", "ascii"] } else { &["hex", "ascii", "decoded"] };
+	return Arg::new("format")
+		.long("format")
+		.short('f')
+		.value_parser(formats.to_vec())
+		.default_value("hex")
+		.help("Data representation");
+}
+
+fn command() -> Command {
+	return Command::new("n3t1r")
+		.about("NTiR-11 IR link debugging tool")
+		.subcommand_required(true)
+		.arg_required_else_help(true)
+		.arg(Arg::new("log-level").long("log-level").global(true).value_name("LEVEL").value_parser(["quiet", "info", "debug"]).default_value("info").help("Library messages printed to stderr: connection events with info, every frame with debug"))
+		.subcommand(Command::new("list-ports")
+			.about("List available serial ports: name, type, VID:PID, serial number and description")
+			.arg(Arg::new("compatible").long("compatible").action(ArgAction::SetTrue).help("Only list known-compatible IR dongles")))
+		.subcommand(Command::new("list-rooms")
+			.about("List available rendez-vous rooms, on this machine and on the local network")
+			.arg(relay_arg()))
+		.subcommand(Command::new("monitor")
+			.about("Print received frames")
+			.args(backend_args())
+			.group(backend_group())
+			.arg(format_arg(false))
+			.arg(Arg::new("count").long("count").short('n').value_parser(clap::value_parser!(u64)).help("Stop after COUNT frames")))
+		.subcommand(Command::new("send")
+			.about("Send a single frame, then print replies")
+			.args(backend_args())
+			.group(backend_group())
+			.arg(format_arg(true))
+			.arg(Arg::new("wait").long("wait").short('w').value_name("MS").value_parser(clap::value_parser!(u64)).default_value("0").help("Print replies received during MS milliseconds"))
+			.arg(Arg::new("data").required(true).help("Frame to send")))
+		.subcommand(Command::new("capture")
+			.about("Record received frames to a file")
+			.args(backend_args())
+			.group(backend_group())
+			.arg(Arg::new("output").long("output").short('o').required(true).value_name("FILE"))
+			.arg(Arg::new("count").long("count").short('n').value_parser(clap::value_parser!(u64)).help("Stop after COUNT frames"))
+			.arg(Arg::new("duration").long("duration").short('d').value_name("SECONDS").value_parser(clap::value_parser!(u64)).help("Stop after SECONDS seconds")))
+		.subcommand(Command::new("replay")
+			.about("Send frames recorded by capture")
+			.args(backend_args())
+			.group(backend_group())
+			.arg(Arg::new("input").long("input").short('i').required(true).value_name("FILE"))
+			.arg(Arg::new("no-timing").long("no-timing").action(ArgAction::SetTrue).help("Send frames back to back instead of with their recorded timing")))
+		.subcommand(Command::new("bridge")
+			.about("Forward frames both ways between two backends")
+			.after_help("Backends are serial:PORT, rendezvous:ROOM, relay:ROOM@HOST[:PORT] or network:SOURCE_PORT:DESTINATION_HOST:DESTINATION_PORT")
+			.arg(Arg::new("left").required(true).value_name("BACKEND"))
+			.arg(Arg::new("right").required(true).value_name("BACKEND"))
+			.arg(passphrase_arg())
+			.arg(reliable_arg())
+			.arg(transport_arg())
+			.arg(listen_arg())
+			.arg(Arg::new("stats-interval").long("stats-interval").value_name("SECONDS").value_parser(clap::value_parser!(u64)).default_value("10").help("Print forwarding statistics every SECONDS seconds, 0 to disable")))
+		.subcommand(Command::new("benchmark")
+			.about("Measure the receive throughput between two handlers over the loopback interface")
+			.arg(Arg::new("frames").long("frames").short('n').value_parser(clap::value_parser!(u64).range(1..)).default_value("100000").help("Frames received by each method"))
+			.arg(Arg::new("size").long("size").short('s').value_name("BYTES").value_parser(clap::value_parser!(u64).range(1..=MAXIMUM_DATA_LEN as u64)).default_value("255").help("Frame length")));
+}
+
+fn main() -> ExitCode {
+	let matches = command().get_matches();
+	set_log_level(LogLevel::parse(matches.get_one::<String>("log-level").unwrap()).unwrap());
+
+	let result = match matches.subcommand() {
+		Some(("list-ports", sub_matches)) => { list_ports(sub_matches) },
+		Some(("list-rooms", sub_matches)) => { list_rooms(sub_matches) },
+		Some(("monitor", sub_matches)) => { monitor(sub_matches) },
+		Some(("send", sub_matches)) => { send(sub_matches) },
+		Some(("capture", sub_matches)) => { capture(sub_matches) },
+		Some(("replay", sub_matches)) => { replay(sub_matches) },
+		Some(("bridge", sub_matches)) => { bridge(sub_matches) },
+		Some(("benchmark", sub_matches)) => { benchmark(sub_matches) },
+		_ => { unreachable!() },
+	};
 
-	//handler.disable();
+	return match result {
+		Ok(()) => { ExitCode::SUCCESS },
+		Err(error) => {
+			eprintln!("Error: {}", error);
+			ExitCode::FAILURE
+		},
+	};
 }
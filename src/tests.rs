@@ -0,0 +1,182 @@
+// The C API driven as a C caller would: random buffer sizes, null pointers and callbacks
+
+use std::ffi::c_void;
+use std::net::UdpSocket;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use super::{take_ptr, ConnectionState, IRCommunicationHandler, IRFrameMetadata, MAXIMUM_DATA_LEN};
+use super::{n3t1r_irch_disable, n3t1r_irch_enable, n3t1r_irch_free, n3t1r_irch_new, n3t1r_irch_select_network_backend, n3t1r_irch_send};
+use super::{n3t1r_irch_get_state, n3t1r_irch_set_state_callback};
+use super::{n3t1r_irch_peek_len, n3t1r_irch_receive, n3t1r_irch_receive_ex, n3t1r_irch_receive_from};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+// Bytes after the buffer that the receive calls must not touch
+const GUARD_LEN: usize = 16;
+const GUARD: u8 = 0xEE;
+
+// xorshift64, seeded so that failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+	fn below(&mut self, bound: usize) -> usize {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		return (self.0 % bound as u64) as usize;
+	}
+}
+
+fn take_result(result_ptr: *mut Result<(), String>) -> Result<(), String> {
+	return take_ptr(result_ptr).unwrap();
+}
+
+fn free_udp_port() -> u16 {
+	return UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+// Two handlers talking over the loopback interface
+fn handler_pair() -> (*mut IRCommunicationHandler, *mut IRCommunicationHandler) {
+	let (left_port, right_port) = (free_udp_port(), free_udp_port());
+	let (left, right) = (n3t1r_irch_new(), n3t1r_irch_new());
+
+	take_result(n3t1r_irch_select_network_backend(left, left_port, c"127.0.0.1".as_ptr(), right_port, ptr::null())).unwrap();
+	take_result(n3t1r_irch_select_network_backend(right, right_port, c"127.0.0.1".as_ptr(), left_port, ptr::null())).unwrap();
+	take_result(n3t1r_irch_enable(left)).unwrap();
+	take_result(n3t1r_irch_enable(right)).unwrap();
+	return (left, right);
+}
+
+fn frame(index: usize, len: usize) -> Vec<u8> {
+	return (0..len).map(|i| (index + i) as u8).collect();
+}
+
+#[test]
+fn random_buffer_sizes_never_lose_nor_overrun() {
+	let mut rng = Rng(0x9E3779B97F4A7C15);
+	let (left, right) = handler_pair();
+
+	let frames: Vec<Vec<u8>> = (0..200).map(|index| frame(index, 1 + rng.below(MAXIMUM_DATA_LEN))).collect();
+	for data in &frames {
+		take_result(n3t1r_irch_send(left, data.as_ptr(), data.len())).unwrap();
+	}
+
+	let mut received = 0;
+	let start = Instant::now();
+	while received < frames.len() && start.elapsed() < TEST_TIMEOUT {
+		let expected = &frames[received];
+
+		if rng.below(4) == 0 {
+			let mut len = usize::MAX;
+			take_result(n3t1r_irch_peek_len(right, &mut len)).unwrap();
+			assert!(len == 0 || len == expected.len());
+			continue;
+		}
+
+		let buffer_len = rng.below(MAXIMUM_DATA_LEN + 32);
+		let mut buffer = vec![GUARD; buffer_len + GUARD_LEN];
+		let data_ptr = if rng.below(8) == 0 { ptr::null_mut() } else { buffer.as_mut_ptr() };
+		let mut data_len = buffer_len;
+		let mut sender = 0;
+		let mut metadata = IRFrameMetadata { timestamp_us: 0, source: 0, sequence: 0, flags: 0 };
+
+		let result = match rng.below(3) {
+			0 => { take_result(n3t1r_irch_receive(right, data_ptr, &mut data_len)) },
+			1 => { take_result(n3t1r_irch_receive_from(right, data_ptr, &mut data_len, &mut sender)) },
+			_ => { take_result(n3t1r_irch_receive_ex(right, data_ptr, &mut data_len, &mut metadata)) },
+		};
+		assert!(buffer[buffer_len..].iter().all(|&byte| byte == GUARD), "overrun of a {} bytes buffer", buffer_len);
+
+		match result {
+			Ok(()) => {
+				if data_len > 0 {
+					assert_eq!(&buffer[..data_len], expected.as_slice());
+					received += 1;
+				}
+			},
+			// Reported with the length of the frame, which stays queued
+			Err(_) => {
+				assert_eq!(data_len, expected.len());
+				assert!(data_ptr.is_null() || data_len > buffer_len);
+			},
+		}
+	}
+	assert_eq!(received, frames.len());
+
+	n3t1r_irch_free(left);
+	n3t1r_irch_free(right);
+}
+
+#[test]
+fn null_pointers_are_errors() {
+	let (left, right) = handler_pair();
+	let mut buffer = [0u8; MAXIMUM_DATA_LEN];
+	let mut data_len = buffer.len();
+
+	assert!(take_result(n3t1r_irch_receive(ptr::null_mut(), buffer.as_mut_ptr(), &mut data_len)).is_err());
+	assert!(take_result(n3t1r_irch_receive_from(right, buffer.as_mut_ptr(), ptr::null_mut(), ptr::null_mut())).is_err());
+	assert!(take_result(n3t1r_irch_receive_ex(ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), ptr::null_mut())).is_err());
+	assert!(take_result(n3t1r_irch_peek_len(ptr::null_mut(), &mut data_len)).is_err());
+	assert!(take_result(n3t1r_irch_peek_len(right, ptr::null_mut())).is_err());
+
+	// Nothing received yet, whatever the buffer
+	assert_eq!(take_result(n3t1r_irch_receive(right, ptr::null_mut(), &mut data_len)), Ok(()));
+	assert_eq!(data_len, 0);
+
+	take_result(n3t1r_irch_send(left, [0x01, 0x02].as_ptr(), 2)).unwrap();
+	let start = Instant::now();
+	let mut len = 0;
+	while len == 0 && start.elapsed() < TEST_TIMEOUT {
+		take_result(n3t1r_irch_peek_len(right, &mut len)).unwrap();
+	}
+	assert_eq!(len, 2);
+
+	data_len = 1;
+	assert!(take_result(n3t1r_irch_receive(right, buffer.as_mut_ptr(), &mut data_len)).is_err());
+	assert_eq!(data_len, 2);
+	data_len = 2;
+	assert_eq!(take_result(n3t1r_irch_receive(right, buffer.as_mut_ptr(), &mut data_len)), Ok(()));
+	assert_eq!((data_len, &buffer[..2]), (2, [0x01, 0x02].as_slice()));
+
+	n3t1r_irch_free(left);
+	n3t1r_irch_free(right);
+}
+
+extern "C" fn push_state(state: ConnectionState, user_data: *mut c_void) {
+	let states = unsafe { &mut *(user_data as *mut Vec<ConnectionState>) };
+	states.push(state);
+}
+
+#[test]
+fn state_changes_are_reported_to_the_callback() {
+	let mut states: Vec<ConnectionState> = Vec::new();
+	let handler = n3t1r_irch_new();
+	n3t1r_irch_set_state_callback(handler, Some(push_state), &mut states as *mut Vec<ConnectionState> as *mut c_void);
+
+	take_result(n3t1r_irch_select_network_backend(handler, free_udp_port(), c"127.0.0.1".as_ptr(), free_udp_port(), ptr::null())).unwrap();
+	take_result(n3t1r_irch_enable(handler)).unwrap();
+	assert_eq!(n3t1r_irch_get_state(handler), ConnectionState::WaitingForPeer);
+	n3t1r_irch_disable(handler);
+	assert_eq!(n3t1r_irch_get_state(handler), ConnectionState::Disabled);
+
+	// Removed
+	n3t1r_irch_set_state_callback(handler, None, ptr::null_mut());
+	take_result(n3t1r_irch_enable(handler)).unwrap();
+	n3t1r_irch_free(handler);
+
+	assert_eq!(states, vec![ConnectionState::Connecting, ConnectionState::WaitingForPeer, ConnectionState::Disabled]);
+}
+
+#[test]
+fn freed_handlers_are_disabled_and_release_their_socket() {
+	let mut states: Vec<ConnectionState> = Vec::new();
+	let handler = n3t1r_irch_new();
+	let port = free_udp_port();
+	take_result(n3t1r_irch_select_network_backend(handler, port, c"127.0.0.1".as_ptr(), free_udp_port(), ptr::null())).unwrap();
+	take_result(n3t1r_irch_enable(handler)).unwrap();
+	n3t1r_irch_set_state_callback(handler, Some(push_state), &mut states as *mut Vec<ConnectionState> as *mut c_void);
+
+	n3t1r_irch_free(handler);
+	assert_eq!(states, vec![ConnectionState::Disabled]);
+	assert!(UdpSocket::bind(("0.0.0.0", port)).is_ok());
+}